@@ -0,0 +1,70 @@
+use crate::{ray::Ray, vec3::Vec3};
+
+/// Axis-aligned bounding box.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Builds a box around two points, padding any degenerate (flat) axis.
+    pub fn new(a: Vec3, b: Vec3) -> Aabb {
+        let delta = 1e-4;
+        let mut min = Vec3(a.0.min(b.0), a.1.min(b.1), a.2.min(b.2));
+        let mut max = Vec3(a.0.max(b.0), a.1.max(b.1), a.2.max(b.2));
+
+        if max.0 - min.0 < delta {
+            min.0 -= delta / 2.;
+            max.0 += delta / 2.;
+        }
+        if max.1 - min.1 < delta {
+            min.1 -= delta / 2.;
+            max.1 += delta / 2.;
+        }
+        if max.2 - min.2 < delta {
+            min.2 -= delta / 2.;
+            max.2 += delta / 2.;
+        }
+
+        Aabb { min, max }
+    }
+
+    pub fn surrounding(a: &Aabb, b: &Aabb) -> Aabb {
+        Aabb::new(
+            Vec3(
+                a.min.0.min(b.min.0),
+                a.min.1.min(b.min.1),
+                a.min.2.min(b.min.2),
+            ),
+            Vec3(
+                a.max.0.max(b.max.0),
+                a.max.1.max(b.max.1),
+                a.max.2.max(b.max.2),
+            ),
+        )
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.interval(ray, t_min, t_max).is_some()
+    }
+
+    /// Parameter range `(entry, exit)` of the ray inside the box, clamped to `[t_min, t_max]`.
+    pub fn interval(&self, ray: &Ray, mut t_min: f64, mut t_max: f64) -> Option<(f64, f64)> {
+        for axis in 0..3 {
+            let inv_d = 1. / ray.dir[axis];
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inv_d;
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inv_d;
+            if inv_d < 0. {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            t_min = t0.max(t_min);
+            t_max = t1.min(t_max);
+            if t_max <= t_min {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+}
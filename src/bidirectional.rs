@@ -0,0 +1,714 @@
+//! Bidirectional path tracing.
+//!
+//! Every sample traces a path from the camera and one from a light, then connects
+//! each vertex of one with each vertex of the other. A path of a given length can be
+//! built in as many ways as it has vertices, from all camera vertices to all light
+//! vertices, and multiple importance sampling weights each way by how likely it is to
+//! find the path compared to the others. Light hitting the camera directly from a
+//! light subpath lands in an arbitrary pixel and is splatted to the film.
+//!
+//! Vertex densities are per unit area, `pdf_fwd` in the direction the subpath was
+//! traced and `pdf_rev` in the opposite direction, as if it had been traced by the
+//! other side. Mirrors and glass scatter into single directions and cannot be
+//! connected to, their densities are left at zero.
+//!
+//! Paths with a single light vertex take a light sampled from the camera vertex as in
+//! light sampling, rather than the start of the light subpath. This also connects to
+//! the punctual lights, which the camera paths never hit and far away lights never
+//! start a light subpath from.
+
+use crate::{
+    camera::Camera,
+    hittable::Hit,
+    integrator::{fog_collision, survives_roulette, trace, visibility, Integrator},
+    materials::{color_emitted, is_specular, scatter, scattering, Material},
+    medium::Interior,
+    ray::Ray,
+    render::{Scene, Settings},
+    spectrum::to_rgb,
+    stats,
+    vec3::{dot, unit_vector, Vec3},
+};
+
+pub struct BidirectionalPathTracer;
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexKind {
+    Camera,
+    Light,
+    Surface,
+    Medium,
+}
+
+#[derive(Clone, Copy)]
+struct Vertex {
+    kind: VertexKind,
+    /// Point, normal and material. Lights have a `Light` material and the normal on the
+    /// emitting side, the camera has its view direction as normal.
+    rec: Hit,
+    /// Ray that reached the vertex along its subpath.
+    ray: Ray,
+    /// Light carried along the subpath up to the vertex over its density, without the
+    /// emitted radiance at light vertices.
+    beta: Vec3,
+    pdf_fwd: f64,
+    pdf_rev: f64,
+    delta: bool,
+    /// Light vertex of a punctual light, which camera paths cannot reach.
+    punctual: bool,
+}
+
+impl Vertex {
+    fn new(kind: VertexKind, rec: Hit, ray: Ray, beta: Vec3, pdf_fwd: f64) -> Vertex {
+        Vertex {
+            kind,
+            rec,
+            ray,
+            beta,
+            pdf_fwd,
+            pdf_rev: 0.,
+            delta: is_specular(rec.material),
+            punctual: false,
+        }
+    }
+
+    fn point(&self) -> Vec3 {
+        self.rec.point
+    }
+}
+
+/// The image plane the camera samples, slightly larger than the viewport since the
+/// last row and column of pixels start at its edge.
+struct Film<'a> {
+    camera: &'a Camera,
+    width: usize,
+    height: usize,
+    area: f64,
+}
+
+impl Film<'_> {
+    fn new<'a>(camera: &'a Camera, settings: &Settings) -> Film<'a> {
+        let (width, height) = (settings.width, settings.height);
+        Film {
+            camera,
+            width,
+            height,
+            area: camera.viewport_area() * (width * height) as f64
+                / ((width - 1) * (height - 1)) as f64,
+        }
+    }
+
+    /// Index of the pixel the point is seen in and the cosine between the direction
+    /// towards it and the view direction.
+    fn pixel(&self, point: &Vec3) -> Option<(usize, f64)> {
+        let (u, v) = self.camera.project(point)?;
+        let x = u * (self.width - 1) as f64;
+        let row = v * (self.height - 1) as f64;
+        if !(0. ..self.width as f64).contains(&x) || !(0. ..self.height as f64).contains(&row) {
+            return None;
+        }
+
+        let cosine = dot(
+            &unit_vector(&(*point - self.camera.origin())),
+            &self.camera.forward(),
+        );
+        let y = self.height - 1 - row as usize;
+        Some((y * self.width + x as usize, cosine))
+    }
+
+    /// Density per unit solid angle of camera rays towards the point.
+    fn pdf(&self, point: &Vec3) -> f64 {
+        match self.pixel(point) {
+            Some((_, cosine)) => 1. / (self.area * cosine * cosine * cosine),
+            None => 0.,
+        }
+    }
+}
+
+impl Integrator for BidirectionalPathTracer {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        settings: &Settings,
+        splats: &mut Vec<(usize, Vec3)>,
+    ) -> Vec3 {
+        let film = Film::new(&scene.camera, settings);
+        let max_vertices = settings.max_depth as usize + 1;
+
+        let (camera_path, escaped) = camera_subpath(scene, settings, &film, ray, max_vertices);
+        let light_path = light_subpath(scene, settings, max_vertices);
+
+        //The sky starts no light subpaths, only camera paths escaping find it
+        let mut radiance = escaped;
+        for t in 1..=camera_path.len() {
+            for s in 0..=usize::max(light_path.len(), 1) {
+                //Lights seen directly are left to the camera paths, which find them
+                //in the right pixel
+                if s + t < 2 || (s, t) == (1, 1) || s + t - 1 > settings.max_depth as usize {
+                    continue;
+                }
+                if s == 1 {
+                    radiance += connect_light(scene, &film, &camera_path[..t]);
+                    continue;
+                }
+
+                let (contribution, pixel) = connect(scene, &film, &camera_path, &light_path, s, t);
+                if contribution.max_component() <= 0. {
+                    continue;
+                }
+
+                let weight = mis_weight(scene, &film, &camera_path, &light_path, s, t);
+                match pixel {
+                    Some(pixel) => splats.push((pixel, to_rgb(contribution * weight))),
+                    None => radiance += contribution * weight,
+                }
+            }
+        }
+        radiance
+    }
+}
+
+fn camera_subpath(
+    scene: &Scene,
+    settings: &Settings,
+    film: &Film,
+    ray: &Ray,
+    max_vertices: usize,
+) -> (Vec<Vertex>, Vec3) {
+    let rec = Hit {
+        point: scene.camera.origin(),
+        normal: scene.camera.forward(),
+        ..Hit::empty()
+    };
+    let mut path = vec![Vertex::new(
+        VertexKind::Camera,
+        rec,
+        *ray,
+        Vec3(1., 1., 1.),
+        1.,
+    )];
+
+    let pdf = film.pdf(&ray.at(1.));
+    let escaped = random_walk(
+        scene,
+        settings,
+        *ray,
+        Vec3(1., 1., 1.),
+        pdf,
+        &mut path,
+        max_vertices,
+    );
+    (path, escaped)
+}
+
+fn light_subpath(scene: &Scene, settings: &Settings, max_vertices: usize) -> Vec<Vertex> {
+    let emission = match scene.lights.sample_emission() {
+        Some(emission) if emission.pdf_direction > 0. => emission,
+        _ => return Vec::new(),
+    };
+
+    let rec = Hit {
+        point: emission.point,
+        normal: emission.normal,
+        material: emission.material,
+        ..Hit::empty()
+    };
+    let ray = Ray {
+        origin: emission.point,
+        dir: emission.dir,
+    };
+    let beta = Vec3(1., 1., 1.) / emission.pdf_position;
+    let mut light_vertex = Vertex::new(VertexKind::Light, rec, ray, beta, emission.pdf_position);
+    light_vertex.punctual = emission.punctual;
+    let mut path = vec![light_vertex];
+
+    let cosine = dot(&emission.normal, &emission.dir);
+    random_walk(
+        scene,
+        settings,
+        ray,
+        beta * emission.radiance * (cosine / emission.pdf_direction),
+        emission.pdf_direction,
+        &mut path,
+        max_vertices,
+    );
+    path
+}
+
+/// Extends the subpath along the ray, which leaves its last vertex with the density
+/// `pdf` per unit solid angle. Returns the light of the background the subpath
+/// escapes into, times the throughput up to there.
+fn random_walk(
+    scene: &Scene,
+    settings: &Settings,
+    mut ray: Ray,
+    mut beta: Vec3,
+    mut pdf: f64,
+    path: &mut Vec<Vertex>,
+    max_vertices: usize,
+) -> Vec3 {
+    let mut interior = Interior::default();
+    while path.len() < max_vertices {
+        let mut rec = Hit::empty();
+        let hit_anything = trace(&scene.world, &ray, &mut rec);
+        let kind = if let Some(collision) = fog_collision(scene, &ray, hit_anything, &rec) {
+            rec = collision;
+            VertexKind::Medium
+        } else if !hit_anything {
+            return beta * scene.background(ray.dir);
+        } else if matches!(
+            rec.material,
+            Material::Isotropic(..) | Material::HenyeyGreenstein(..) | Material::EmissiveMedium(..)
+        ) {
+            VertexKind::Medium
+        } else {
+            VertexKind::Surface
+        };
+        beta = beta * interior.transmittance(rec.t * ray.dir.length());
+
+        let previous = path.len() - 1;
+        let mut vertex = Vertex::new(kind, rec, ray, beta, 0.);
+        vertex.pdf_fwd = to_area(pdf, &path[previous], &vertex);
+        path.push(vertex);
+
+        let mut attenuation = Vec3(0., 0., 0.);
+        let mut scattered = Ray {
+            origin: Vec3(0., 0., 0.),
+            dir: Vec3(0., 0., 0.),
+        };
+        if !scatter(rec.material, &ray, &rec, &mut attenuation, &mut scattered) {
+            return Vec3(0., 0., 0.);
+        }
+
+        let dir = unit_vector(&scattered.dir);
+        let (pdf_fwd, pdf_rev) = if vertex.delta {
+            (0., 0.)
+        } else {
+            let reverse = Ray {
+                origin: rec.point + dir,
+                dir: -dir,
+            };
+            (
+                scattering(rec.material, &ray, &rec, &dir).map_or(0., |(_, pdf)| pdf),
+                scattering(rec.material, &reverse, &rec, &unit_vector(&-ray.dir))
+                    .map_or(0., |(_, pdf)| pdf),
+            )
+        };
+        path[previous].pdf_rev = to_area(pdf_rev, &vertex, &path[previous]);
+
+        interior.cross(&rec, &scattered);
+
+        beta = beta * attenuation;
+        pdf = pdf_fwd;
+        ray = scattered;
+
+        if !survives_roulette(&mut beta, path.len() as i32 - 2, settings) {
+            return Vec3(0., 0., 0.);
+        }
+    }
+    stats::record(|stats| stats.terminated_by_depth += 1);
+    Vec3(0., 0., 0.)
+}
+
+/// Converts a density per unit solid angle at `from` to one per unit area at `to`.
+fn to_area(pdf: f64, from: &Vertex, to: &Vertex) -> f64 {
+    let offset = to.point() - from.point();
+    let distance_squared = offset.length_squared();
+    if distance_squared == 0. {
+        return 0.;
+    }
+    let cosine = match to.kind {
+        VertexKind::Medium => 1.,
+        _ => dot(&to.rec.normal, &offset).abs() / distance_squared.sqrt(),
+    };
+    pdf * cosine / distance_squared
+}
+
+/// Density per unit area with which `vertex`, reached from `previous`, samples `next`.
+fn pdf(film: &Film, vertex: &Vertex, previous: Option<&Vertex>, next: &Vertex) -> f64 {
+    let dir = unit_vector(&(next.point() - vertex.point()));
+    let pdf = match vertex.kind {
+        VertexKind::Camera => film.pdf(&next.point()),
+        //Lights give their densities through `Lights::pdf_emission`
+        VertexKind::Light => 0.,
+        VertexKind::Surface | VertexKind::Medium => {
+            let incoming = match previous {
+                Some(previous) => Ray {
+                    origin: previous.point(),
+                    dir: vertex.point() - previous.point(),
+                },
+                None => vertex.ray,
+            };
+            scattering(vertex.rec.material, &incoming, &vertex.rec, &dir).map_or(0., |(_, pdf)| pdf)
+        }
+    };
+    to_area(pdf, vertex, next)
+}
+
+/// Light scattered at the vertex towards `to`, cosine included.
+fn scattered_towards(vertex: &Vertex, to: &Vertex) -> Vec3 {
+    let dir = unit_vector(&(to.point() - vertex.point()));
+    match vertex.kind {
+        VertexKind::Surface | VertexKind::Medium => {
+            scattering(vertex.rec.material, &vertex.ray, &vertex.rec, &dir)
+                .map_or(Vec3(0., 0., 0.), |(value, _)| value)
+        }
+        VertexKind::Camera | VertexKind::Light => Vec3(0., 0., 0.),
+    }
+}
+
+/// Weighted contribution of the path ending in a light sampled from the last of the
+/// camera vertices.
+fn connect_light(scene: &Scene, film: &Film, camera_path: &[Vertex]) -> Vec3 {
+    let none = Vec3(0., 0., 0.);
+    let camera_vertex = &camera_path[camera_path.len() - 1];
+    if !matches!(camera_vertex.kind, VertexKind::Surface | VertexKind::Medium) {
+        return none;
+    }
+    let sample = match scene.lights.sample(camera_vertex.point()) {
+        Some(sample) => sample,
+        None => return none,
+    };
+    let rec = &camera_vertex.rec;
+    let value = match scattering(rec.material, &camera_vertex.ray, rec, &sample.dir) {
+        Some((value, _)) if value.max_component() > 0. => value,
+        _ => return none,
+    };
+    let transmittance = visibility(scene, rec.point, sample.dir, sample.distance);
+    if transmittance == 0. {
+        return none;
+    }
+    let contribution = camera_vertex.beta * value * sample.radiance * (transmittance / sample.pdf);
+
+    //Far away lights start no light subpaths, this is the only way to find them
+    if sample.distance.is_infinite() {
+        return contribution;
+    }
+    let light_rec = Hit {
+        point: rec.point + sample.distance * sample.dir,
+        normal: sample.normal,
+        ..Hit::empty()
+    };
+    let mut light_vertex = Vertex::new(
+        VertexKind::Light,
+        light_rec,
+        Ray {
+            origin: light_rec.point,
+            dir: -sample.dir,
+        },
+        Vec3(1., 1., 1.),
+        0.,
+    );
+    light_vertex.punctual = sample.punctual;
+    contribution
+        * mis_weight(
+            scene,
+            film,
+            camera_path,
+            &[light_vertex],
+            1,
+            camera_path.len(),
+        )
+}
+
+/// Unweighted contribution of the path with `s` light and `t` camera vertices, and
+/// the pixel it lands in when it connects to the camera directly. Single light
+/// vertices are left to `connect_light`.
+fn connect(
+    scene: &Scene,
+    film: &Film,
+    camera_path: &[Vertex],
+    light_path: &[Vertex],
+    s: usize,
+    t: usize,
+) -> (Vec3, Option<usize>) {
+    let none = (Vec3(0., 0., 0.), None);
+
+    if s == 0 {
+        //The camera path found a light by itself
+        let last = &camera_path[t - 1];
+        if !matches!(last.kind, VertexKind::Surface | VertexKind::Medium) {
+            return none;
+        }
+        return (
+            last.beta * color_emitted(last.rec.material, last.rec.front_face),
+            None,
+        );
+    }
+
+    let light_vertex = &light_path[s - 1];
+    let camera_vertex = &camera_path[t - 1];
+    if light_vertex.delta || camera_vertex.delta {
+        return none;
+    }
+
+    let offset = camera_vertex.point() - light_vertex.point();
+    let distance = offset.length();
+    let dir = offset / distance;
+
+    let (camera_side, pixel) = if t == 1 {
+        //Light reaching the camera lands in whichever pixel it is seen in
+        match film.pixel(&light_vertex.point()) {
+            Some((pixel, cosine)) => {
+                let importance = 1. / (film.area * cosine * cosine * cosine);
+                (Vec3(importance, importance, importance), Some(pixel))
+            }
+            None => return none,
+        }
+    } else {
+        (
+            camera_vertex.beta * scattered_towards(camera_vertex, light_vertex),
+            None,
+        )
+    };
+
+    let light_side = light_vertex.beta * scattered_towards(light_vertex, camera_vertex);
+    let unoccluded = light_side * camera_side / (distance * distance);
+    if unoccluded.max_component() <= 0. {
+        return none;
+    }
+
+    let transmittance = visibility(scene, light_vertex.point(), dir, distance);
+    (unoccluded * transmittance, pixel)
+}
+
+/// Power heuristic weight of the strategy with `s` light and `t` camera vertices
+/// against all others building the same path.
+fn mis_weight(
+    scene: &Scene,
+    film: &Film,
+    camera_path: &[Vertex],
+    light_path: &[Vertex],
+    s: usize,
+    t: usize,
+) -> f64 {
+    //Lights seen directly are only found by camera paths
+    if s + t == 2 {
+        return 1.;
+    }
+
+    //Densities change at the connection, where the vertices are reached from the
+    //other subpath
+    let mut camera = camera_path[..t].to_vec();
+    let mut light = light_path[..s].to_vec();
+
+    if s == 0 {
+        let last = camera[t - 1];
+        let previous = camera[t - 2];
+        let dir = unit_vector(&(previous.point() - last.point()));
+        let (pdf_position, pdf_direction) = scene.lights.pdf_emission(last.point(), dir);
+
+        //Lights the light subpaths cannot start from are found this way only
+        if pdf_position == 0. || pdf_direction == 0. {
+            return 1.;
+        }
+        camera[t - 1].pdf_rev = pdf_position;
+        camera[t - 2].pdf_rev = to_area(pdf_direction, &last, &previous);
+    } else if s == 1 {
+        //The light was sampled from the camera vertex, its densities are those of the
+        //light subpaths starting there
+        let light_vertex = light[0];
+        let camera_vertex = camera[t - 1];
+        let dir = unit_vector(&(camera_vertex.point() - light_vertex.point()));
+        let (pdf_position, pdf_direction) = scene.lights.pdf_emission(light_vertex.point(), dir);
+        if pdf_position == 0. || pdf_direction == 0. {
+            return 1.;
+        }
+        light[0].pdf_fwd = pdf_position;
+        camera[t - 1].pdf_rev = to_area(pdf_direction, &light_vertex, &camera_vertex);
+        camera[t - 2].pdf_rev = pdf(film, &camera_vertex, Some(&light_vertex), &camera[t - 2]);
+        light[0].pdf_rev = pdf(
+            film,
+            &camera_vertex,
+            t.checked_sub(2).map(|i| &camera[i]),
+            &light_vertex,
+        );
+    } else {
+        let light_vertex = light[s - 1];
+        let camera_vertex = camera[t - 1];
+        camera[t - 1].pdf_rev = pdf(film, &light_vertex, Some(&light[s - 2]), &camera_vertex);
+        if t > 1 {
+            camera[t - 2].pdf_rev = pdf(film, &camera_vertex, Some(&light_vertex), &camera[t - 2]);
+        }
+        light[s - 1].pdf_rev = pdf(
+            film,
+            &camera_vertex,
+            t.checked_sub(2).map(|i| &camera[i]),
+            &light_vertex,
+        );
+        light[s - 2].pdf_rev = pdf(film, &light_vertex, Some(&camera_vertex), &light[s - 2]);
+    }
+
+    //Zero densities belong to mirrors and glass, which cancel out of the ratios
+    let remap = |pdf: f64| if pdf == 0. { 1. } else { pdf };
+    let mut sum = 0.;
+
+    let mut ratio = 1.;
+    for i in (1..t).rev() {
+        ratio *= remap(camera[i].pdf_rev) / remap(camera[i].pdf_fwd);
+        if !camera[i].delta && !camera[i - 1].delta {
+            sum += ratio * ratio;
+        }
+    }
+
+    let mut ratio = 1.;
+    for i in (0..s).rev() {
+        ratio *= remap(light[i].pdf_rev) / remap(light[i].pdf_fwd);
+        let reachable = if i == 0 {
+            !light[0].punctual
+        } else {
+            !light[i - 1].delta
+        };
+        if !light[i].delta && reachable {
+            sum += ratio * ratio;
+        }
+    }
+
+    1. / (1. + sum)
+}
+
+#[cfg(test)]
+mod bidirectional_tests {
+    use super::*;
+    use crate::{
+        framebuffer::Framebuffer,
+        hittables::{
+            xz_rect,
+            Hittables::{self, HittableObjects, Sphere, Triangle},
+        },
+        integrator::IntegratorKind,
+        lights::Punctual,
+        materials::Material::{Lambertian, Light, OneSidedLight},
+        render::render_tile,
+        tiles::Tile,
+    };
+
+    //Mean pixel value of a small render from inside a diffuse box lit by the light
+    fn mean_image(
+        integrator: IntegratorKind,
+        samples_per_pixel: u32,
+        light: Hittables,
+        punctual: &[Punctual],
+    ) -> Vec3 {
+        let settings = Settings {
+            width: 8,
+            height: 6,
+            samples_per_pixel,
+            max_depth: 16,
+            russian_roulette_depth: 3,
+            seed: 1,
+            integrator,
+            spectral: false,
+        };
+        let mut scene = Scene::new(
+            HittableObjects(vec![
+                Sphere(Vec3(0., 0., 0.), -10., Lambertian(0.7, 0.7, 0.7)),
+                light,
+            ]),
+            None,
+            Camera::new(
+                Vec3(0., -5., 5.),
+                Vec3(0., 0., 0.),
+                Vec3(0., 1., 0.),
+                60.,
+                8. / 6.,
+            ),
+        );
+        for &light in punctual {
+            scene.lights.add(light);
+        }
+
+        let tile = Tile::image(settings.width, settings.height);
+        let mut framebuffer = Framebuffer::new(settings.width, settings.height);
+        let (mut pixels, mut samples) = framebuffer.tile(&tile);
+        let mut splats = Vec::new();
+        render_tile(
+            &scene,
+            &settings,
+            &tile,
+            u32::MAX,
+            &mut pixels,
+            &mut samples,
+            &mut splats,
+        );
+        framebuffer.set_tile(&tile, &pixels, &samples);
+        framebuffer.add_splats(&splats);
+
+        let pixel_count = (settings.width * settings.height) as f64;
+        let total_samples = pixel_count * samples_per_pixel as f64;
+        let mut mean = Vec3(0., 0., 0.);
+        for i in 0..framebuffer.pixels.len() {
+            mean += framebuffer.pixels[i] / samples_per_pixel as f64
+                + framebuffer.splats[i] * (pixel_count / total_samples);
+        }
+        mean / pixel_count
+    }
+
+    #[test]
+    fn test_converges_to_path_tracing() {
+        let light = || Sphere(Vec3(0., 5., 0.), 1., Light(4., 4., 4.));
+        let path = mean_image(IntegratorKind::Path, 1000, light(), &[]);
+        let bidirectional = mean_image(IntegratorKind::Bidirectional, 200, light(), &[]);
+        assert!(
+            f64::abs(path.0 - bidirectional.0) < 0.03 * path.0,
+            "{:?} != {:?}",
+            path,
+            bidirectional
+        );
+    }
+
+    #[test]
+    fn test_area_lights_converge_to_path_tracing() {
+        //A panel lighting the box both ways and a triangle shining down only
+        let lights = || {
+            HittableObjects(vec![
+                xz_rect(-2., 2., -2., 2., 5., Light(1., 1., 1.)),
+                Triangle(
+                    Vec3(-3., 3., -3.),
+                    Vec3(-3., 3., 0.),
+                    Vec3(0., 3., -3.),
+                    OneSidedLight(2., 2., 2.),
+                ),
+            ])
+        };
+        let path = mean_image(IntegratorKind::Path, 1000, lights(), &[]);
+        for (integrator, samples) in [
+            (IntegratorKind::LightSampling, 200),
+            (IntegratorKind::Bidirectional, 200),
+        ] {
+            let mean = mean_image(integrator, samples, lights(), &[]);
+            assert!(
+                f64::abs(path.0 - mean.0) < 0.03 * path.0,
+                "{}: {:?} != {:?}",
+                integrator,
+                path,
+                mean
+            );
+        }
+    }
+
+    #[test]
+    fn test_punctual_lights_converge_to_light_sampling() {
+        //A small sphere lit the same way keeps some paths ending on an area light
+        let sphere = || Sphere(Vec3(0., 5., 0.), 0.5, Light(4., 4., 4.));
+        let punctual = [
+            Punctual::Point(Vec3(0., 3., 0.), Light(20., 20., 20.)),
+            Punctual::Spot(
+                Vec3(3., 0., 0.),
+                Vec3(-1., -1., 0.),
+                20.,
+                40.,
+                Light(40., 40., 40.),
+            ),
+        ];
+        let light_sampling = mean_image(IntegratorKind::LightSampling, 200, sphere(), &punctual);
+        let bidirectional = mean_image(IntegratorKind::Bidirectional, 200, sphere(), &punctual);
+        assert!(
+            f64::abs(light_sampling.0 - bidirectional.0) < 0.03 * light_sampling.0,
+            "{:?} != {:?}",
+            light_sampling,
+            bidirectional
+        );
+    }
+}
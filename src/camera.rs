@@ -1,14 +1,18 @@
 use crate::{
     ray::Ray,
     utils::degrees_to_radians,
-    vec3::{cross, unit_vector, Vec3},
+    vec3::{cross, dot, unit_vector, Vec3},
 };
 
+#[derive(Debug)]
 pub struct Camera {
     origin: Vec3,
     lower_left_corner: Vec3,
     horizontal: Vec3,
     vertical: Vec3,
+    /// Factor the radiance reaching the image is scaled by, to bring bright scenes
+    /// such as daylight into its range.
+    pub exposure: f64,
 }
 
 impl Camera {
@@ -19,6 +23,38 @@ impl Camera {
         }
     }
 
+    pub fn origin(&self) -> Vec3 {
+        self.origin
+    }
+
+    /// Unit vector along the view direction.
+    pub fn forward(&self) -> Vec3 {
+        unit_vector(
+            &(self.lower_left_corner + self.horizontal / 2. + self.vertical / 2. - self.origin),
+        )
+    }
+
+    /// Area of the viewport, which lies at unit distance in front of the camera.
+    pub fn viewport_area(&self) -> f64 {
+        self.horizontal.length() * self.vertical.length()
+    }
+
+    /// Viewport coordinates of the ray from the camera through the point, as passed to
+    /// `get_ray`, `None` for points behind the camera.
+    pub fn project(&self, point: &Vec3) -> Option<(f64, f64)> {
+        let dir = *point - self.origin;
+        let along = dot(&dir, &self.forward());
+        if along <= 0. {
+            return None;
+        }
+
+        let on_viewport = dir / along - (self.lower_left_corner - self.origin);
+        Some((
+            dot(&on_viewport, &self.horizontal) / self.horizontal.length_squared(),
+            dot(&on_viewport, &self.vertical) / self.vertical.length_squared(),
+        ))
+    }
+
     pub fn new(look_from: Vec3, look_at: Vec3, vup: Vec3, vfov: f64, aspect_ratio: f64) -> Camera {
         let theta = degrees_to_radians(vfov);
         let h = f64::tan(theta / 2.);
@@ -39,6 +75,29 @@ impl Camera {
             lower_left_corner,
             horizontal,
             vertical,
+            exposure: 1.,
+        }
+    }
+}
+
+#[cfg(test)]
+mod camera_tests {
+    use super::*;
+
+    #[test]
+    fn test_project_inverts_get_ray() {
+        let camera = Camera::new(
+            Vec3(1., 2., 3.),
+            Vec3(0., 0., -1.),
+            Vec3(0., 1., 0.),
+            40.,
+            1.5,
+        );
+        for (u, v) in [(0.5, 0.5), (0.1, 0.9), (1.2, -0.3)] {
+            let ray = camera.get_ray(u, v);
+            let (pu, pv) = camera.project(&ray.at(7.)).unwrap();
+            assert!(f64::abs(pu - u) < 1e-9 && f64::abs(pv - v) < 1e-9);
         }
+        assert!(camera.project(&Vec3(1., 2., 10.)).is_none());
     }
 }
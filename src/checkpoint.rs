@@ -0,0 +1,369 @@
+//! Checkpoints of an unfinished render, to pick it up again after a crash or reboot.
+//!
+//! Checkpoint files use a little-endian binary layout:
+//!
+//! ```text
+//! magic           4 bytes  "RCK5"
+//! width height    2 x u32  image size
+//! max depth       u32
+//! roulette depth  u32      depth from which paths end by Russian roulette
+//! seed            u64      base seed of the per-sample random sequences
+//! integrator      u32 length, UTF-8 name as given to --integrator
+//! spectral        u8       1 for renders with --spectral
+//! pixels          width * height x (3 x f64 radiance sum, u32 sample count)
+//! splats          width * height x 3 x f64, only for integrators that splat
+//! ```
+//!
+//! Pixels are stored row by row from the top of the image. Every sample draws its
+//! random numbers from a sequence seeded by the pixel and the number of the sample, so
+//! the sums and counts are all the state needed to continue exactly where the render
+//! stopped.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use crate::{framebuffer::Framebuffer, integrator::IntegratorKind, vec3::Vec3};
+
+const MAGIC: &[u8; 4] = b"RCK5";
+
+/// Render settings a checkpoint can only be resumed with.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Checkpoint {
+    pub max_depth: i32,
+    pub russian_roulette_depth: i32,
+    pub seed: u64,
+    pub integrator: IntegratorKind,
+    pub spectral: bool,
+}
+
+impl Checkpoint {
+    pub fn load(path: &str, width: usize, height: usize) -> io::Result<(Checkpoint, Framebuffer)> {
+        Checkpoint::read(&mut BufReader::new(File::open(path)?), width, height)
+    }
+
+    /// Writes the checkpoint next to `path` first and then moves it into place, so a
+    /// crash while saving keeps the previous checkpoint intact.
+    pub fn save(&self, framebuffer: &Framebuffer, path: &str) -> io::Result<()> {
+        let mut partial = Path::new(path).as_os_str().to_owned();
+        partial.push(".partial");
+
+        self.write(framebuffer, &mut BufWriter::new(File::create(&partial)?))?;
+        fs::rename(&partial, path)
+    }
+
+    /// Reads a checkpoint of an image of the given size, refusing others before making
+    /// room for their pixels.
+    pub fn read(
+        reader: &mut impl Read,
+        width: usize,
+        height: usize,
+    ) -> io::Result<(Checkpoint, Framebuffer)> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a checkpoint file",
+            ));
+        }
+
+        let size = (read_u32(reader)? as usize, read_u32(reader)? as usize);
+        if size != (width, height) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "it was rendered at {}x{}, not {}x{}",
+                    size.0, size.1, width, height
+                ),
+            ));
+        }
+        let checkpoint = Checkpoint {
+            max_depth: read_u32(reader)? as i32,
+            russian_roulette_depth: read_u32(reader)? as i32,
+            seed: read_u64(reader)?,
+            integrator: read_integrator(reader)?,
+            spectral: read_u8(reader)? != 0,
+        };
+
+        let mut framebuffer = Framebuffer::new(width, height);
+        read_pixels(reader, &mut framebuffer.pixels, &mut framebuffer.samples)?;
+        if checkpoint.integrator.splats() {
+            for splat in &mut framebuffer.splats {
+                *splat = read_vec3(reader)?;
+            }
+        }
+
+        Ok((checkpoint, framebuffer))
+    }
+
+    pub fn write(&self, framebuffer: &Framebuffer, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(framebuffer.width as u32).to_le_bytes())?;
+        writer.write_all(&(framebuffer.height as u32).to_le_bytes())?;
+        writer.write_all(&(self.max_depth as u32).to_le_bytes())?;
+        writer.write_all(&(self.russian_roulette_depth as u32).to_le_bytes())?;
+        writer.write_all(&self.seed.to_le_bytes())?;
+        write_string(writer, &self.integrator.to_string())?;
+        writer.write_all(&[self.spectral as u8])?;
+        write_pixels(writer, &framebuffer.pixels, &framebuffer.samples)?;
+        if self.integrator.splats() {
+            for splat in &framebuffer.splats {
+                write_vec3(writer, splat)?;
+            }
+        }
+        writer.flush()
+    }
+}
+
+/// Reads radiance sums and sample counts into the given buffers, in the layout of
+/// the pixels of a checkpoint.
+pub fn read_pixels(
+    reader: &mut impl Read,
+    pixels: &mut [Vec3],
+    samples: &mut [u32],
+) -> io::Result<()> {
+    for (pixel, samples) in pixels.iter_mut().zip(samples.iter_mut()) {
+        *pixel = read_vec3(reader)?;
+        *samples = read_u32(reader)?;
+    }
+    Ok(())
+}
+
+pub fn write_pixels(writer: &mut impl Write, pixels: &[Vec3], samples: &[u32]) -> io::Result<()> {
+    for (pixel, samples) in pixels.iter().zip(samples) {
+        write_vec3(writer, pixel)?;
+        writer.write_all(&samples.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn read_vec3(reader: &mut impl Read) -> io::Result<Vec3> {
+    Ok(Vec3(
+        read_f64(reader)?,
+        read_f64(reader)?,
+        read_f64(reader)?,
+    ))
+}
+
+pub fn write_vec3(writer: &mut impl Write, vec: &Vec3) -> io::Result<()> {
+    for axis in 0..3 {
+        writer.write_all(&vec[axis].to_le_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0u8; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+pub fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+pub fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Reads an integrator as written by `write_string` with its name.
+pub fn read_integrator(reader: &mut impl Read) -> io::Result<IntegratorKind> {
+    let name = read_string(reader)?;
+    IntegratorKind::parse(&name).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown integrator '{}'", name),
+        )
+    })
+}
+
+pub fn write_string(writer: &mut impl Write, string: &str) -> io::Result<()> {
+    writer.write_all(&(string.len() as u32).to_le_bytes())?;
+    writer.write_all(string.as_bytes())
+}
+
+fn read_string(reader: &mut impl Read) -> io::Result<String> {
+    let length = read_u32(reader)? as u64;
+    let mut bytes = Vec::new();
+    reader.take(length).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn read_f64(reader: &mut impl Read) -> io::Result<f64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod checkpoint_tests {
+    use super::*;
+    use crate::{
+        camera::Camera,
+        hittables::Hittables::{HittableObjects, Sphere},
+        materials::Material::{Lambertian, Light},
+        render::{render_tile, Scene, Settings},
+        tiles::{tiles, Tile, TileOrder},
+    };
+
+    /// Renders passes of a sample per pixel, as progressive renders do, until every
+    /// pixel is done or `stop` tiles have been rendered.
+    fn render(scene: &Scene, settings: &Settings, framebuffer: &mut Framebuffer, stop: usize) {
+        let tiles = tiles(
+            &Tile::image(settings.width, settings.height),
+            4,
+            TileOrder::Scanline,
+        );
+        let mut rendered = 0;
+        while framebuffer
+            .samples
+            .iter()
+            .any(|&count| count < settings.samples_per_pixel)
+        {
+            for tile in &tiles {
+                if rendered == stop {
+                    return;
+                }
+                let (mut pixels, mut samples) = framebuffer.tile(tile);
+                let mut splats = Vec::new();
+                render_tile(
+                    scene,
+                    settings,
+                    tile,
+                    1,
+                    &mut pixels,
+                    &mut samples,
+                    &mut splats,
+                );
+                framebuffer.set_tile(tile, &pixels, &samples);
+                framebuffer.add_splats(&splats);
+                rendered += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut framebuffer = Framebuffer::new(3, 2);
+        framebuffer.pixels[1] = Vec3(0.1, 2.5, 1e-9);
+        framebuffer.samples[1] = 7;
+        framebuffer.samples[5] = 3;
+        let checkpoint = Checkpoint {
+            max_depth: 8,
+            russian_roulette_depth: 3,
+            seed: u64::MAX - 1,
+            integrator: IntegratorKind::Bidirectional,
+            spectral: true,
+        };
+        framebuffer.splats[4] = Vec3(3., 0., 0.5);
+
+        let mut bytes = Vec::new();
+        checkpoint.write(&framebuffer, &mut bytes).unwrap();
+        let (read, read_framebuffer) = Checkpoint::read(&mut bytes.as_slice(), 3, 2).unwrap();
+
+        assert_eq!(read, checkpoint);
+        assert_eq!((read_framebuffer.width, read_framebuffer.height), (3, 2));
+        assert_eq!(read_framebuffer.pixels[1], framebuffer.pixels[1]);
+        assert_eq!(read_framebuffer.samples, framebuffer.samples);
+        assert_eq!(read_framebuffer.splats, framebuffer.splats);
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        assert!(Checkpoint::read(&mut b"VXG1....".as_slice(), 3, 2).is_err());
+    }
+
+    #[test]
+    fn test_rejects_other_sizes() {
+        let mut bytes = Vec::new();
+        let checkpoint = Checkpoint {
+            max_depth: 8,
+            russian_roulette_depth: 3,
+            seed: 1,
+            integrator: IntegratorKind::Path,
+            spectral: false,
+        };
+        checkpoint
+            .write(&Framebuffer::new(3, 2), &mut bytes)
+            .unwrap();
+        assert!(matches!(
+            Checkpoint::read(&mut bytes.as_slice(), 2, 3),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData
+        ));
+
+        //A huge size in a short file is refused before anything is allocated for it
+        bytes[4..12].copy_from_slice(&[0xff; 8]);
+        assert!(Checkpoint::read(&mut bytes.as_slice(), 3, 2).is_err());
+    }
+
+    #[test]
+    fn test_resumed_render_matches_uninterrupted() {
+        for integrator in [IntegratorKind::LightSampling, IntegratorKind::Bidirectional] {
+            let settings = Settings {
+                width: 12,
+                height: 8,
+                samples_per_pixel: 3,
+                max_depth: 4,
+                russian_roulette_depth: 2,
+                seed: 9,
+                integrator,
+                spectral: false,
+            };
+            let mut scene = Scene::new(
+                HittableObjects(vec![
+                    Sphere(Vec3(0., 0., -1.), 0.5, Lambertian(0.7, 0.3, 0.3)),
+                    Sphere(Vec3(0., -100.5, -1.), 100., Lambertian(0.5, 0.5, 0.5)),
+                    Sphere(Vec3(0., 3., -1.), 1., Light(4., 4., 4.)),
+                ]),
+                None,
+                Camera::new(
+                    Vec3(0., 0., 1.),
+                    Vec3(0., 0., -1.),
+                    Vec3(0., 1., 0.),
+                    60.,
+                    settings.width as f64 / settings.height as f64,
+                ),
+            );
+            scene.prepare(&settings);
+            let checkpoint = Checkpoint {
+                max_depth: settings.max_depth,
+                russian_roulette_depth: settings.russian_roulette_depth,
+                seed: settings.seed,
+                integrator,
+                spectral: settings.spectral,
+            };
+
+            let mut uninterrupted = Framebuffer::new(settings.width, settings.height);
+            render(&scene, &settings, &mut uninterrupted, usize::MAX);
+
+            //Stops halfway through the second pass
+            let mut stopped = Framebuffer::new(settings.width, settings.height);
+            render(&scene, &settings, &mut stopped, 9);
+            let mut bytes = Vec::new();
+            checkpoint.write(&stopped, &mut bytes).unwrap();
+            let (read, mut resumed) =
+                Checkpoint::read(&mut bytes.as_slice(), settings.width, settings.height).unwrap();
+            assert_eq!(read, checkpoint);
+            assert!(resumed.samples.contains(&1) && resumed.samples.contains(&2));
+            render(&scene, &settings, &mut resumed, usize::MAX);
+
+            assert_eq!(resumed.samples, uninterrupted.samples);
+            assert_eq!(resumed.pixels, uninterrupted.pixels);
+            //Splats land in a different order, which only rounding can tell
+            for (resumed, uninterrupted) in resumed.splats.iter().zip(&uninterrupted.splats) {
+                assert!((*resumed - *uninterrupted).length() <= 1e-9 * uninterrupted.length());
+            }
+            assert_eq!(resumed.to_image(), uninterrupted.to_image());
+        }
+    }
+}
@@ -0,0 +1,354 @@
+use crate::{
+    aabb::Aabb,
+    hittable::Hit,
+    hittables::{bounding_box, hit, set_sphere_hit, sphere_roots, Hittables},
+    materials::Material,
+    ray::Ray,
+};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    /// The first operand with the second one carved out.
+    Difference,
+}
+
+/// Span of the ray inside a solid as its entry and exit surfaces. Unlike regular hit
+/// records, the normals always point out of the solid.
+type Interval = (Hit, Hit);
+
+//Upper bound on the surface crossings followed along a ray through one primitive
+const MAX_CROSSINGS: usize = 16;
+
+/// All spans of the ray inside the solid, sorted along the ray. Open surfaces (planes,
+/// quads, disks) and participating media enclose no volume and yield no spans.
+pub fn intervals(object: &Hittables, ray: &Ray) -> Vec<Interval> {
+    match object {
+        Hittables::HittableObjects(list) => list.iter().fold(Vec::new(), |acc, item| {
+            combine(CsgOperation::Union, acc, intervals(item, ray))
+        }),
+        Hittables::Sphere(center, radius, material) => {
+            let (near, far) = match sphere_roots(*center, *radius, ray) {
+                Some(roots) => roots,
+                None => return Vec::new(),
+            };
+
+            let radius = f64::abs(*radius);
+            let mut entry = Hit::empty();
+            let mut exit = Hit::empty();
+            set_sphere_hit(*center, radius, *material, ray, near, &mut entry);
+            set_sphere_hit(*center, radius, *material, ray, far, &mut exit);
+            entry.normal = (entry.point - *center) / radius;
+            exit.normal = (exit.point - *center) / radius;
+
+            vec![(entry, exit)]
+        }
+        Hittables::Csg(operation, a, b) => {
+            combine(*operation, intervals(a, ray), intervals(b, ray))
+        }
+        Hittables::Plane(..)
+        | Hittables::Quad(..)
+        | Hittables::Disk(..)
+        | Hittables::ConstantMedium(..)
+        | Hittables::VoxelMedium(..) => Vec::new(),
+        _ => crossing_intervals(object, ray),
+    }
+}
+
+/// Follows the ray through every surface of a closed primitive, pairing the surfaces
+/// where it enters with the ones where it leaves.
+fn crossing_intervals(object: &Hittables, ray: &Ray) -> Vec<Interval> {
+    let mut result = Vec::new();
+    let mut entry = None;
+    let mut t_min = f64::NEG_INFINITY;
+    let mut rec = Hit::empty();
+
+    for _ in 0..MAX_CROSSINGS {
+        if !hit(object, ray, t_min, f64::INFINITY, &mut rec) {
+            break;
+        }
+        t_min = rec.t + 1e-7;
+
+        if rec.front_face {
+            entry = Some(rec);
+        } else {
+            rec.normal = -rec.normal;
+            if let Some(entry) = entry.take() {
+                result.push((entry, rec));
+            }
+        }
+    }
+
+    result
+}
+
+fn inside(operation: CsgOperation, in_a: bool, in_b: bool) -> bool {
+    match operation {
+        CsgOperation::Union => in_a || in_b,
+        CsgOperation::Intersection => in_a && in_b,
+        CsgOperation::Difference => in_a && !in_b,
+    }
+}
+
+/// Applies the operation to two sorted lists of spans by sweeping over their surfaces
+/// in order and keeping the ones where the ray enters or leaves the result.
+fn combine(operation: CsgOperation, a: Vec<Interval>, b: Vec<Interval>) -> Vec<Interval> {
+    let mut events: Vec<(Hit, bool, bool)> = Vec::with_capacity(2 * (a.len() + b.len()));
+    for (entry, exit) in a {
+        events.push((entry, true, true));
+        events.push((exit, true, false));
+    }
+    for (entry, exit) in b {
+        events.push((entry, false, true));
+        events.push((exit, false, false));
+    }
+    events.sort_by(|x, y| x.0.t.total_cmp(&y.0.t));
+
+    let mut result = Vec::new();
+    let mut in_a = false;
+    let mut in_b = false;
+    let mut a_material = Material::Init;
+    let mut entry = None;
+
+    for (mut surface, from_a, entering) in events {
+        let was_inside = inside(operation, in_a, in_b);
+        if from_a {
+            in_a = entering;
+            if entering {
+                a_material = surface.material;
+            }
+        } else {
+            in_b = entering;
+        }
+
+        let is_inside = inside(operation, in_a, in_b);
+        if was_inside == is_inside {
+            continue;
+        }
+
+        //Carved surfaces face into the removed solid and belong to the first operand
+        if operation == CsgOperation::Difference && !from_a {
+            surface.normal = -surface.normal;
+            surface.material = a_material;
+        }
+
+        if is_inside {
+            entry = Some(surface);
+        } else if let Some(entry) = entry.take() {
+            result.push((entry, surface));
+        }
+    }
+
+    result
+}
+
+pub fn hit_csg(
+    operation: CsgOperation,
+    a: &Hittables,
+    b: &Hittables,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rec: &mut Hit,
+) -> bool {
+    for (entry, exit) in combine(operation, intervals(a, ray), intervals(b, ray)) {
+        for surface in [entry, exit] {
+            if t_min <= surface.t && surface.t <= t_max {
+                *rec = surface;
+                rec.set_face_normal(ray, &surface.normal);
+                return true;
+            }
+        }
+    }
+    false
+}
+
+pub fn csg_bounding_box(operation: CsgOperation, a: &Hittables, b: &Hittables) -> Option<Aabb> {
+    match operation {
+        CsgOperation::Union => Some(Aabb::surrounding(&bounding_box(a)?, &bounding_box(b)?)),
+        CsgOperation::Intersection => bounding_box(a).or_else(|| bounding_box(b)),
+        CsgOperation::Difference => bounding_box(a),
+    }
+}
+
+#[cfg(test)]
+mod csg_tests {
+    use super::*;
+    use crate::{materials::Material::Dielectric, vec3::Vec3};
+
+    fn ray(origin: Vec3, dir: Vec3) -> Ray {
+        Ray { origin, dir }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!(f64::abs(a - b) < 1e-6, "{} != {}", a, b);
+    }
+
+    fn assert_close_vec(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-6, "{:?} != {:?}", a, b);
+    }
+
+    //Unit sphere at the origin and a unit sphere shifted by 1.5 along z
+    fn csg(operation: CsgOperation) -> Hittables {
+        Hittables::Csg(
+            operation,
+            Box::new(Hittables::Sphere(Vec3(0., 0., 0.), 1., Dielectric(1.5))),
+            Box::new(Hittables::Sphere(Vec3(0., 0., 1.5), 1., Dielectric(1.5))),
+        )
+    }
+
+    #[test]
+    fn test_union() {
+        let union = csg(CsgOperation::Union);
+        let mut rec = Hit::empty();
+
+        assert!(hit(
+            &union,
+            &ray(Vec3(0., 0., 5.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 2.5);
+
+        //The inner surfaces where the spheres overlap are not part of the union
+        assert!(hit(
+            &union,
+            &ray(Vec3(0., 0., 0.75), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 1.75);
+        assert!(!rec.front_face);
+        assert_close_vec(rec.normal, Vec3(0., 0., 1.));
+    }
+
+    #[test]
+    fn test_intersection() {
+        let lens = csg(CsgOperation::Intersection);
+        let mut rec = Hit::empty();
+
+        assert!(hit(
+            &lens,
+            &ray(Vec3(0., 0., 5.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 4.);
+        assert_close_vec(rec.normal, Vec3(0., 0., 1.));
+
+        assert!(hit(
+            &lens,
+            &ray(Vec3(0., 0., 5.), Vec3(0., 0., -1.)),
+            4.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 4.5);
+
+        //Outside the overlap
+        assert!(!hit(
+            &lens,
+            &ray(Vec3(0., 0.95, 5.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+    }
+
+    #[test]
+    fn test_difference() {
+        let concave = csg(CsgOperation::Difference);
+        let mut rec = Hit::empty();
+
+        //Enters through the carved surface, whose normal faces the removed sphere
+        assert!(hit(
+            &concave,
+            &ray(Vec3(0., 0., 5.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 4.5);
+        assert!(rec.front_face);
+        assert_close_vec(rec.normal, Vec3(0., 0., 1.));
+
+        assert!(hit(
+            &concave,
+            &ray(Vec3(0., 0., 5.), Vec3(0., 0., -1.)),
+            4.501,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 6.);
+        assert!(!rec.front_face);
+
+        //From the other side the removed part is never entered
+        assert!(hit(
+            &concave,
+            &ray(Vec3(0., 0., -5.), Vec3(0., 0., 1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 4.);
+        assert!(hit(
+            &concave,
+            &ray(Vec3(0., 0., -5.), Vec3(0., 0., 1.)),
+            4.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 5.5);
+        assert!(!rec.front_face);
+    }
+
+    #[test]
+    fn test_difference_with_cuboid() {
+        //Unit cube with a cylindrical hole drilled along y
+        let drilled = Hittables::Csg(
+            CsgOperation::Difference,
+            Box::new(Hittables::Cuboid(
+                Vec3(-1., -1., -1.),
+                Vec3(1., 1., 1.),
+                Dielectric(1.5),
+            )),
+            Box::new(Hittables::Cylinder(
+                Vec3(0., -2., 0.),
+                Vec3(0., 4., 0.),
+                0.5,
+                Dielectric(1.5),
+            )),
+        );
+        let mut rec = Hit::empty();
+
+        assert!(!hit(
+            &drilled,
+            &ray(Vec3(0., 5., 0.), Vec3(0., -1., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+
+        assert!(hit(
+            &drilled,
+            &ray(Vec3(5., 0., 0.), Vec3(-1., 0., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 4.);
+        assert!(hit(
+            &drilled,
+            &ray(Vec3(5., 0., 0.), Vec3(-1., 0., 0.)),
+            4.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 4.5);
+        assert_close_vec(rec.normal, Vec3(1., 0., 0.));
+    }
+}
@@ -0,0 +1,607 @@
+//! Rendering one image on several machines.
+//!
+//! A coordinator waits for workers on a TCP port and hands out one tile at a time.
+//! Workers build the scene themselves, the one given with `--scene`, and get the
+//! settings when they connect, with a fingerprint of the coordinator's scene. A worker
+//! whose scene differs, say another one or built from another revision, stops rather
+//! than render tiles of the wrong image. Each task carries the sums and sample counts
+//! the tile has so far, the worker renders it up to the samples per pixel and sends it
+//! back. Since every sample is seeded by its pixel and number, the image comes out the
+//! same as a local render. The tiles of workers that disconnect or stop answering go
+//! back to the queue for the others, as do those of workers sending splats outside the
+//! image.
+//!
+//! Messages are little-endian, with pixels as in checkpoints:
+//!
+//! ```text
+//! settings   4 bytes "RRD6", width height samples max_depth roulette_depth 5 x u32,
+//!            seed u64, integrator u32 length and UTF-8 name, spectral u8,
+//!            scene fingerprint u64
+//! task       u8 1, x y width height 4 x u32, width * height pixels
+//! done       u8 0
+//! result     width * height pixels, splat count u32, splats as pixel index u32 and
+//!            3 x f64, rays traced u64, statistics as u64 in the order of the fields
+//!            of `Stats`, intersection tests by primitive in between
+//! ```
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufReader, BufWriter, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use rayon::prelude::*;
+
+use crate::{
+    checkpoint::{
+        read_integrator, read_pixels, read_u32, read_u64, read_u8, read_vec3, write_pixels,
+        write_string, write_vec3,
+    },
+    framebuffer::Framebuffer,
+    progress,
+    render::{render_tile, Scene, Settings},
+    stats::{self, Stats},
+    tiles::Tile,
+    vec3::Vec3,
+};
+
+const MAGIC: &[u8; 4] = b"RRD6";
+const DONE: u8 = 0;
+const TASK: u8 = 1;
+
+/// Called with every finished tile, its radiance sums and sample counts.
+pub type OnTile<'a> = &'a (dyn Fn(&Tile, &[Vec3], &[u32]) + Sync);
+
+//How often idle connections look for work and the listener for new workers
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Renders `tiles` of the scene into the framebuffer on the workers connecting to
+/// `listener`, until all tiles are done or `stopped` returns true. `on_tile` is called
+/// with every finished tile after it is stored.
+#[allow(clippy::too_many_arguments)]
+pub fn coordinate(
+    listener: &TcpListener,
+    scene: &Scene,
+    settings: &Settings,
+    tiles: &[Tile],
+    framebuffer: &Mutex<Framebuffer>,
+    timeout: Duration,
+    stopped: &(dyn Fn() -> bool + Sync),
+    on_tile: OnTile,
+) -> io::Result<()> {
+    let queue = Mutex::new((0..tiles.len()).collect::<VecDeque<usize>>());
+    let remaining = AtomicUsize::new(tiles.len());
+    let coordinator = Coordinator {
+        fingerprint: scene.fingerprint(),
+        settings,
+        tiles,
+        framebuffer,
+        timeout,
+        stopped,
+        on_tile,
+        queue: &queue,
+        remaining: &remaining,
+    };
+
+    listener.set_nonblocking(true)?;
+    thread::scope(|scope| {
+        while remaining.load(Ordering::Relaxed) > 0 && !stopped() {
+            match listener.accept() {
+                Ok((stream, address)) => {
+                    let coordinator = &coordinator;
+                    scope.spawn(move || {
+                        if let Err(e) = coordinator.serve(stream) {
+                            eprintln!("Worker {} failed: {}", address, e);
+                        }
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    })
+}
+
+struct Coordinator<'a> {
+    fingerprint: u64,
+    settings: &'a Settings,
+    tiles: &'a [Tile],
+    framebuffer: &'a Mutex<Framebuffer>,
+    timeout: Duration,
+    stopped: &'a (dyn Fn() -> bool + Sync),
+    on_tile: OnTile<'a>,
+    //Tiles not handed out yet, and tiles not finished yet
+    queue: &'a Mutex<VecDeque<usize>>,
+    remaining: &'a AtomicUsize,
+}
+
+impl Coordinator<'_> {
+    fn serve(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_nodelay(true)?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut writer = BufWriter::new(stream);
+        write_settings(&mut writer, self.settings, self.fingerprint)?;
+
+        while !(self.stopped)() {
+            let index = match self.queue.lock().unwrap().pop_front() {
+                Some(index) => index,
+                None if self.remaining.load(Ordering::Relaxed) == 0 => break,
+                //Other workers still have tiles that may come back
+                None => {
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+            };
+
+            let tile = &self.tiles[index];
+            let (mut pixels, mut samples) = self.framebuffer.lock().unwrap().tile(tile);
+            let mut splats = Vec::new();
+            let (rays, worker_stats) = match exchange(
+                &mut reader,
+                &mut writer,
+                self.settings.width * self.settings.height,
+                tile,
+                &mut pixels,
+                &mut samples,
+                &mut splats,
+            ) {
+                Ok(result) => result,
+                Err(e) => {
+                    self.queue.lock().unwrap().push_front(index);
+                    return Err(e);
+                }
+            };
+
+            let mut framebuffer = self.framebuffer.lock().unwrap();
+            framebuffer.set_tile(tile, &pixels, &samples);
+            framebuffer.add_splats(&splats);
+            drop(framebuffer);
+            progress::count_rays(rays);
+            stats::merge(&worker_stats);
+            (self.on_tile)(tile, &pixels, &samples);
+            self.remaining.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        writer.write_all(&[DONE])?;
+        writer.flush()
+    }
+}
+
+/// Sends a tile to the worker and reads the rendered tile back into the same buffers,
+/// with the light it splatted to other pixels, which must be among the first
+/// `pixel_count`. Returns the rays the worker traced and its statistics.
+fn exchange(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    pixel_count: usize,
+    tile: &Tile,
+    pixels: &mut [Vec3],
+    samples: &mut [u32],
+    splats: &mut Vec<(usize, Vec3)>,
+) -> io::Result<(u64, Stats)> {
+    writer.write_all(&[TASK])?;
+    for n in [tile.x, tile.y, tile.width, tile.height] {
+        writer.write_all(&(n as u32).to_le_bytes())?;
+    }
+    write_pixels(writer, pixels, samples)?;
+    writer.flush()?;
+
+    read_pixels(reader, pixels, samples)?;
+    for _ in 0..read_u32(reader)? {
+        let pixel = read_u32(reader)? as usize;
+        if pixel >= pixel_count {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("splat on pixel {} outside the image", pixel),
+            ));
+        }
+        splats.push((pixel, read_vec3(reader)?));
+    }
+    Ok((read_u64(reader)?, read_stats(reader)?))
+}
+
+/// Connects to the coordinator at `address` and renders tiles until it is done.
+/// `scene` builds the scene for the settings the coordinator sends.
+pub fn work(address: &str, scene: impl FnOnce(&Settings) -> Scene) -> io::Result<()> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+
+    let (settings, mut scene) = read_settings(&mut reader, scene)?;
+    scene.prepare(&settings);
+
+    loop {
+        let mut tag = [0u8];
+        reader.read_exact(&mut tag)?;
+        if tag[0] == DONE {
+            return Ok(());
+        }
+
+        let tile = Tile {
+            x: read_u32(&mut reader)? as usize,
+            y: read_u32(&mut reader)? as usize,
+            width: read_u32(&mut reader)? as usize,
+            height: read_u32(&mut reader)? as usize,
+        };
+        let mut pixels = vec![Vec3(0., 0., 0.); tile.width * tile.height];
+        let mut samples = vec![0; tile.width * tile.height];
+        read_pixels(&mut reader, &mut pixels, &mut samples)?;
+
+        //The rows of the tile are rendered in parallel on this machine
+        let rows: Vec<_> = pixels
+            .par_chunks_mut(tile.width)
+            .zip(samples.par_chunks_mut(tile.width))
+            .enumerate()
+            .map(|(row, (pixels, samples))| {
+                let row_tile = Tile {
+                    y: tile.y + row,
+                    height: 1,
+                    ..tile
+                };
+                let mut splats = Vec::new();
+                render_tile(
+                    &scene,
+                    &settings,
+                    &row_tile,
+                    u32::MAX,
+                    pixels,
+                    samples,
+                    &mut splats,
+                );
+                (progress::take_rays(), stats::take(), splats)
+            })
+            .collect();
+        let rays: u64 = rows.iter().map(|(rays, _, _)| rays).sum();
+        let splats: Vec<&(usize, Vec3)> = rows.iter().flat_map(|(_, _, splats)| splats).collect();
+
+        write_pixels(&mut writer, &pixels, &samples)?;
+        writer.write_all(&(splats.len() as u32).to_le_bytes())?;
+        for (pixel, radiance) in splats {
+            writer.write_all(&(*pixel as u32).to_le_bytes())?;
+            write_vec3(&mut writer, radiance)?;
+        }
+        writer.write_all(&rays.to_le_bytes())?;
+        let mut total = Stats::new();
+        for (_, stats, _) in &rows {
+            total.add(stats);
+        }
+        write_stats(&mut writer, &total)?;
+        writer.flush()?;
+    }
+}
+
+fn write_settings(
+    writer: &mut impl Write,
+    settings: &Settings,
+    fingerprint: u64,
+) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(settings.width as u32).to_le_bytes())?;
+    writer.write_all(&(settings.height as u32).to_le_bytes())?;
+    writer.write_all(&settings.samples_per_pixel.to_le_bytes())?;
+    writer.write_all(&(settings.max_depth as u32).to_le_bytes())?;
+    writer.write_all(&(settings.russian_roulette_depth as u32).to_le_bytes())?;
+    writer.write_all(&settings.seed.to_le_bytes())?;
+    write_string(writer, &settings.integrator.to_string())?;
+    writer.write_all(&[settings.spectral as u8])?;
+    writer.write_all(&fingerprint.to_le_bytes())?;
+    writer.flush()
+}
+
+fn write_stats(writer: &mut impl Write, stats: &Stats) -> io::Result<()> {
+    let counters = [stats.primary_rays, stats.rays, stats.shadow_rays]
+        .into_iter()
+        .chain(stats.intersection_tests)
+        .chain([
+            stats.light_bvh_nodes_visited,
+            stats.terminated_by_depth,
+            stats.terminated_by_roulette,
+        ]);
+    for counter in counters {
+        writer.write_all(&counter.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_stats(reader: &mut impl Read) -> io::Result<Stats> {
+    let mut stats = Stats {
+        primary_rays: read_u64(reader)?,
+        rays: read_u64(reader)?,
+        shadow_rays: read_u64(reader)?,
+        ..Stats::new()
+    };
+    for tests in &mut stats.intersection_tests {
+        *tests = read_u64(reader)?;
+    }
+    stats.light_bvh_nodes_visited = read_u64(reader)?;
+    stats.terminated_by_depth = read_u64(reader)?;
+    stats.terminated_by_roulette = read_u64(reader)?;
+    Ok(stats)
+}
+
+/// Reads the settings and builds the scene for them with `scene`, failing if it is not
+/// the scene of the coordinator.
+fn read_settings(
+    reader: &mut impl Read,
+    scene: impl FnOnce(&Settings) -> Scene,
+) -> io::Result<(Settings, Scene)> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a rustracer coordinator",
+        ));
+    }
+
+    let settings = Settings {
+        width: read_u32(reader)? as usize,
+        height: read_u32(reader)? as usize,
+        samples_per_pixel: read_u32(reader)?,
+        max_depth: read_u32(reader)? as i32,
+        russian_roulette_depth: read_u32(reader)? as i32,
+        seed: read_u64(reader)?,
+        integrator: read_integrator(reader)?,
+        spectral: read_u8(reader)? != 0,
+    };
+    let fingerprint = read_u64(reader)?;
+    let scene = scene(&settings);
+    if scene.fingerprint() != fingerprint {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the coordinator renders another scene",
+        ));
+    }
+    Ok((settings, scene))
+}
+
+#[cfg(test)]
+mod distributed_tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    use crate::{
+        camera::Camera,
+        hittables::Hittables::{HittableObjects, Sphere},
+        integrator::IntegratorKind,
+        materials::Material::{Lambertian, Light},
+        tiles::{tiles, TileOrder},
+    };
+
+    fn scene(settings: &Settings) -> Scene {
+        Scene::new(
+            HittableObjects(vec![
+                Sphere(Vec3(0., 0., -1.), 0.5, Lambertian(0.7, 0.3, 0.3)),
+                Sphere(Vec3(0., 3., -1.), 2., Light(4., 4., 4.)),
+            ]),
+            None,
+            Camera::new(
+                Vec3(0., 0., 1.),
+                Vec3(0., 0., -1.),
+                Vec3(0., 1., 0.),
+                60.,
+                settings.width as f64 / settings.height as f64,
+            ),
+        )
+    }
+
+    #[test]
+    fn test_workers_match_local_render() {
+        let settings = Settings {
+            width: 24,
+            height: 12,
+            samples_per_pixel: 3,
+            max_depth: 4,
+            russian_roulette_depth: 2,
+            seed: 5,
+            integrator: IntegratorKind::LightSampling,
+            spectral: true,
+        };
+        let tiles = tiles(
+            &Tile::image(settings.width, settings.height),
+            8,
+            TileOrder::Spiral,
+        );
+
+        let mut local = Framebuffer::new(settings.width, settings.height);
+        for tile in &tiles {
+            let (mut pixels, mut samples) = local.tile(tile);
+            let mut splats = Vec::new();
+            render_tile(
+                &scene(&settings),
+                &settings,
+                tile,
+                u32::MAX,
+                &mut pixels,
+                &mut samples,
+                &mut splats,
+            );
+            local.set_tile(tile, &pixels, &samples);
+            local.add_splats(&splats);
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let framebuffer = Mutex::new(Framebuffer::new(settings.width, settings.height));
+        let finished = AtomicUsize::new(0);
+
+        let coordinator_scene = scene(&settings);
+        let before = stats::total();
+        thread::scope(|scope| {
+            let coordinator = scope.spawn(|| {
+                coordinate(
+                    &listener,
+                    &coordinator_scene,
+                    &settings,
+                    &tiles,
+                    &framebuffer,
+                    Duration::from_secs(10),
+                    &|| false,
+                    &|_, _, _| {
+                        finished.fetch_add(1, Ordering::Relaxed);
+                    },
+                )
+            });
+
+            //Takes a tile and disconnects without answering, before the workers start,
+            //so the tile has to go to one of them
+            let mut stream = TcpStream::connect(&address).unwrap();
+            read_settings(&mut stream, scene).unwrap();
+            let mut tag = [0u8];
+            stream.read_exact(&mut tag).unwrap();
+            assert_eq!(tag[0], TASK);
+            drop(stream);
+
+            let workers: Vec<_> = (0..2)
+                .map(|_| scope.spawn(|| work(&address, scene)))
+                .collect();
+            for worker in workers {
+                worker.join().unwrap().unwrap();
+            }
+            coordinator.join().unwrap().unwrap();
+        });
+
+        let framebuffer = framebuffer.into_inner().unwrap();
+        assert_eq!(finished.into_inner(), tiles.len());
+        assert_eq!(framebuffer.samples, local.samples);
+        assert_eq!(framebuffer.pixels, local.pixels);
+        assert!(local.pixels.iter().any(|pixel| pixel.0 > 0.));
+
+        //The statistics of the worker reach the coordinator, on top of what other
+        //tests running at the same time add
+        let camera_rays = (settings.width * settings.height) as u64 * 3;
+        assert!(stats::total().primary_rays - before.primary_rays >= camera_rays);
+    }
+
+    #[test]
+    fn test_requeues_splats_outside_image() {
+        let settings = Settings {
+            width: 8,
+            height: 4,
+            samples_per_pixel: 1,
+            max_depth: 2,
+            russian_roulette_depth: 2,
+            seed: 1,
+            integrator: IntegratorKind::Path,
+            spectral: false,
+        };
+        let tiles = [Tile::image(settings.width, settings.height)];
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let framebuffer = Mutex::new(Framebuffer::new(settings.width, settings.height));
+
+        let coordinator_scene = scene(&settings);
+        thread::scope(|scope| {
+            let coordinator = scope.spawn(|| {
+                coordinate(
+                    &listener,
+                    &coordinator_scene,
+                    &settings,
+                    &tiles,
+                    &framebuffer,
+                    Duration::from_secs(10),
+                    &|| false,
+                    &|_, _, _| {},
+                )
+            });
+
+            //Answers the task with a splat one past the last pixel
+            let mut stream = TcpStream::connect(&address).unwrap();
+            read_settings(&mut stream, scene).unwrap();
+            let mut task = vec![0u8; 17];
+            stream.read_exact(&mut task).unwrap();
+            let mut pixels = vec![Vec3(0., 0., 0.); 32];
+            let mut samples = vec![0; 32];
+            read_pixels(&mut stream, &mut pixels, &mut samples).unwrap();
+            write_pixels(&mut stream, &pixels, &samples).unwrap();
+            stream.write_all(&1u32.to_le_bytes()).unwrap();
+            stream.write_all(&32u32.to_le_bytes()).unwrap();
+            write_vec3(&mut stream, &Vec3(1., 1., 1.)).unwrap();
+            stream.write_all(&0u64.to_le_bytes()).unwrap();
+            write_stats(&mut stream, &Stats::new()).unwrap();
+            //The coordinator hangs up instead of sending the next task
+            assert_eq!(stream.read(&mut [0u8]).unwrap_or(0), 0);
+
+            work(&address, scene).unwrap();
+            coordinator.join().unwrap().unwrap();
+        });
+        let framebuffer = framebuffer.into_inner().unwrap();
+        assert!(framebuffer.samples.iter().all(|&n| n == 1));
+    }
+
+    #[test]
+    fn test_stats_round_trip() {
+        let mut stats = Stats::new();
+        stats.primary_rays = 1;
+        stats.shadow_rays = 2;
+        stats.intersection_tests[3] = 4;
+        stats.light_bvh_nodes_visited = 5;
+        stats.terminated_by_roulette = 6;
+        let mut bytes = Vec::new();
+        write_stats(&mut bytes, &stats).unwrap();
+        assert_eq!(read_stats(&mut bytes.as_slice()).unwrap(), stats);
+    }
+
+    #[test]
+    fn test_rejects_other_scene() {
+        let settings = Settings {
+            width: 8,
+            height: 4,
+            samples_per_pixel: 1,
+            max_depth: 2,
+            russian_roulette_depth: 2,
+            seed: 1,
+            integrator: IntegratorKind::Path,
+            spectral: false,
+        };
+        let tiles = [Tile::image(settings.width, settings.height)];
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let framebuffer = Mutex::new(Framebuffer::new(settings.width, settings.height));
+        let stopped = AtomicBool::new(false);
+
+        let coordinator_scene = scene(&settings);
+        thread::scope(|scope| {
+            let coordinator = scope.spawn(|| {
+                coordinate(
+                    &listener,
+                    &coordinator_scene,
+                    &settings,
+                    &tiles,
+                    &framebuffer,
+                    Duration::from_secs(10),
+                    &|| stopped.load(Ordering::Relaxed),
+                    &|_, _, _| panic!("tile of another scene"),
+                )
+            });
+
+            //Built from another revision, with the light moved
+            let other = |settings: &Settings| {
+                let mut scene = scene(settings);
+                scene.world =
+                    HittableObjects(vec![Sphere(Vec3(0., 4., -1.), 2., Light(4., 4., 4.))]);
+                scene
+            };
+            let error = work(&address, other).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            stopped.store(true, Ordering::Relaxed);
+            coordinator.join().unwrap().unwrap();
+        });
+        assert!(framebuffer
+            .into_inner()
+            .unwrap()
+            .samples
+            .iter()
+            .all(|&n| n == 0));
+    }
+}
@@ -3,9 +3,9 @@ use std::io::prelude::*;
 
 fn create_file(filename: &str) -> File {
     match File::create(filename) {
-        Ok(file) => return file,
+        Ok(file) => file,
         Err(_) => panic!("failed to create file"),
-    };
+    }
 }
 
 #[allow(unused_must_use, unused)]
@@ -27,7 +27,7 @@ pub fn write_test_image() {
             let ig = (255.999 * g) as i32;
             let ib = (255.999 * b) as i32;
 
-            write!(file, "{} {} {}\n", ir, ig, ib);
+            writeln!(file, "{} {} {}", ir, ig, ib);
         }
     }
 }
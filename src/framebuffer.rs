@@ -0,0 +1,197 @@
+use std::{fs, path::Path};
+
+use image::{ImageFormat, ImageResult, Rgb, RgbImage};
+
+use crate::{tiles::Tile, utils::clamp, vec3::Vec3};
+
+/// Sum of the radiance samples of every pixel, with the number of samples taken so
+/// far. Rows are stored from the top of the image. Pixels can have different sample
+/// counts, so a pass interrupted half way still gives a usable image.
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+    pub samples: Vec<u32>,
+    /// Light traced from the lights into each pixel. Every light path can land in any
+    /// pixel, so these sums are averaged over the samples of the whole image rather
+    /// than those of the pixel.
+    pub splats: Vec<Vec3>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Vec3(0., 0., 0.); width * height],
+            samples: vec![0; width * height],
+            splats: vec![Vec3(0., 0., 0.); width * height],
+        }
+    }
+
+    /// Copy of the sums and sample counts inside the tile, row by row.
+    pub fn tile(&self, tile: &Tile) -> (Vec<Vec3>, Vec<u32>) {
+        let mut pixels = Vec::with_capacity(tile.width * tile.height);
+        let mut samples = Vec::with_capacity(tile.width * tile.height);
+        for y in tile.y..tile.y + tile.height {
+            let start = y * self.width + tile.x;
+            pixels.extend_from_slice(&self.pixels[start..start + tile.width]);
+            samples.extend_from_slice(&self.samples[start..start + tile.width]);
+        }
+        (pixels, samples)
+    }
+
+    pub fn set_tile(&mut self, tile: &Tile, pixels: &[Vec3], samples: &[u32]) {
+        for row in 0..tile.height {
+            let start = (tile.y + row) * self.width + tile.x;
+            let tile_start = row * tile.width;
+            self.pixels[start..start + tile.width]
+                .copy_from_slice(&pixels[tile_start..tile_start + tile.width]);
+            self.samples[start..start + tile.width]
+                .copy_from_slice(&samples[tile_start..tile_start + tile.width]);
+        }
+    }
+
+    /// Adds light traced into pixels, given by their index.
+    pub fn add_splats(&mut self, splats: &[(usize, Vec3)]) {
+        for (i, radiance) in splats {
+            self.splats[*i] += *radiance;
+        }
+    }
+
+    pub fn to_image(&self) -> RgbImage {
+        self.region_image(&Tile::image(self.width, self.height))
+    }
+
+    /// Image of just the pixels inside the region.
+    pub fn region_image(&self, region: &Tile) -> RgbImage {
+        //Each sample traces one light path, which estimates the light of all pixels
+        let total_samples: f64 = self.samples.iter().map(|&n| n as f64).sum();
+        let splat_scale = (self.width * self.height) as f64 / f64::max(total_samples, 1.);
+
+        RgbImage::from_fn(region.width as u32, region.height as u32, |x, y| {
+            let i = (region.y + y as usize) * self.width + region.x + x as usize;
+            let sum = self.pixels[i] + self.splats[i] * (splat_scale * self.samples[i] as f64);
+            let c = color_rgb(&sum, self.samples[i]);
+            Rgb([c.0, c.1, c.2])
+        })
+    }
+
+    /// Replaces the region of an image of the same size, e.g. an earlier render of the
+    /// whole frame, with the pixels rendered here.
+    pub fn composite(&self, region: &Tile, image: &mut RgbImage) {
+        let cropped = self.region_image(region);
+        image::imageops::replace(image, &cropped, region.x as i64, region.y as i64);
+    }
+}
+
+/// Writes the image next to `path` first and then moves it into place, so readers
+/// never see a partially written file.
+pub fn save_image(image: &RgbImage, path: &str) -> ImageResult<()> {
+    let format = ImageFormat::from_path(path)?;
+    let path = Path::new(path);
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".partial");
+
+    image.save_with_format(&partial, format)?;
+    fs::rename(&partial, path)?;
+    Ok(())
+}
+
+/// Average of `samples` radiance samples with gamma correction, black without samples.
+pub fn color_rgb(sum: &Vec3, samples: u32) -> (u8, u8, u8) {
+    if samples == 0 {
+        return (0, 0, 0);
+    }
+
+    let scale = 1.0 / samples as f64;
+
+    let r = f64::sqrt(scale * sum.0);
+    let g = f64::sqrt(scale * sum.1);
+    let b = f64::sqrt(scale * sum.2);
+
+    (
+        (256. * clamp(r, 0., 0.999)) as u8,
+        (256. * clamp(g, 0., 0.999)) as u8,
+        (256. * clamp(b, 0., 0.999)) as u8,
+    )
+}
+
+#[cfg(test)]
+mod framebuffer_tests {
+    use super::*;
+
+    #[test]
+    fn test_color_rgb() {
+        assert_eq!(color_rgb(&Vec3(4., 1., 0.), 4), (255, 128, 0));
+        assert_eq!(color_rgb(&Vec3(4., 1., 0.), 0), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_tile_round_trip() {
+        let mut framebuffer = Framebuffer::new(4, 3);
+        let tile = Tile {
+            x: 1,
+            y: 1,
+            width: 3,
+            height: 2,
+        };
+        let (mut pixels, mut samples) = framebuffer.tile(&tile);
+        pixels[4] = Vec3(1., 2., 3.);
+        samples[4] = 5;
+        framebuffer.set_tile(&tile, &pixels, &samples);
+
+        assert_eq!(framebuffer.pixels[2 * 4 + 2], Vec3(1., 2., 3.));
+        assert_eq!(framebuffer.samples.iter().sum::<u32>(), 5);
+    }
+
+    #[test]
+    fn test_region() {
+        let mut framebuffer = Framebuffer::new(4, 3);
+        framebuffer.pixels[4 + 2] = Vec3(1., 1., 1.);
+        framebuffer.samples[4 + 2] = 1;
+        let region = Tile {
+            x: 2,
+            y: 1,
+            width: 2,
+            height: 1,
+        };
+
+        let cropped = framebuffer.region_image(&region);
+        assert_eq!(cropped.dimensions(), (2, 1));
+        assert_eq!(cropped.get_pixel(0, 0), &Rgb([255, 255, 255]));
+
+        let mut base = RgbImage::from_pixel(4, 3, Rgb([9, 9, 9]));
+        framebuffer.composite(&region, &mut base);
+        assert_eq!(base.get_pixel(2, 1), &Rgb([255, 255, 255]));
+        assert_eq!(base.get_pixel(3, 1), &Rgb([0, 0, 0]));
+        assert_eq!(base.get_pixel(1, 1), &Rgb([9, 9, 9]));
+    }
+
+    #[test]
+    fn test_uneven_samples() {
+        let mut framebuffer = Framebuffer::new(2, 1);
+        framebuffer.pixels = vec![Vec3(1., 1., 1.), Vec3(3., 3., 3.)];
+        framebuffer.samples = vec![4, 12];
+
+        let image = framebuffer.to_image();
+        assert_eq!(image.get_pixel(0, 0), image.get_pixel(1, 0));
+    }
+
+    #[test]
+    fn test_splats_average_over_image() {
+        let mut framebuffer = Framebuffer::new(2, 1);
+        framebuffer.samples = vec![2, 6];
+        framebuffer.add_splats(&[(0, Vec3(1., 1., 1.)), (0, Vec3(3., 3., 3.))]);
+
+        //Four light paths per pixel on average
+        let image = framebuffer.to_image();
+        assert_eq!(image.get_pixel(0, 0), &Rgb([255, 255, 255]));
+        assert_eq!(image.get_pixel(1, 0), &Rgb([0, 0, 0]));
+        framebuffer.add_splats(&[(1, Vec3(1., 1., 1.))]);
+        assert_eq!(
+            framebuffer.to_image().get_pixel(1, 0),
+            &Rgb([128, 128, 128])
+        );
+    }
+}
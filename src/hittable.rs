@@ -10,13 +10,28 @@ pub struct Hit {
     pub point: Vec3,
     pub normal: Vec3,
     pub t: f64,
+    pub u: f64,
+    pub v: f64,
     pub front_face: bool,
     pub material: Material,
 }
 
 impl Hit {
+    /// Placeholder record to be filled in by `hit`.
+    pub fn empty() -> Hit {
+        Hit {
+            point: Vec3(0., 0., 0.),
+            normal: Vec3(0., 0., 0.),
+            t: 0.,
+            u: 0.,
+            v: 0.,
+            front_face: true,
+            material: Material::Init,
+        }
+    }
+
     pub fn set_face_normal(&mut self, ray: &Ray, outward_normal: &Vec3) {
-        self.front_face = dot(&ray.dir, &outward_normal) < 0.;
+        self.front_face = dot(&ray.dir, outward_normal) < 0.;
         self.normal = if self.front_face {
             *outward_normal
         } else {
@@ -41,6 +41,6 @@ impl Hittable for Sphere {
         rec.set_face_normal(ray, &outward_normal);
         rec.material = self.material;
 
-        return true;
+        true
     }
 }
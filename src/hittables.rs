@@ -1,13 +1,104 @@
+use std::sync::Arc;
+
 use crate::{
+    aabb::Aabb,
+    csg::{csg_bounding_box, hit_csg, CsgOperation},
     hittable::Hit,
-    materials::{self, Material},
-    vec3::{dot, Vec3},
+    materials::Material,
+    mesh::Mesh,
+    polynomial::{refine_root, solve_quartic},
+    ray::Ray,
+    sdf::{self, hit_sdf, Sdf},
+    stats,
+    utils::{random_double, PI},
+    vec3::{cross, dot, orthonormal_basis, unit_vector, Vec3},
+    voxel::VoxelGrid,
 };
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum Hittables {
     HittableObjects(Vec<Hittables>),
     Sphere(Vec3, f64, Material),
+    /// Infinite plane through a point with the given normal.
+    Plane(Vec3, Vec3, Material),
+    /// Parallelogram spanned by the edges `u` and `v` from the corner `Q`.
+    Quad(Vec3, Vec3, Vec3, Material),
+    /// Axis-aligned box between the `min` and `max` corners.
+    Cuboid(Vec3, Vec3, Material),
+    /// Disk with center, normal and radius.
+    Disk(Vec3, Vec3, f64, Material),
+    /// Capped cylinder from the base center along the axis, whose length is the height.
+    Cylinder(Vec3, Vec3, f64, Material),
+    /// Capped cone from the base center to the apex at `base + axis`, with the base radius.
+    Cone(Vec3, Vec3, f64, Material),
+    /// Torus around the axis through its center, with major and minor radius.
+    Torus(Vec3, Vec3, f64, f64, Material),
+    /// Homogeneous participating medium filling a closed boundary, with its density
+    /// and phase function material.
+    ConstantMedium(Box<Hittables>, f64, Material),
+    /// Heterogeneous participating medium from a voxel grid, with a density scale, the
+    /// phase function material and the color multiplied with the grid's emission channel.
+    VoxelMedium(Arc<VoxelGrid>, f64, Material, Vec3),
+    /// Constructive solid geometry over two closed objects. Surfaces keep their own
+    /// material, except carved surfaces which take the material of the first operand.
+    Csg(CsgOperation, Box<Hittables>, Box<Hittables>),
+    /// Signed distance field, found by sphere tracing.
+    Sdf(Box<Sdf>, Material),
+    /// Triangle with corners counter-clockwise seen from the side of its normal.
+    Triangle(Vec3, Vec3, Vec3, Material),
+    /// Triangle mesh, one material for all its triangles.
+    Mesh(Arc<Mesh>, Material),
+}
+
+/// Axis-aligned rectangle spanning `x0..x1`, `y0..y1` in the plane `z = k`.
+pub fn xy_rect(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, material: Material) -> Hittables {
+    Hittables::Quad(
+        Vec3(x0, y0, k),
+        Vec3(x1 - x0, 0., 0.),
+        Vec3(0., y1 - y0, 0.),
+        material,
+    )
+}
+
+/// Axis-aligned rectangle spanning `x0..x1`, `z0..z1` in the plane `y = k`.
+pub fn xz_rect(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, material: Material) -> Hittables {
+    Hittables::Quad(
+        Vec3(x0, k, z0),
+        Vec3(0., 0., z1 - z0),
+        Vec3(x1 - x0, 0., 0.),
+        material,
+    )
+}
+
+/// Axis-aligned rectangle spanning `y0..y1`, `z0..z1` in the plane `x = k`.
+pub fn yz_rect(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, material: Material) -> Hittables {
+    Hittables::Quad(
+        Vec3(k, y0, z0),
+        Vec3(0., y1 - y0, 0.),
+        Vec3(0., 0., z1 - z0),
+        material,
+    )
+}
+
+/// Position of the primitive type in `stats::PRIMITIVES`, `None` for lists.
+pub fn primitive_index(hittable_object: &Hittables) -> Option<usize> {
+    match hittable_object {
+        Hittables::HittableObjects(_) => None,
+        Hittables::Sphere(..) => Some(0),
+        Hittables::Plane(..) => Some(1),
+        Hittables::Quad(..) => Some(2),
+        Hittables::Cuboid(..) => Some(3),
+        Hittables::Disk(..) => Some(4),
+        Hittables::Cylinder(..) => Some(5),
+        Hittables::Cone(..) => Some(6),
+        Hittables::Torus(..) => Some(7),
+        Hittables::ConstantMedium(..) => Some(8),
+        Hittables::VoxelMedium(..) => Some(9),
+        Hittables::Csg(..) => Some(10),
+        Hittables::Sdf(..) => Some(11),
+        Hittables::Triangle(..) => Some(12),
+        Hittables::Mesh(..) => Some(13),
+    }
 }
 
 pub fn hit(
@@ -17,58 +108,1282 @@ pub fn hit(
     t_max: f64,
     rec: &mut crate::hittable::Hit,
 ) -> bool {
+    if let Some(primitive) = primitive_index(hittable_object) {
+        stats::record(|stats| stats.intersection_tests[primitive] += 1);
+    }
+
     match hittable_object {
         Hittables::HittableObjects(list) => {
-            let mut temp_rec = Hit {
-                point: Vec3(0., 0., 0.),
-                normal: Vec3(0., 0., 0.),
-                t: 0.,
-                front_face: true,
-                material: materials::Material::Init,
-            };
+            let mut temp_rec = Hit::empty();
             let mut hit_anything = false;
             let mut closest_so_far = t_max;
 
             for item in list {
-                if hit(item, &ray, t_min, closest_so_far, &mut temp_rec) {
+                if hit(item, ray, t_min, closest_so_far, &mut temp_rec) {
                     hit_anything = true;
                     closest_so_far = temp_rec.t;
-                    *rec = temp_rec.clone();
+                    *rec = temp_rec;
                 }
             }
 
             hit_anything
         }
         Hittables::Sphere(center, radius, material) => {
-            let oc = ray.origin - *center;
-            let a = ray.dir.length_squared();
-            let half_b = dot(&oc, &ray.dir);
-            let c = oc.length_squared() - radius * radius;
-
-            let discriminant = half_b * half_b - a * c;
-            if discriminant < 0. {
-                return false;
-            }
-
-            let sqrtd = f64::sqrt(discriminant);
+            let (near, far) = match sphere_roots(*center, *radius, ray) {
+                Some(roots) => roots,
+                None => return false,
+            };
 
             //Nearest Root
 
-            let mut root = (-half_b - sqrtd) / a;
+            let mut root = near;
             if root < t_min || t_max < root {
-                root = (-half_b + sqrtd) / a;
+                root = far;
                 if root < t_min || t_max < root {
                     return false;
                 }
             }
 
-            rec.t = root;
-            rec.point = ray.at(rec.t);
-            let outward_normal = (rec.point - *center) / *radius;
-            rec.set_face_normal(ray, &outward_normal);
-            rec.material = *material;
+            set_sphere_hit(*center, *radius, *material, ray, root, rec);
 
             true
         }
+        Hittables::Plane(point, normal, material) => {
+            hit_plane(*point, *normal, *material, ray, t_min, t_max, rec)
+        }
+        Hittables::Quad(q, u, v, material) => {
+            hit_quad(*q, *u, *v, *material, ray, t_min, t_max, rec)
+        }
+        Hittables::Cuboid(min, max, material) => {
+            hit_cuboid(*min, *max, *material, ray, t_min, t_max, rec)
+        }
+        Hittables::Disk(center, normal, radius, material) => {
+            hit_disk(*center, *normal, *radius, *material, ray, t_min, t_max, rec)
+        }
+        Hittables::Cylinder(base, axis, radius, material) => {
+            hit_cylinder(*base, *axis, *radius, *material, ray, t_min, t_max, rec)
+        }
+        Hittables::Cone(base, axis, radius, material) => {
+            hit_cone(*base, *axis, *radius, *material, ray, t_min, t_max, rec)
+        }
+        Hittables::Torus(center, axis, major, minor, material) => {
+            //Solving the quartic is costly, rays missing the bounds skip it
+            bounding_box(hittable_object).is_some_and(|bounds| bounds.hit(ray, t_min, t_max))
+                && hit_torus(
+                    *center, *axis, *major, *minor, *material, ray, t_min, t_max, rec,
+                )
+        }
+        Hittables::ConstantMedium(boundary, density, material) => {
+            hit_constant_medium(boundary, *density, *material, ray, t_min, t_max, rec)
+        }
+        Hittables::VoxelMedium(grid, scale, material, emission) => {
+            hit_voxel_medium(grid, *scale, *material, *emission, ray, t_min, t_max, rec)
+        }
+        Hittables::Csg(operation, a, b) => hit_csg(*operation, a, b, ray, t_min, t_max, rec),
+        Hittables::Sdf(sdf, material) => hit_sdf(sdf, *material, ray, t_min, t_max, rec),
+        Hittables::Triangle(a, b, c, material) => {
+            hit_triangle(*a, *b, *c, *material, ray, t_min, t_max, rec)
+        }
+        Hittables::Mesh(mesh, material) => hit_mesh(mesh, *material, ray, t_min, t_max, rec),
+    }
+}
+
+/// Fraction of the light that gets through along the ray between `t_min` and `t_max`:
+/// none behind a surface, and through voxel media an estimate by ratio tracking, so
+/// shadow rays are dimmed by them rather than cut off at a sampled collision.
+pub fn transmittance(world: &Hittables, ray: &Ray, t_min: f64, t_max: f64) -> f64 {
+    match world {
+        Hittables::HittableObjects(objects) => {
+            let mut transmittance = 1.;
+            for object in objects {
+                transmittance *= self::transmittance(object, ray, t_min, t_max);
+                if transmittance == 0. {
+                    break;
+                }
+            }
+            transmittance
+        }
+        Hittables::VoxelMedium(grid, scale, _, _) => grid
+            .bounds
+            .interval(ray, t_min, t_max)
+            .map_or(1., |(entry, exit)| {
+                grid.transmittance(*scale, ray, entry, exit)
+            }),
+        object => {
+            if hit(object, ray, t_min, t_max, &mut Hit::empty()) {
+                0.
+            } else {
+                1.
+            }
+        }
+    }
+}
+
+/// Both ray parameters where the ray crosses the sphere, nearest first.
+pub fn sphere_roots(center: Vec3, radius: f64, ray: &Ray) -> Option<(f64, f64)> {
+    let oc = ray.origin - center;
+    let a = ray.dir.length_squared();
+    let half_b = dot(&oc, &ray.dir);
+    let c = oc.length_squared() - radius * radius;
+
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0. {
+        return None;
+    }
+
+    let sqrtd = f64::sqrt(discriminant);
+    Some(((-half_b - sqrtd) / a, (-half_b + sqrtd) / a))
+}
+
+pub fn set_sphere_hit(
+    center: Vec3,
+    radius: f64,
+    material: Material,
+    ray: &Ray,
+    t: f64,
+    rec: &mut Hit,
+) {
+    rec.t = t;
+    rec.point = ray.at(t);
+    let outward_normal = (rec.point - center) / radius;
+    rec.set_face_normal(ray, &outward_normal);
+    (rec.u, rec.v) = sphere_uv(&unit_vector(&(rec.point - center)));
+    rec.material = material;
+}
+
+/// Bounding box of the object, `None` for unbounded objects such as planes.
+pub fn bounding_box(hittable_object: &Hittables) -> Option<Aabb> {
+    match hittable_object {
+        Hittables::HittableObjects(list) => {
+            let mut boxes = list.iter().map(bounding_box);
+            let first = boxes.next()??;
+            boxes.try_fold(first, |acc, b| Some(Aabb::surrounding(&acc, &b?)))
+        }
+        Hittables::Sphere(center, radius, _) => {
+            let r = Vec3(radius.abs(), radius.abs(), radius.abs());
+            Some(Aabb::new(*center - r, *center + r))
+        }
+        Hittables::Plane(_, _, _) => None,
+        Hittables::Quad(q, u, v, _) => {
+            let a = Aabb::new(*q, *q + *u + *v);
+            let b = Aabb::new(*q + *u, *q + *v);
+            Some(Aabb::surrounding(&a, &b))
+        }
+        Hittables::Cuboid(min, max, _) => Some(Aabb::new(*min, *max)),
+        Hittables::Disk(center, normal, radius, _) => {
+            let e = disk_extent(&unit_vector(normal), *radius);
+            Some(Aabb::new(*center - e, *center + e))
+        }
+        Hittables::Cylinder(base, axis, radius, _) => {
+            let e = disk_extent(&unit_vector(axis), *radius);
+            let top = *base + *axis;
+            Some(Aabb::surrounding(
+                &Aabb::new(*base - e, *base + e),
+                &Aabb::new(top - e, top + e),
+            ))
+        }
+        Hittables::Cone(base, axis, radius, _) => {
+            let e = disk_extent(&unit_vector(axis), *radius);
+            let apex = *base + *axis;
+            Some(Aabb::surrounding(
+                &Aabb::new(*base - e, *base + e),
+                &Aabb::new(apex, apex),
+            ))
+        }
+        Hittables::Torus(center, axis, major, minor, _) => {
+            let a = unit_vector(axis);
+            let e = disk_extent(&a, major + minor) + *minor * Vec3(a.0.abs(), a.1.abs(), a.2.abs());
+            Some(Aabb::new(*center - e, *center + e))
+        }
+        Hittables::ConstantMedium(boundary, _, _) => bounding_box(boundary),
+        Hittables::VoxelMedium(grid, _, _, _) => Some(grid.bounds),
+        Hittables::Csg(operation, a, b) => csg_bounding_box(*operation, a, b),
+        Hittables::Sdf(sdf, _) => sdf::bounds(sdf),
+        Hittables::Triangle(a, b, c, _) => {
+            Some(Aabb::surrounding(&Aabb::new(*a, *b), &Aabb::new(*c, *c)))
+        }
+        Hittables::Mesh(mesh, _) => Some(mesh.bounds),
+    }
+}
+
+/// Half extent along each world axis of a disk with unit normal `n`.
+fn disk_extent(n: &Vec3, radius: f64) -> Vec3 {
+    let extent = |n: f64| radius * f64::sqrt(f64::max(0., 1. - n * n));
+    Vec3(extent(n.0), extent(n.1), extent(n.2))
+}
+
+/// Spherical coordinates of a point on the unit sphere, mapped to `[0, 1]`.
+pub fn sphere_uv(p: &Vec3) -> (f64, f64) {
+    let theta = f64::acos(-p.1);
+    let phi = f64::atan2(-p.2, p.0) + PI;
+    (phi / (2. * PI), theta / PI)
+}
+
+/// Angle of `p` around the axis spanned by the basis `t`, `b`, mapped to `[0, 1]`.
+fn azimuth(p: &Vec3, t: &Vec3, b: &Vec3) -> f64 {
+    (f64::atan2(dot(p, b), dot(p, t)) + PI) / (2. * PI)
+}
+
+fn hit_plane(
+    point: Vec3,
+    normal: Vec3,
+    material: Material,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rec: &mut Hit,
+) -> bool {
+    let n = unit_vector(&normal);
+    let denom = dot(&n, &ray.dir);
+    if f64::abs(denom) < 1e-8 {
+        return false;
+    }
+
+    let t = dot(&(point - ray.origin), &n) / denom;
+    if t < t_min || t_max < t {
+        return false;
+    }
+
+    rec.t = t;
+    rec.point = ray.at(t);
+    rec.set_face_normal(ray, &n);
+    let (tangent, bitangent) = orthonormal_basis(&n);
+    rec.u = dot(&(rec.point - point), &tangent);
+    rec.v = dot(&(rec.point - point), &bitangent);
+    rec.material = material;
+
+    true
+}
+
+#[allow(clippy::too_many_arguments)]
+fn hit_quad(
+    q: Vec3,
+    u: Vec3,
+    v: Vec3,
+    material: Material,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rec: &mut Hit,
+) -> bool {
+    let n = cross(&u, &v);
+    let normal = unit_vector(&n);
+    let denom = dot(&normal, &ray.dir);
+    if f64::abs(denom) < 1e-8 {
+        return false;
+    }
+
+    let t = dot(&(q - ray.origin), &normal) / denom;
+    if t < t_min || t_max < t {
+        return false;
+    }
+
+    //Planar coordinates of the hit point relative to the edges
+    let point = ray.at(t);
+    let w = n / n.length_squared();
+    let p = point - q;
+    let alpha = dot(&w, &cross(&p, &v));
+    let beta = dot(&w, &cross(&u, &p));
+    if !(0. ..=1.).contains(&alpha) || !(0. ..=1.).contains(&beta) {
+        return false;
+    }
+
+    rec.t = t;
+    rec.point = point;
+    rec.set_face_normal(ray, &normal);
+    rec.u = alpha;
+    rec.v = beta;
+    rec.material = material;
+
+    true
+}
+
+/// Möller-Trumbore intersection, with the barycentric coordinates of `b` and `c` as
+/// texture coordinates.
+#[allow(clippy::too_many_arguments)]
+fn hit_triangle(
+    a: Vec3,
+    b: Vec3,
+    c: Vec3,
+    material: Material,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rec: &mut Hit,
+) -> bool {
+    let (edge1, edge2) = (b - a, c - a);
+    let p = cross(&ray.dir, &edge2);
+    let det = dot(&edge1, &p);
+    if f64::abs(det) < 1e-12 {
+        return false;
+    }
+
+    let inv_det = 1. / det;
+    let s = ray.origin - a;
+    let u = dot(&s, &p) * inv_det;
+    if !(0. ..=1.).contains(&u) {
+        return false;
+    }
+    let q = cross(&s, &edge1);
+    let v = dot(&ray.dir, &q) * inv_det;
+    if v < 0. || u + v > 1. {
+        return false;
+    }
+
+    let t = dot(&edge2, &q) * inv_det;
+    if t < t_min || t_max < t {
+        return false;
+    }
+
+    rec.t = t;
+    rec.point = ray.at(t);
+    rec.set_face_normal(ray, &unit_vector(&cross(&edge1, &edge2)));
+    rec.u = u;
+    rec.v = v;
+    rec.material = material;
+
+    true
+}
+
+fn hit_mesh(
+    mesh: &Mesh,
+    material: Material,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rec: &mut Hit,
+) -> bool {
+    if !mesh.bounds.hit(ray, t_min, t_max) {
+        return false;
+    }
+
+    let mut hit_anything = false;
+    let mut closest_so_far = t_max;
+    for i in 0..mesh.triangles.len() {
+        let (a, b, c) = mesh.triangle(i);
+        if hit_triangle(a, b, c, material, ray, t_min, closest_so_far, rec) {
+            hit_anything = true;
+            closest_so_far = rec.t;
+        }
+    }
+    hit_anything
+}
+
+fn hit_cuboid(
+    min: Vec3,
+    max: Vec3,
+    material: Material,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rec: &mut Hit,
+) -> bool {
+    let mut t_near = f64::NEG_INFINITY;
+    let mut t_far = f64::INFINITY;
+    let mut near_axis = 0;
+    let mut far_axis = 0;
+
+    for axis in 0..3 {
+        let inv_d = 1. / ray.dir[axis];
+        let mut t0 = (min[axis] - ray.origin[axis]) * inv_d;
+        let mut t1 = (max[axis] - ray.origin[axis]) * inv_d;
+        if inv_d < 0. {
+            std::mem::swap(&mut t0, &mut t1);
+        }
+        if t0 > t_near {
+            t_near = t0;
+            near_axis = axis;
+        }
+        if t1 < t_far {
+            t_far = t1;
+            far_axis = axis;
+        }
+    }
+
+    if t_near > t_far {
+        return false;
+    }
+
+    let (t, axis) = if t_min <= t_near && t_near <= t_max {
+        (t_near, near_axis)
+    } else if t_min <= t_far && t_far <= t_max {
+        (t_far, far_axis)
+    } else {
+        return false;
+    };
+
+    let point = ray.at(t);
+    let sign = if f64::abs(point[axis] - max[axis]) < f64::abs(point[axis] - min[axis]) {
+        1.
+    } else {
+        -1.
+    };
+    let mut outward_normal = Vec3(0., 0., 0.);
+    match axis {
+        0 => outward_normal.0 = sign,
+        1 => outward_normal.1 = sign,
+        _ => outward_normal.2 = sign,
+    }
+
+    let (a, b) = ((axis + 1) % 3, (axis + 2) % 3);
+    rec.t = t;
+    rec.point = point;
+    rec.set_face_normal(ray, &outward_normal);
+    rec.u = (point[a] - min[a]) / (max[a] - min[a]);
+    rec.v = (point[b] - min[b]) / (max[b] - min[b]);
+    rec.material = material;
+
+    true
+}
+
+#[allow(clippy::too_many_arguments)]
+fn hit_disk(
+    center: Vec3,
+    normal: Vec3,
+    radius: f64,
+    material: Material,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rec: &mut Hit,
+) -> bool {
+    let n = unit_vector(&normal);
+    let denom = dot(&n, &ray.dir);
+    if f64::abs(denom) < 1e-8 {
+        return false;
+    }
+
+    let t = dot(&(center - ray.origin), &n) / denom;
+    if t < t_min || t_max < t {
+        return false;
+    }
+
+    let point = ray.at(t);
+    let offset = point - center;
+    if offset.length_squared() > radius * radius {
+        return false;
+    }
+
+    let (tangent, bitangent) = orthonormal_basis(&n);
+    rec.t = t;
+    rec.point = point;
+    rec.set_face_normal(ray, &n);
+    rec.u = azimuth(&offset, &tangent, &bitangent);
+    rec.v = offset.length() / radius;
+    rec.material = material;
+
+    true
+}
+
+#[allow(clippy::too_many_arguments)]
+fn hit_cylinder(
+    base: Vec3,
+    axis: Vec3,
+    radius: f64,
+    material: Material,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rec: &mut Hit,
+) -> bool {
+    let height = axis.length();
+    let a = axis / height;
+    let mut hit_anything = false;
+    let mut closest_so_far = t_max;
+
+    //Infinite cylinder, solved in the plane perpendicular to the axis
+    let oc = ray.origin - base;
+    let d_perp = ray.dir - dot(&ray.dir, &a) * a;
+    let oc_perp = oc - dot(&oc, &a) * a;
+    let qa = d_perp.length_squared();
+    let half_b = dot(&oc_perp, &d_perp);
+    let c = oc_perp.length_squared() - radius * radius;
+    let discriminant = half_b * half_b - qa * c;
+
+    if qa > 1e-12 && discriminant >= 0. {
+        let sqrtd = f64::sqrt(discriminant);
+        for root in [(-half_b - sqrtd) / qa, (-half_b + sqrtd) / qa] {
+            if root < t_min || closest_so_far < root {
+                continue;
+            }
+            let point = ray.at(root);
+            let h = dot(&(point - base), &a);
+            if h < 0. || height < h {
+                continue;
+            }
+
+            let radial = point - base - h * a;
+            let (tangent, bitangent) = orthonormal_basis(&a);
+            rec.t = root;
+            rec.point = point;
+            rec.set_face_normal(ray, &(radial / radius));
+            rec.u = azimuth(&radial, &tangent, &bitangent);
+            rec.v = h / height;
+            rec.material = material;
+            closest_so_far = root;
+            hit_anything = true;
+            break;
+        }
+    }
+
+    if hit_disk(base, -a, radius, material, ray, t_min, closest_so_far, rec) {
+        closest_so_far = rec.t;
+        hit_anything = true;
+    }
+    if hit_disk(
+        base + axis,
+        a,
+        radius,
+        material,
+        ray,
+        t_min,
+        closest_so_far,
+        rec,
+    ) {
+        hit_anything = true;
+    }
+
+    hit_anything
+}
+
+#[allow(clippy::too_many_arguments)]
+fn hit_cone(
+    base: Vec3,
+    axis: Vec3,
+    radius: f64,
+    material: Material,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rec: &mut Hit,
+) -> bool {
+    let height = axis.length();
+    let apex = base + axis;
+    //Unit vector from the apex towards the base
+    let v = -axis / height;
+    let cos2 = height * height / (height * height + radius * radius);
+    let mut hit_anything = false;
+    let mut closest_so_far = t_max;
+
+    let co = ray.origin - apex;
+    let d_v = dot(&ray.dir, &v);
+    let co_v = dot(&co, &v);
+    let qa = d_v * d_v - cos2 * ray.dir.length_squared();
+    let half_b = d_v * co_v - cos2 * dot(&ray.dir, &co);
+    let c = co_v * co_v - cos2 * co.length_squared();
+
+    let mut roots = Vec::new();
+    if f64::abs(qa) > 1e-12 {
+        let discriminant = half_b * half_b - qa * c;
+        if discriminant >= 0. {
+            let sqrtd = f64::sqrt(discriminant);
+            roots.push((-half_b - sqrtd) / qa);
+            roots.push((-half_b + sqrtd) / qa);
+        }
+    } else if f64::abs(half_b) > 1e-12 {
+        roots.push(-c / (2. * half_b));
+    }
+    roots.sort_by(f64::total_cmp);
+
+    for root in roots {
+        if root < t_min || closest_so_far < root {
+            continue;
+        }
+        let point = ray.at(root);
+        let cp = point - apex;
+        //Reject the mirrored nappe and points beyond the base
+        let h = dot(&cp, &v);
+        if h < 0. || height < h {
+            continue;
+        }
+
+        let outward_normal = unit_vector(&(cp * (h / cp.length_squared()) - v));
+        let (tangent, bitangent) = orthonormal_basis(&v);
+        rec.t = root;
+        rec.point = point;
+        rec.set_face_normal(ray, &outward_normal);
+        rec.u = azimuth(&cp, &tangent, &bitangent);
+        rec.v = h / height;
+        rec.material = material;
+        closest_so_far = root;
+        hit_anything = true;
+        break;
+    }
+
+    if hit_disk(base, v, radius, material, ray, t_min, closest_so_far, rec) {
+        hit_anything = true;
+    }
+
+    hit_anything
+}
+
+#[allow(clippy::too_many_arguments)]
+fn hit_torus(
+    center: Vec3,
+    axis: Vec3,
+    major: f64,
+    minor: f64,
+    material: Material,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rec: &mut Hit,
+) -> bool {
+    //Work in a local frame with the axis along y and a unit direction
+    let a = unit_vector(&axis);
+    let (tangent, bitangent) = orthonormal_basis(&a);
+    let to_local = |v: &Vec3| Vec3(dot(v, &tangent), dot(v, &a), dot(v, &bitangent));
+
+    let dir_length = ray.dir.length();
+    let o = to_local(&(ray.origin - center));
+    let d = to_local(&ray.dir) / dir_length;
+
+    let r2 = major * major;
+    let k = 2. * dot(&o, &d);
+    let l = o.length_squared() + r2 - minor * minor;
+    let g = 4. * r2 * (d.0 * d.0 + d.2 * d.2);
+    let h = 8. * r2 * (o.0 * d.0 + o.2 * d.2);
+    let i = 4. * r2 * (o.0 * o.0 + o.2 * o.2);
+
+    //(s² + ks + l)² = gs² + hs + i
+    let coefficients = [l * l - i, 2. * k * l - h, 2. * l + k * k - g, 2. * k, 1.];
+    let closest = solve_quartic(coefficients)
+        .into_iter()
+        .map(|s| refine_root(&coefficients, s) / dir_length)
+        .filter(|t| t_min <= *t && *t <= t_max)
+        .min_by(f64::total_cmp);
+
+    let t = match closest {
+        Some(t) => t,
+        None => return false,
+    };
+
+    let p = o + (t * dir_length) * d;
+    let ring = major * unit_vector(&Vec3(p.0, 0., p.2));
+    let local_normal = (p - ring) / minor;
+    let outward_normal = local_normal.0 * tangent + local_normal.1 * a + local_normal.2 * bitangent;
+
+    rec.t = t;
+    rec.point = ray.at(t);
+    rec.set_face_normal(ray, &outward_normal);
+    rec.u = (f64::atan2(p.2, p.0) + PI) / (2. * PI);
+    rec.v = (f64::atan2(p.1, Vec3(p.0, 0., p.2).length() - major) + PI) / (2. * PI);
+    rec.material = material;
+
+    true
+}
+
+/// Entry and exit of the ray through the closed boundary, clamped to `[t_min, t_max]`.
+fn boundary_interval(
+    boundary: &Hittables,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64)> {
+    let mut rec = Hit::empty();
+
+    if !hit(boundary, ray, f64::NEG_INFINITY, f64::INFINITY, &mut rec) {
+        return None;
+    }
+    let entry = rec.t;
+    if !hit(boundary, ray, entry + 0.0001, f64::INFINITY, &mut rec) {
+        return None;
+    }
+    let exit = rec.t;
+
+    let entry = f64::max(entry, t_min);
+    let exit = f64::min(exit, t_max);
+    if entry >= exit {
+        return None;
+    }
+    Some((entry, exit))
+}
+
+fn hit_constant_medium(
+    boundary: &Hittables,
+    density: f64,
+    material: Material,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rec: &mut Hit,
+) -> bool {
+    let (entry, exit) = match boundary_interval(boundary, ray, t_min, t_max) {
+        Some(interval) => interval,
+        None => return false,
+    };
+
+    //Free-flight sampling: the probability of passing through is the transmittance
+    let ray_length = ray.dir.length();
+    let distance_inside = (exit - entry) * ray_length;
+    let hit_distance = -f64::ln(1. - random_double()) / density;
+    if hit_distance > distance_inside {
+        return false;
+    }
+
+    rec.t = entry + hit_distance / ray_length;
+    rec.point = ray.at(rec.t);
+    //Normal and face are meaningless inside a volume
+    rec.normal = Vec3(1., 0., 0.);
+    rec.front_face = true;
+    rec.u = 0.;
+    rec.v = 0.;
+    rec.material = material;
+
+    true
+}
+
+#[allow(clippy::too_many_arguments)]
+fn hit_voxel_medium(
+    grid: &VoxelGrid,
+    scale: f64,
+    material: Material,
+    emission: Vec3,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rec: &mut Hit,
+) -> bool {
+    let t = match grid
+        .bounds
+        .interval(ray, t_min, t_max)
+        .and_then(|(entry, exit)| grid.sample_collision(scale, ray, entry, exit))
+    {
+        Some(t) => t,
+        None => return false,
+    };
+
+    rec.t = t;
+    rec.point = ray.at(t);
+    rec.normal = Vec3(1., 0., 0.);
+    rec.front_face = true;
+    rec.u = 0.;
+    rec.v = 0.;
+    rec.material = material;
+
+    //Only the absorbed fraction (1 - albedo) of a collision emits
+    let emitted = grid.emission(&rec.point) * emission;
+    if !emitted.close_to_zero() {
+        if let Material::Isotropic(r, g, b) | Material::HenyeyGreenstein(r, g, b, _) = material {
+            rec.material = Material::EmissiveMedium(
+                r,
+                g,
+                b,
+                (1. - r) * emitted.0,
+                (1. - g) * emitted.1,
+                (1. - b) * emitted.2,
+            );
+        }
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod hittables_tests {
+    use super::*;
+    use crate::materials::Material::Lambertian;
+
+    fn empty_hit() -> Hit {
+        Hit::empty()
+    }
+
+    fn material() -> Material {
+        Lambertian(0.5, 0.5, 0.5)
+    }
+
+    fn ray(origin: Vec3, dir: Vec3) -> Ray {
+        Ray { origin, dir }
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!(f64::abs(a - b) < 1e-6, "{} != {}", a, b);
+    }
+
+    fn assert_close_vec(a: Vec3, b: Vec3) {
+        assert!((a - b).length() < 1e-6, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_sphere() {
+        let sphere = Hittables::Sphere(Vec3(0., 0., 0.), 1., material());
+        let mut rec = empty_hit();
+        assert!(hit(
+            &sphere,
+            &ray(Vec3(0., 0., 5.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 4.);
+        assert_close_vec(rec.normal, Vec3(0., 0., 1.));
+        assert!(rec.front_face);
+        assert!(!hit(
+            &sphere,
+            &ray(Vec3(0., 2., 5.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+    }
+
+    #[test]
+    fn test_plane() {
+        let plane = Hittables::Plane(Vec3(0., -1., 0.), Vec3(0., 1., 0.), material());
+        let mut rec = empty_hit();
+        assert!(hit(
+            &plane,
+            &ray(Vec3(0., 1., 0.), Vec3(0., -1., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 2.);
+        assert_close_vec(rec.normal, Vec3(0., 1., 0.));
+        assert!(!hit(
+            &plane,
+            &ray(Vec3(0., 1., 0.), Vec3(1., 0., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert!(!hit(
+            &plane,
+            &ray(Vec3(0., 1., 0.), Vec3(0., 1., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+    }
+
+    #[test]
+    fn test_quad() {
+        let quad = xy_rect(-1., 1., -1., 1., -2., material());
+        let mut rec = empty_hit();
+        assert!(hit(
+            &quad,
+            &ray(Vec3(0.5, 0.5, 0.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 2.);
+        assert_close(rec.u, 0.75);
+        assert_close(rec.v, 0.75);
+        assert_close_vec(rec.normal, Vec3(0., 0., 1.));
+        assert!(!hit(
+            &quad,
+            &ray(Vec3(1.5, 0., 0.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+    }
+
+    #[test]
+    fn test_triangle() {
+        let triangle = Hittables::Triangle(
+            Vec3(0., 0., -2.),
+            Vec3(1., 0., -2.),
+            Vec3(0., 1., -2.),
+            material(),
+        );
+        let mut rec = empty_hit();
+        assert!(hit(
+            &triangle,
+            &ray(Vec3(0.25, 0.5, 0.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 2.);
+        assert_close(rec.u, 0.25);
+        assert_close(rec.v, 0.5);
+        assert_close_vec(rec.normal, Vec3(0., 0., 1.));
+        assert!(rec.front_face);
+        assert!(!hit(
+            &triangle,
+            &ray(Vec3(0.75, 0.75, 0.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+    }
+
+    #[test]
+    fn test_mesh() {
+        //Tetrahedron, hit on the nearest of the two faces along the ray
+        let mesh = Mesh::new(
+            vec![
+                Vec3(0., 0., 0.),
+                Vec3(1., 0., 0.),
+                Vec3(0., 1., 0.),
+                Vec3(0., 0., 1.),
+            ],
+            vec![[0, 2, 1], [0, 1, 3], [0, 3, 2], [1, 2, 3]],
+        );
+        let tetrahedron = Hittables::Mesh(Arc::new(mesh), material());
+        let mut rec = empty_hit();
+        assert!(hit(
+            &tetrahedron,
+            &ray(Vec3(0.2, 0.2, 5.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 4.4);
+        assert_close_vec(rec.normal, unit_vector(&Vec3(1., 1., 1.)));
+        assert!(rec.front_face);
+        assert!(bounding_box(&tetrahedron).is_some());
+        assert!(!hit(
+            &tetrahedron,
+            &ray(Vec3(0.8, 0.8, 5.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+    }
+
+    #[test]
+    fn test_cuboid() {
+        let cuboid = Hittables::Cuboid(Vec3(-1., -1., -1.), Vec3(1., 1., 1.), material());
+        let mut rec = empty_hit();
+        assert!(hit(
+            &cuboid,
+            &ray(Vec3(5., 0., 0.), Vec3(-1., 0., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 4.);
+        assert_close_vec(rec.normal, Vec3(1., 0., 0.));
+        assert!(rec.front_face);
+
+        //From inside, the exit face is hit with the normal flipped towards the ray
+        assert!(hit(
+            &cuboid,
+            &ray(Vec3(0., 0., 0.), Vec3(0., 1., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 1.);
+        assert_close_vec(rec.normal, Vec3(0., -1., 0.));
+        assert!(!rec.front_face);
+
+        assert!(!hit(
+            &cuboid,
+            &ray(Vec3(5., 2., 0.), Vec3(-1., 0., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+    }
+
+    #[test]
+    fn test_disk() {
+        let disk = Hittables::Disk(Vec3(0., 0., 0.), Vec3(0., 1., 0.), 1., material());
+        let mut rec = empty_hit();
+        assert!(hit(
+            &disk,
+            &ray(Vec3(0.5, 1., 0.), Vec3(0., -1., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 1.);
+        assert_close(rec.v, 0.5);
+        assert!(!hit(
+            &disk,
+            &ray(Vec3(1.5, 1., 0.), Vec3(0., -1., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+    }
+
+    #[test]
+    fn test_cylinder() {
+        let cylinder = Hittables::Cylinder(Vec3(0., 0., 0.), Vec3(0., 2., 0.), 1., material());
+        let mut rec = empty_hit();
+
+        //Side
+        assert!(hit(
+            &cylinder,
+            &ray(Vec3(5., 1., 0.), Vec3(-1., 0., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 4.);
+        assert_close_vec(rec.normal, Vec3(1., 0., 0.));
+        assert_close(rec.v, 0.5);
+
+        //Top cap
+        assert!(hit(
+            &cylinder,
+            &ray(Vec3(0.5, 5., 0.), Vec3(0., -1., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 3.);
+        assert_close_vec(rec.normal, Vec3(0., 1., 0.));
+
+        //Bottom cap from below
+        assert!(hit(
+            &cylinder,
+            &ray(Vec3(0., -1., 0.), Vec3(0., 1., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 1.);
+        assert_close_vec(rec.normal, Vec3(0., -1., 0.));
+
+        //Above the top
+        assert!(!hit(
+            &cylinder,
+            &ray(Vec3(5., 3., 0.), Vec3(-1., 0., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+    }
+
+    #[test]
+    fn test_cone() {
+        let cone = Hittables::Cone(Vec3(0., 0., 0.), Vec3(0., 1., 0.), 1., material());
+        let mut rec = empty_hit();
+
+        //Halfway up the side the radius is 0.5
+        assert!(hit(
+            &cone,
+            &ray(Vec3(5., 0.5, 0.), Vec3(-1., 0., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 4.5);
+        assert_close_vec(rec.normal, unit_vector(&Vec3(1., 1., 0.)));
+
+        //Base cap
+        assert!(hit(
+            &cone,
+            &ray(Vec3(0.5, -1., 0.), Vec3(0., 1., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 1.);
+        assert_close_vec(rec.normal, Vec3(0., -1., 0.));
+
+        //Mirrored nappe above the apex is not part of the cone
+        assert!(!hit(
+            &cone,
+            &ray(Vec3(5., 1.5, 0.), Vec3(-1., 0., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+    }
+
+    #[test]
+    fn test_torus() {
+        let torus = Hittables::Torus(Vec3(0., 0., 0.), Vec3(0., 1., 0.), 1., 0.25, material());
+        let mut rec = empty_hit();
+
+        assert!(hit(
+            &torus,
+            &ray(Vec3(-5., 0., 0.), Vec3(2., 0., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, (5. - 1.25) / 2.);
+        assert_close_vec(rec.normal, Vec3(-1., 0., 0.));
+
+        //From above onto the tube
+        assert!(hit(
+            &torus,
+            &ray(Vec3(0., 5., 1.), Vec3(0., -1., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 4.75);
+        assert_close_vec(rec.normal, Vec3(0., 1., 0.));
+
+        //Through the hole
+        assert!(!hit(
+            &torus,
+            &ray(Vec3(0., 5., 0.), Vec3(0., -1., 0.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+    }
+
+    #[test]
+    fn test_constant_medium() {
+        let boundary = Box::new(Hittables::Sphere(Vec3(0., 0., 0.), 1., material()));
+        let mut rec = empty_hit();
+
+        //A very dense medium scatters right at the boundary
+        let dense =
+            Hittables::ConstantMedium(boundary.clone(), 1e9, Material::Isotropic(1., 1., 1.));
+        assert!(hit(
+            &dense,
+            &ray(Vec3(0., 0., 5.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 4.);
+
+        //Starting inside, scattering happens ahead of the origin
+        assert!(hit(
+            &dense,
+            &ray(Vec3(0., 0., 0.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert!(rec.t < 0.01);
+
+        //Transmittance across the diameter of a medium with density 1 is exp(-2)
+        let thin = Hittables::ConstantMedium(boundary, 1., Material::Isotropic(1., 1., 1.));
+        let samples = 100_000;
+        let passed = (0..samples)
+            .filter(|_| {
+                !hit(
+                    &thin,
+                    &ray(Vec3(0., 0., 5.), Vec3(0., 0., -1.)),
+                    0.001,
+                    f64::INFINITY,
+                    &mut rec,
+                )
+            })
+            .count();
+        assert!(f64::abs(passed as f64 / samples as f64 - f64::exp(-2.)) < 0.01);
+    }
+
+    #[test]
+    fn test_voxel_medium() {
+        let bounds = Aabb {
+            min: Vec3(-1., -1., -1.),
+            max: Vec3(1., 1., 1.),
+        };
+        let grid = Arc::new(VoxelGrid::new(
+            (2, 2, 2),
+            bounds,
+            vec![1.; 8],
+            Some(vec![1.; 8]),
+        ));
+        let mut rec = empty_hit();
+
+        let fire = Hittables::VoxelMedium(
+            grid.clone(),
+            1e9,
+            Material::Isotropic(0.5, 0.5, 0.5),
+            Vec3(4., 2., 0.),
+        );
+        assert!(hit(
+            &fire,
+            &ray(Vec3(0., 0., 5.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 4.);
+        assert!(matches!(rec.material, Material::EmissiveMedium(_, _, _, e, _, _) if e == 2.));
+
+        let empty = Hittables::VoxelMedium(
+            grid,
+            0.,
+            Material::Isotropic(0.5, 0.5, 0.5),
+            Vec3(0., 0., 0.),
+        );
+        assert!(!hit(
+            &empty,
+            &ray(Vec3(0., 0., 5.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+    }
+
+    #[test]
+    fn test_voxel_medium_transmittance() {
+        //Density 0.5 and 1.5 across x, a unit in the middle over a length of 2, in
+        //front of a wall
+        let grid = Arc::new(VoxelGrid::new(
+            (2, 2, 2),
+            Aabb {
+                min: Vec3(-1., -1., -1.),
+                max: Vec3(1., 1., 1.),
+            },
+            vec![0.5, 1.5, 0.5, 1.5, 0.5, 1.5, 0.5, 1.5],
+            None,
+        ));
+        let smoke = Hittables::VoxelMedium(
+            grid,
+            1.,
+            Material::Isotropic(0.5, 0.5, 0.5),
+            Vec3(0., 0., 0.),
+        );
+        let world = Hittables::HittableObjects(vec![
+            smoke,
+            Hittables::Sphere(Vec3(0., 0., -10.), 1., material()),
+        ]);
+        let towards = ray(Vec3(0., 0., 5.), Vec3(0., 0., -1.));
+
+        crate::rand::seed(2, &[]);
+        let n = 10_000;
+        let estimates: Vec<f64> = (0..n)
+            .map(|_| transmittance(&world, &towards, 0.001, 8.))
+            .collect();
+        let mean = estimates.iter().sum::<f64>() / n as f64;
+        assert!(f64::abs(mean - f64::exp(-2.)) < 0.01, "{}", mean);
+        //Dimmed in steps rather than all or nothing
+        assert!(estimates.iter().any(|&t| t > 0. && t < 1.));
+
+        assert_eq!(transmittance(&world, &towards, 0.001, 15.), 0.);
+    }
+
+    #[test]
+    fn test_objects_closest() {
+        let world = Hittables::HittableObjects(vec![
+            Hittables::Sphere(Vec3(0., 0., -4.), 1., material()),
+            Hittables::Sphere(Vec3(0., 0., -2.), 0.5, material()),
+        ]);
+        let mut rec = empty_hit();
+        assert!(hit(
+            &world,
+            &ray(Vec3(0., 0., 0.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 1.5);
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        let cylinder = Hittables::Cylinder(Vec3(0., 0., 0.), Vec3(0., 2., 0.), 1., material());
+        let b = bounding_box(&cylinder).unwrap();
+        //The flat caps are padded slightly
+        assert!((b.min - Vec3(-1., 0., -1.)).length() < 1e-3);
+        assert!((b.max - Vec3(1., 2., 1.)).length() < 1e-3);
+
+        let torus = Hittables::Torus(Vec3(0., 0., 0.), Vec3(0., 1., 0.), 1., 0.25, material());
+        let b = bounding_box(&torus).unwrap();
+        assert_close_vec(b.max, Vec3(1.25, 0.25, 1.25));
+
+        let world = Hittables::HittableObjects(vec![
+            cylinder,
+            Hittables::Plane(Vec3(0., 0., 0.), Vec3(0., 1., 0.), material()),
+        ]);
+        assert!(bounding_box(&world).is_none());
     }
 }
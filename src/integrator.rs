@@ -0,0 +1,895 @@
+//! Rendering algorithms, each estimating the radiance arriving at the camera along a
+//! ray. The path tracers converge to the same image, the others trade accuracy for
+//! speed or show properties of the surfaces for debugging.
+
+use std::fmt;
+
+use crate::{
+    bidirectional::BidirectionalPathTracer,
+    hittable::Hit,
+    hittables::{hit, transmittance, Hittables},
+    materials::{
+        albedo, color_emitted, is_light, refract, refractance, scatter, scattering, Material,
+    },
+    medium::{sample_henyey_greenstein, Interior},
+    metropolis::MetropolisPathTracer,
+    photons::PhotonMappingPathTracer,
+    progress,
+    ray::Ray,
+    render::{Scene, Settings},
+    spectrum::{disperse, reflectance},
+    stats,
+    utils::{random_double, random_unit_vector},
+    vec3::{dot, reflect, unit_vector, Vec3},
+};
+
+pub trait Integrator {
+    /// Radiance arriving at the camera along the ray. Integrators tracing light from
+    /// the lights to the camera add what they find to `splats`, with the index of the
+    /// pixel it lands in, see `Framebuffer::splats`.
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        settings: &Settings,
+        splats: &mut Vec<(usize, Vec3)>,
+    ) -> Vec3;
+}
+
+/// Photons traced for photon mapping unless given.
+pub const DEFAULT_PHOTONS: u32 = 500_000;
+/// Mutation settings of Metropolis light transport unless given.
+pub const DEFAULT_LARGE_STEP_PROBABILITY: f64 = 0.3;
+pub const DEFAULT_SIGMA: f64 = 0.01;
+
+/// The integrators to choose from, as stored in the settings of a render.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IntegratorKind {
+    /// Path tracing that finds light only by following the scattered rays, which
+    /// leaves out the punctual lights.
+    Path,
+    /// Path tracing that also samples a light at every bounce, weighting both ways of
+    /// finding it by multiple importance sampling.
+    LightSampling,
+    /// Paths traced from the camera and from the lights, connected in every possible
+    /// way and weighted by multiple importance sampling.
+    Bidirectional,
+    /// Path tracing with light sampling that takes caustics from a photon map, built
+    /// from the given number of photons traced from the lights.
+    PhotonMapping(u32),
+    /// Primary sample space Metropolis light transport over the paths of the path
+    /// tracer with light sampling.
+    Metropolis {
+        /// Chance of a mutation drawing a new path rather than changing the current one.
+        large_step_probability: f64,
+        /// Size of the small changes, as the standard deviation of the random numbers.
+        sigma: f64,
+    },
+    /// Fraction of the hemisphere above the surfaces left open within the distance.
+    AmbientOcclusion(f64),
+    /// Mirror reflection and refraction with direct lighting only.
+    Whitted,
+    /// A property of the surfaces the camera sees.
+    Debug(DebugChannel),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DebugChannel {
+    /// Outward normals mapped from `[-1, 1]` to `[0, 1]`.
+    Normals,
+    /// Distance from the camera, shaded `1 / (1 + distance)`.
+    Depth,
+    /// Texture coordinates as red and green, repeating outside `[0, 1]`.
+    Uv,
+    /// Base color of the material.
+    Albedo,
+}
+
+impl IntegratorKind {
+    /// Parses the name used on the command line, `ao:<distance>` for ambient occlusion
+    /// limited to a distance, `photons:<count>` for photon mapping with a number of
+    /// photons and `mlt:<large step probability>[:<sigma>]` for Metropolis light
+    /// transport with other mutations.
+    pub fn parse(name: &str) -> Option<IntegratorKind> {
+        match name {
+            "path" => Some(IntegratorKind::Path),
+            "nee" => Some(IntegratorKind::LightSampling),
+            "bdpt" => Some(IntegratorKind::Bidirectional),
+            "photons" => Some(IntegratorKind::PhotonMapping(DEFAULT_PHOTONS)),
+            "mlt" => Some(IntegratorKind::Metropolis {
+                large_step_probability: DEFAULT_LARGE_STEP_PROBABILITY,
+                sigma: DEFAULT_SIGMA,
+            }),
+            "ao" => Some(IntegratorKind::AmbientOcclusion(f64::INFINITY)),
+            "whitted" => Some(IntegratorKind::Whitted),
+            "normals" => Some(IntegratorKind::Debug(DebugChannel::Normals)),
+            "depth" => Some(IntegratorKind::Debug(DebugChannel::Depth)),
+            "uv" => Some(IntegratorKind::Debug(DebugChannel::Uv)),
+            "albedo" => Some(IntegratorKind::Debug(DebugChannel::Albedo)),
+            _ => {
+                if let Some(count) = name.strip_prefix("photons:") {
+                    let count = count.parse::<u32>().ok()?;
+                    return (count > 0).then_some(IntegratorKind::PhotonMapping(count));
+                }
+                if let Some(mutations) = name.strip_prefix("mlt:") {
+                    let (large_step, sigma) = match mutations.split_once(':') {
+                        Some((large_step, sigma)) => (large_step, sigma.parse::<f64>().ok()?),
+                        None => (mutations, DEFAULT_SIGMA),
+                    };
+                    let large_step_probability = large_step.parse::<f64>().ok()?;
+                    return ((0. ..=1.).contains(&large_step_probability) && sigma > 0.).then_some(
+                        IntegratorKind::Metropolis {
+                            large_step_probability,
+                            sigma,
+                        },
+                    );
+                }
+                let distance = name.strip_prefix("ao:")?.parse::<f64>().ok()?;
+                (distance > 0.).then_some(IntegratorKind::AmbientOcclusion(distance))
+            }
+        }
+    }
+
+    /// Whether the integrator adds light to pixels other than the one sampled.
+    pub fn splats(&self) -> bool {
+        matches!(
+            self,
+            IntegratorKind::Bidirectional | IntegratorKind::Metropolis { .. }
+        )
+    }
+
+    pub fn integrator(&self) -> Box<dyn Integrator> {
+        match *self {
+            IntegratorKind::Path => Box::new(PathTracer),
+            IntegratorKind::LightSampling => Box::new(LightSamplingPathTracer),
+            IntegratorKind::Bidirectional => Box::new(BidirectionalPathTracer),
+            IntegratorKind::PhotonMapping(_) => Box::new(PhotonMappingPathTracer),
+            IntegratorKind::Metropolis {
+                large_step_probability,
+                sigma,
+            } => Box::new(MetropolisPathTracer {
+                large_step_probability,
+                sigma,
+            }),
+            IntegratorKind::AmbientOcclusion(distance) => Box::new(AmbientOcclusion { distance }),
+            IntegratorKind::Whitted => Box::new(Whitted),
+            IntegratorKind::Debug(channel) => Box::new(DebugView(channel)),
+        }
+    }
+}
+
+impl fmt::Display for IntegratorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegratorKind::Path => write!(f, "path"),
+            IntegratorKind::LightSampling => write!(f, "nee"),
+            IntegratorKind::Bidirectional => write!(f, "bdpt"),
+            IntegratorKind::PhotonMapping(DEFAULT_PHOTONS) => write!(f, "photons"),
+            IntegratorKind::PhotonMapping(count) => write!(f, "photons:{}", count),
+            IntegratorKind::Metropolis {
+                large_step_probability,
+                sigma,
+            } => {
+                write!(f, "mlt")?;
+                if *large_step_probability != DEFAULT_LARGE_STEP_PROBABILITY
+                    || *sigma != DEFAULT_SIGMA
+                {
+                    write!(f, ":{}", large_step_probability)?;
+                }
+                if *sigma != DEFAULT_SIGMA {
+                    write!(f, ":{}", sigma)?;
+                }
+                Ok(())
+            }
+            IntegratorKind::AmbientOcclusion(distance) if distance.is_infinite() => {
+                write!(f, "ao")
+            }
+            IntegratorKind::AmbientOcclusion(distance) => write!(f, "ao:{}", distance),
+            IntegratorKind::Whitted => write!(f, "whitted"),
+            IntegratorKind::Debug(DebugChannel::Normals) => write!(f, "normals"),
+            IntegratorKind::Debug(DebugChannel::Depth) => write!(f, "depth"),
+            IntegratorKind::Debug(DebugChannel::Uv) => write!(f, "uv"),
+            IntegratorKind::Debug(DebugChannel::Albedo) => write!(f, "albedo"),
+        }
+    }
+}
+
+/// Follows the path a ray starts until it escapes, is absorbed or ends by Russian
+/// roulette. `throughput` tracks how much of the light found further down the path
+/// reaches the camera, so dim paths are cut short while the surviving ones are
+/// weighted up to keep the estimate unbiased.
+pub struct PathTracer;
+
+impl Integrator for PathTracer {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        settings: &Settings,
+        _splats: &mut Vec<(usize, Vec3)>,
+    ) -> Vec3 {
+        let mut radiance = Vec3(0., 0., 0.);
+        let mut throughput = Vec3(1., 1., 1.);
+        let mut ray = *ray;
+        let mut interior = Interior::default();
+
+        for depth in 0..settings.max_depth {
+            let mut rec = Hit::empty();
+
+            let hit_anything = trace(&scene.world, &ray, &mut rec);
+
+            //Scattering in the atmosphere before the ray reaches a surface
+            let fog_scattering = scene.fog.as_ref().and_then(|fog| {
+                fog.sample_distance(&ray)
+                    .filter(|&t| !hit_anything || t < rec.t)
+                    .map(|t| (fog, t))
+            });
+
+            if let Some((fog, t)) = fog_scattering {
+                throughput = throughput * interior.transmittance(t * ray.dir.length());
+                throughput = throughput * reflectance(fog.albedo);
+                ray = Ray {
+                    origin: ray.at(t),
+                    dir: sample_henyey_greenstein(&unit_vector(&ray.dir), fog.g),
+                };
+            } else {
+                if !hit_anything {
+                    return radiance + throughput * scene.background(ray.dir);
+                }
+                throughput = throughput * interior.transmittance(rec.t * ray.dir.length());
+
+                let mut scattered = Ray {
+                    origin: Vec3(0., 0., 0.),
+                    dir: Vec3(0., 0., 0.),
+                };
+
+                let mut attenuation = Vec3(0., 0., 0.);
+                radiance += throughput * color_emitted(rec.material, rec.front_face);
+
+                if !scatter(rec.material, &ray, &rec, &mut attenuation, &mut scattered) {
+                    return radiance;
+                }
+
+                interior.cross(&rec, &scattered);
+                throughput = throughput * attenuation;
+                ray = scattered;
+            }
+
+            if !survives_roulette(&mut throughput, depth, settings) {
+                return radiance;
+            }
+        }
+
+        stats::record(|stats| stats.terminated_by_depth += 1);
+        radiance
+    }
+}
+
+/// Path tracer that connects every scattering point to a sampled light with a shadow
+/// ray. Lights hit by the scattered rays still count, both estimates are weighted by
+/// the power heuristic so each dominates where it has less noise: light sampling for
+/// small lights and diffuse surfaces, scattering for big lights and glossy ones.
+pub struct LightSamplingPathTracer;
+
+impl Integrator for LightSamplingPathTracer {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        settings: &Settings,
+        _splats: &mut Vec<(usize, Vec3)>,
+    ) -> Vec3 {
+        let mut radiance = Vec3(0., 0., 0.);
+        let mut throughput = Vec3(1., 1., 1.);
+        let mut ray = *ray;
+        //Density with which the last scattering picked the ray, `None` for camera
+        //rays and after mirrors and glass, which light sampling cannot stand in for
+        let mut scattering_pdf: Option<f64> = None;
+        let mut interior = Interior::default();
+
+        for depth in 0..settings.max_depth {
+            let mut rec = Hit::empty();
+
+            let hit_anything = trace(&scene.world, &ray, &mut rec);
+
+            if let Some(collision) = fog_collision(scene, &ray, hit_anything, &rec) {
+                rec = collision;
+                throughput = throughput * interior.transmittance(rec.t * ray.dir.length());
+            } else if !hit_anything {
+                //Light sampling leaves the sky to the scattered rays
+                return radiance + throughput * scene.background(ray.dir);
+            } else {
+                throughput = throughput * interior.transmittance(rec.t * ray.dir.length());
+                let weight = match scattering_pdf {
+                    Some(pdf) if is_light(rec.material) => {
+                        power_heuristic(pdf, scene.lights.pdf(ray.origin, rec.point))
+                    }
+                    _ => 1.,
+                };
+                radiance += throughput * color_emitted(rec.material, rec.front_face) * weight;
+            }
+
+            radiance += throughput * direct_light(scene, &ray, &rec, true);
+
+            let mut scattered = Ray {
+                origin: Vec3(0., 0., 0.),
+                dir: Vec3(0., 0., 0.),
+            };
+            let mut attenuation = Vec3(0., 0., 0.);
+            if !scatter(rec.material, &ray, &rec, &mut attenuation, &mut scattered) {
+                return radiance;
+            }
+
+            interior.cross(&rec, &scattered);
+            scattering_pdf = scattering(rec.material, &ray, &rec, &unit_vector(&scattered.dir))
+                .map(|(_, pdf)| pdf);
+            throughput = throughput * attenuation;
+            ray = scattered;
+
+            if !survives_roulette(&mut throughput, depth, settings) {
+                return radiance;
+            }
+        }
+
+        stats::record(|stats| stats.terminated_by_depth += 1);
+        radiance
+    }
+}
+
+/// White where the cosine-weighted hemisphere above the first surface is open,
+/// black where a ray into it hits something within `distance`. Rays that miss the
+/// world are white.
+pub struct AmbientOcclusion {
+    pub distance: f64,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        _settings: &Settings,
+        _splats: &mut Vec<(usize, Vec3)>,
+    ) -> Vec3 {
+        let mut rec = Hit::empty();
+        if !trace(&scene.world, ray, &mut rec) {
+            return Vec3(1., 1., 1.);
+        }
+
+        let mut dir = rec.normal + random_unit_vector();
+        if dir.close_to_zero() {
+            dir = rec.normal;
+        }
+
+        if occluded(&scene.world, rec.point, unit_vector(&dir), self.distance) {
+            Vec3(0., 0., 0.)
+        } else {
+            Vec3(1., 1., 1.)
+        }
+    }
+}
+
+/// Classic ray tracing: metals reflect and glass splits into a reflected and a
+/// refracted ray, every other surface is lit by one light sample and no bounce light.
+/// Fog only dims the shadow rays.
+pub struct Whitted;
+
+//Branches contributing less than this to the pixel are not followed
+const WHITTED_MIN_WEIGHT: f64 = 1e-3;
+
+impl Integrator for Whitted {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        settings: &Settings,
+        _splats: &mut Vec<(usize, Vec3)>,
+    ) -> Vec3 {
+        whitted(ray, scene, settings.max_depth, 1., &Interior::default())
+    }
+}
+
+fn whitted(ray: &Ray, scene: &Scene, depth: i32, weight: f64, interior: &Interior) -> Vec3 {
+    if depth <= 0 || weight < WHITTED_MIN_WEIGHT {
+        stats::record(|stats| stats.terminated_by_depth += 1);
+        return Vec3(0., 0., 0.);
+    }
+
+    let mut rec = Hit::empty();
+    if !trace(&scene.world, ray, &mut rec) {
+        return scene.background(ray.dir);
+    }
+    let transmittance = interior.transmittance(rec.t * ray.dir.length());
+
+    let unit_direction = unit_vector(&ray.dir);
+    let reflected = Ray {
+        origin: rec.point,
+        dir: reflect(&unit_direction, &rec.normal),
+    };
+
+    let radiance = match rec.material {
+        Material::Metal(r, g, b, _) => {
+            let color = reflectance(Vec3(r, g, b));
+            let weight = weight * color.max_component();
+            color * whitted(&reflected, scene, depth - 1, weight, interior)
+        }
+        Material::Dielectric(ir) | Material::AbsorbingDielectric(ir, _, _, _) => {
+            dielectric(ray, &rec, ir, scene, depth, weight, interior)
+        }
+        Material::Dispersive(glass) => {
+            //Only the channel of the wavelength the glass bends the rays for goes on
+            let (wavelength, channel) = disperse();
+            let ir = glass.index(wavelength);
+            channel * dielectric(ray, &rec, ir, scene, depth, weight, interior)
+        }
+        material => color_emitted(material, rec.front_face) + direct_light(scene, ray, &rec, false),
+    };
+    transmittance * radiance
+}
+
+fn dielectric(
+    ray: &Ray,
+    rec: &Hit,
+    ir: f64,
+    scene: &Scene,
+    depth: i32,
+    weight: f64,
+    interior: &Interior,
+) -> Vec3 {
+    let unit_direction = unit_vector(&ray.dir);
+    let reflected = Ray {
+        origin: rec.point,
+        dir: reflect(&unit_direction, &rec.normal),
+    };
+    let refraction_ratio = if rec.front_face { 1. / ir } else { ir };
+    let cos_theta = f64::min(dot(&-unit_direction, &rec.normal), 1.);
+    let sin_theta = f64::sqrt(1. - cos_theta * cos_theta);
+
+    if refraction_ratio * sin_theta > 1. {
+        return whitted(&reflected, scene, depth - 1, weight, interior);
+    }
+
+    let reflectance = refractance(cos_theta, refraction_ratio);
+    let refracted = Ray {
+        origin: rec.point,
+        dir: refract(&unit_direction, &rec.normal, refraction_ratio),
+    };
+    let mut inside = interior.clone();
+    inside.cross(rec, &refracted);
+    reflectance * whitted(&reflected, scene, depth - 1, weight * reflectance, interior)
+        + (1. - reflectance)
+            * whitted(
+                &refracted,
+                scene,
+                depth - 1,
+                weight * (1. - reflectance),
+                &inside,
+            )
+}
+
+/// Shows a property of the first surface hit, black where rays miss the world.
+pub struct DebugView(pub DebugChannel);
+
+impl Integrator for DebugView {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        _settings: &Settings,
+        _splats: &mut Vec<(usize, Vec3)>,
+    ) -> Vec3 {
+        let mut rec = Hit::empty();
+        if !trace(&scene.world, ray, &mut rec) {
+            return Vec3(0., 0., 0.);
+        }
+
+        match self.0 {
+            DebugChannel::Normals => {
+                let normal = if rec.front_face {
+                    rec.normal
+                } else {
+                    -rec.normal
+                };
+                0.5 * (normal + Vec3(1., 1., 1.))
+            }
+            DebugChannel::Depth => {
+                let shade = 1. / (1. + rec.t * ray.dir.length());
+                Vec3(shade, shade, shade)
+            }
+            DebugChannel::Uv => Vec3(rec.u.rem_euclid(1.), rec.v.rem_euclid(1.), 0.),
+            DebugChannel::Albedo => albedo(rec.material),
+        }
+    }
+}
+
+/// Finds the closest hit along the ray and counts the ray.
+pub fn trace(world: &Hittables, ray: &Ray, rec: &mut Hit) -> bool {
+    progress::count_ray();
+    stats::record(|stats| stats.rays += 1);
+    hit(world, ray, 0.001, f64::INFINITY, rec)
+}
+
+/// Whether anything lies within `distance` of `origin` along the unit direction `dir`,
+/// counted as a shadow ray.
+pub fn occluded(world: &Hittables, origin: Vec3, dir: Vec3, distance: f64) -> bool {
+    progress::count_ray();
+    stats::record(|stats| stats.shadow_rays += 1);
+    let mut rec = Hit::empty();
+    //Stop short of the end, which usually lies on a light
+    hit(
+        world,
+        &Ray { origin, dir },
+        0.001,
+        distance * (1. - 1e-6),
+        &mut rec,
+    )
+}
+
+/// Fraction of the light leaving `origin` along the unit direction `dir` that arrives
+/// `distance` away, through the fog and voxel media and nothing else.
+pub fn visibility(scene: &Scene, origin: Vec3, dir: Vec3, distance: f64) -> f64 {
+    progress::count_ray();
+    stats::record(|stats| stats.shadow_rays += 1);
+    let ray = Ray { origin, dir };
+    //Stop short of the end, which usually lies on a light
+    let transmittance = transmittance(&scene.world, &ray, 0.001, distance * (1. - 1e-6));
+    if transmittance == 0. {
+        return 0.;
+    }
+    transmittance
+        * scene
+            .fog
+            .as_ref()
+            .map_or(1., |fog| fog.transmittance(&ray, distance))
+}
+
+/// Where the ray scatters in the fog before reaching the surface it hits, if anywhere,
+/// as a collision in a medium with the phase function of the fog.
+pub fn fog_collision(scene: &Scene, ray: &Ray, hit_anything: bool, rec: &Hit) -> Option<Hit> {
+    let fog = scene.fog.as_ref()?;
+    let t = fog
+        .sample_distance(ray)
+        .filter(|&t| !hit_anything || t < rec.t)?;
+    Some(Hit {
+        point: ray.at(t),
+        normal: -unit_vector(&ray.dir),
+        t,
+        u: 0.,
+        v: 0.,
+        front_face: true,
+        material: Material::HenyeyGreenstein(fog.albedo.0, fog.albedo.1, fog.albedo.2, fog.g),
+    })
+}
+
+/// Light reaching the scattering point of `rec` straight from a sampled light, towards
+/// the origin of `ray`. With `mis` the estimate is weighted against finding the same
+/// light by scattering.
+pub fn direct_light(scene: &Scene, ray: &Ray, rec: &Hit, mis: bool) -> Vec3 {
+    let sample = match scene.lights.sample(rec.point) {
+        Some(sample) => sample,
+        None => return Vec3(0., 0., 0.),
+    };
+    let (value, scattering_pdf) = match scattering(rec.material, ray, rec, &sample.dir) {
+        Some((value, pdf)) if value.max_component() > 0. => (value, pdf),
+        _ => return Vec3(0., 0., 0.),
+    };
+    let transmittance = visibility(scene, rec.point, sample.dir, sample.distance);
+    if transmittance == 0. {
+        return Vec3(0., 0., 0.);
+    }
+
+    //Scattered rays never find punctual lights, light sampling is the only way
+    let weight = if mis && !sample.punctual {
+        power_heuristic(sample.pdf, scattering_pdf)
+    } else {
+        1.
+    };
+    value * sample.radiance * (transmittance * weight / sample.pdf)
+}
+
+/// Weight of the sampling strategy with density `pdf` against the one with `other`.
+pub fn power_heuristic(pdf: f64, other: f64) -> f64 {
+    let squared = pdf * pdf;
+    squared / (squared + other * other)
+}
+
+/// Russian roulette from the depth in the settings. Surviving paths are weighted up,
+/// false when the path ends.
+pub fn survives_roulette(throughput: &mut Vec3, depth: i32, settings: &Settings) -> bool {
+    if depth + 1 < settings.russian_roulette_depth {
+        return true;
+    }
+
+    let survival = f64::min(throughput.max_component(), 0.95);
+    if random_double() >= survival {
+        stats::record(|stats| stats.terminated_by_roulette += 1);
+        return false;
+    }
+    *throughput /= survival;
+    true
+}
+
+#[cfg(test)]
+mod integrator_tests {
+    use super::*;
+    use crate::{
+        camera::Camera,
+        hittables::Hittables::{HittableObjects, Plane, Sphere},
+        lights::Punctual,
+        materials::Material::{AbsorbingDielectric, Lambertian, Light},
+        rand,
+        sky::{sun_direction, Sky},
+        spectrum,
+        utils::PI,
+    };
+
+    //Inside a diffuse box lit by a sphere
+    fn scene() -> Scene {
+        Scene::new(
+            HittableObjects(vec![
+                Sphere(Vec3(0., 0., 0.), -10., Lambertian(0.8, 0.8, 0.8)),
+                Sphere(Vec3(0., 5., 0.), 2., Light(1., 1., 1.)),
+            ]),
+            None,
+            Camera::new(
+                Vec3(0., -5., 0.),
+                Vec3(1., -5., 0.),
+                Vec3(0., 1., 0.),
+                60.,
+                1.,
+            ),
+        )
+    }
+
+    fn settings(integrator: IntegratorKind, russian_roulette_depth: i32) -> Settings {
+        Settings {
+            width: 1,
+            height: 1,
+            samples_per_pixel: 1,
+            max_depth: 32,
+            russian_roulette_depth,
+            seed: 0,
+            integrator,
+            spectral: false,
+        }
+    }
+
+    //Mean radiance over many paths from inside the box
+    fn mean_radiance(settings: &Settings, n: u64) -> Vec3 {
+        let scene = scene();
+        let integrator = settings.integrator.integrator();
+        let mut sum = Vec3(0., 0., 0.);
+        for i in 0..n {
+            rand::seed(11, &[i]);
+            spectrum::set_wavelengths(
+                settings
+                    .spectral
+                    .then(|| spectrum::sample_wavelengths(rand::next_f64())),
+            );
+            let ray = Ray {
+                origin: Vec3(0., -5., 0.),
+                dir: Vec3(1., 0.2, 0.),
+            };
+            sum += spectrum::to_rgb(integrator.radiance(&ray, &scene, settings, &mut Vec::new()));
+        }
+        spectrum::set_wavelengths(None);
+        sum / n as f64
+    }
+
+    #[test]
+    fn test_russian_roulette_is_unbiased() {
+        let exact = mean_radiance(&settings(IntegratorKind::Path, 32), 40_000);
+        let roulette = mean_radiance(&settings(IntegratorKind::Path, 2), 40_000);
+        assert!(
+            f64::abs(exact.0 - roulette.0) < 0.03 * exact.0,
+            "{:?} != {:?}",
+            exact,
+            roulette
+        );
+    }
+
+    #[test]
+    fn test_spectral_matches_rgb() {
+        //White light in a grey box has no color to lose to the spectra
+        let rgb = mean_radiance(&settings(IntegratorKind::LightSampling, 3), 20_000);
+        let spectral = mean_radiance(
+            &Settings {
+                spectral: true,
+                ..settings(IntegratorKind::LightSampling, 3)
+            },
+            20_000,
+        );
+        assert!(
+            (rgb - spectral).length() < 0.03 * rgb.length(),
+            "{:?} != {:?}",
+            rgb,
+            spectral
+        );
+    }
+
+    #[test]
+    fn test_light_sampling_converges_to_path_tracing() {
+        let path = mean_radiance(&settings(IntegratorKind::Path, 32), 40_000);
+        let light_sampling = mean_radiance(&settings(IntegratorKind::LightSampling, 32), 10_000);
+        assert!(
+            f64::abs(path.0 - light_sampling.0) < 0.03 * path.0,
+            "{:?} != {:?}",
+            path,
+            light_sampling
+        );
+    }
+
+    #[test]
+    fn test_absorbing_glass() {
+        //Straight through the middle of a ball of glass not bending light, onto a light
+        let scene = Scene::new(
+            HittableObjects(vec![
+                Sphere(Vec3(0., 0., 0.), 1., AbsorbingDielectric(1., 0.5, 0.25, 1.)),
+                Sphere(Vec3(0., 0., 5.), 1., Light(1., 1., 1.)),
+            ]),
+            None,
+            scene().camera,
+        );
+        let ray = Ray {
+            origin: Vec3(0., 0., -5.),
+            dir: Vec3(0., 0., 1.),
+        };
+        for integrator in [IntegratorKind::Path, IntegratorKind::LightSampling] {
+            let settings = settings(integrator, 32);
+            let radiance =
+                integrator
+                    .integrator()
+                    .radiance(&ray, &scene, &settings, &mut Vec::new());
+            assert!(
+                (radiance - Vec3(0.25, 0.0625, 1.)).length() < 1e-9,
+                "{:?}",
+                radiance
+            );
+        }
+    }
+
+    #[test]
+    fn test_punctual_lights() {
+        //Straight down onto a diffuse floor lit by a single light
+        let ray = Ray {
+            origin: Vec3(0., 1., 0.),
+            dir: Vec3(0., -1., 0.),
+        };
+        let floor = Plane(
+            Vec3(0., 0., 0.),
+            Vec3(0., 1., 0.),
+            Lambertian(0.5, 0.5, 0.5),
+        );
+        let light = Light(4., 4., 4.);
+        for (punctual, irradiance) in [
+            (Punctual::Point(Vec3(0., 2., 0.), light), 1.),
+            (
+                Punctual::Spot(Vec3(0., 2., 0.), Vec3(0., -1., 0.), 10., 20., light),
+                1.,
+            ),
+            (
+                Punctual::Spot(Vec3(0., 2., 0.), Vec3(1., 0., 0.), 10., 20., light),
+                0.,
+            ),
+            (Punctual::Directional(Vec3(0., 1., 0.), 0., light), 4.),
+            (
+                Punctual::Directional(Vec3(3., 1., 0.), 0., light),
+                4. / 10_f64.sqrt(),
+            ),
+        ] {
+            let mut scene = Scene::new(floor.clone(), None, scene().camera);
+            scene.lights.add(punctual);
+            for integrator in [IntegratorKind::LightSampling, IntegratorKind::Whitted] {
+                let settings = settings(integrator, 32);
+                let radiance =
+                    integrator
+                        .integrator()
+                        .radiance(&ray, &scene, &settings, &mut Vec::new());
+                let expected = 0.5 / PI * irradiance;
+                assert!(
+                    f64::abs(radiance.0 - expected) < 1e-9,
+                    "{}: {:?} != {}",
+                    integrator,
+                    radiance,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_sky() {
+        //A diffuse floor under the sky looks like the ground of the sky with its albedo
+        let floor = Plane(
+            Vec3(0., 0., 0.),
+            Vec3(0., 1., 0.),
+            Lambertian(0.5, 0.5, 0.5),
+        );
+        let sky = Sky::new(sun_direction(40., 30.), 3., Vec3(0.5, 0.5, 0.5));
+        let mut scene = Scene::new(floor, None, scene().camera);
+        scene.set_sky(sky);
+
+        let down = Ray {
+            origin: Vec3(0., 1., 0.),
+            dir: Vec3(0., -1., 0.),
+        };
+        let light_sampling = settings(IntegratorKind::LightSampling, 32);
+        let n = 20_000;
+        let mut sum = Vec3(0., 0., 0.);
+        for i in 0..n {
+            rand::seed(5, &[i]);
+            sum +=
+                LightSamplingPathTracer.radiance(&down, &scene, &light_sampling, &mut Vec::new());
+        }
+        let expected = sky.radiance(Vec3(0., -1., 0.));
+        assert!(
+            (sum / n as f64 - expected).length() < 0.02 * expected.length(),
+            "{:?} != {:?}",
+            sum / n as f64,
+            expected
+        );
+
+        //Rays escaping the world see the sky
+        let up = Ray {
+            origin: Vec3(0., 1., 0.),
+            dir: Vec3(1., 2., 0.),
+        };
+        for integrator in [
+            IntegratorKind::Path,
+            IntegratorKind::LightSampling,
+            IntegratorKind::Whitted,
+        ] {
+            let settings = settings(integrator, 32);
+            let radiance =
+                integrator
+                    .integrator()
+                    .radiance(&up, &scene, &settings, &mut Vec::new());
+            assert_eq!(radiance, sky.radiance(up.dir), "{}", integrator);
+        }
+    }
+
+    #[test]
+    fn test_debug_views() {
+        let scene = scene();
+        let settings = settings(IntegratorKind::Path, 32);
+        //Straight down onto the bottom of the box
+        let ray = Ray {
+            origin: Vec3(0., 0., 0.),
+            dir: Vec3(0., -2., 0.),
+        };
+        let view = |channel| DebugView(channel).radiance(&ray, &scene, &settings, &mut Vec::new());
+
+        assert_eq!(view(DebugChannel::Normals), Vec3(0.5, 1., 0.5));
+        assert!(f64::abs(view(DebugChannel::Depth).0 - 1. / 11.) < 1e-9);
+        assert_eq!(view(DebugChannel::Albedo), Vec3(0.8, 0.8, 0.8));
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        for name in [
+            "path",
+            "nee",
+            "bdpt",
+            "photons",
+            "photons:1000",
+            "mlt",
+            "mlt:0.5",
+            "mlt:0.3:0.05",
+            "ao",
+            "ao:0.5",
+            "whitted",
+            "normals",
+            "depth",
+            "uv",
+            "albedo",
+        ] {
+            let integrator = IntegratorKind::parse(name).unwrap();
+            assert_eq!(integrator.to_string(), name);
+        }
+        assert_eq!(IntegratorKind::parse("ao:-1"), None);
+        assert_eq!(IntegratorKind::parse("photons:0"), None);
+        assert_eq!(IntegratorKind::parse("mlt:2"), None);
+        assert_eq!(IntegratorKind::parse("mlt:0.3:0"), None);
+        assert_eq!(IntegratorKind::parse("sppm"), None);
+    }
+}
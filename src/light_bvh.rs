@@ -0,0 +1,437 @@
+//! Bounding volume hierarchy over the lights, to pick the lights likely to contribute
+//! most at a point among thousands.
+//!
+//! Every node bounds the position, power and emitted directions of the lights below
+//! it. Walking down from the root, each step picks a child by its importance at the
+//! point: its power over the squared distance, scaled by the cosine of the smallest
+//! angle at which it could be shining towards the point. This is the light BVH of
+//! pbrt-v4 after Conty Estevez and Kulla, without the surface normal at the point.
+
+use crate::{
+    aabb::Aabb,
+    stats,
+    utils::PI,
+    vec3::{cross, dot, unit_vector, Vec3},
+};
+
+/// What a light or group of lights looks like from afar.
+#[derive(Clone, Copy, Debug)]
+pub struct LightBounds {
+    pub bounds: Aabb,
+    /// Power emitted, by luminance.
+    pub phi: f64,
+    /// Cone of the normals of the surfaces emitting: the axis and the cosine of the
+    /// angle out to which they spread.
+    pub w: Vec3,
+    pub cos_theta_o: f64,
+    /// Cosine of the angle away from its normal out to which a surface emits.
+    pub cos_theta_e: f64,
+    /// Whether the surfaces emit on the back side as well.
+    pub two_sided: bool,
+}
+
+impl LightBounds {
+    fn union(&self, other: &LightBounds) -> LightBounds {
+        let (w, cos_theta_o) = cone_union((self.w, self.cos_theta_o), (other.w, other.cos_theta_o));
+        LightBounds {
+            bounds: Aabb::surrounding(&self.bounds, &other.bounds),
+            phi: self.phi + other.phi,
+            w,
+            cos_theta_o,
+            cos_theta_e: f64::min(self.cos_theta_e, other.cos_theta_e),
+            two_sided: self.two_sided || other.two_sided,
+        }
+    }
+
+    /// How much the lights could contribute at the point, up to a common factor.
+    pub fn importance(&self, point: Vec3) -> f64 {
+        let center = 0.5 * (self.bounds.min + self.bounds.max);
+        let offset = point - center;
+        let distance_squared = offset.length_squared();
+        //Not more than as if the point were at the edge of the bounds
+        let diagonal = self.bounds.max - self.bounds.min;
+        let d2 = f64::max(distance_squared, diagonal.length() / 2.);
+
+        //Angle between the axis of the normals and the direction towards the point
+        let mut cos_theta_w = if distance_squared > 0. {
+            dot(&self.w, &(offset / distance_squared.sqrt()))
+        } else {
+            1.
+        };
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        let sin_theta_w = sin_from_cos(cos_theta_w);
+
+        //Angle the bounds take up seen from the point
+        let radius_squared = (diagonal / 2.).length_squared();
+        let cos_theta_b = if distance_squared < radius_squared {
+            -1.
+        } else {
+            f64::sqrt(f64::max(0., 1. - radius_squared / distance_squared))
+        };
+        let sin_theta_b = sin_from_cos(cos_theta_b);
+
+        //Smallest angle between a normal in the cone and a direction towards the bounds
+        let sin_theta_o = sin_from_cos(self.cos_theta_o);
+        let (sin_theta_x, cos_theta_x) =
+            angle_difference((sin_theta_w, cos_theta_w), (sin_theta_o, self.cos_theta_o));
+        let (_, cos_theta_p) =
+            angle_difference((sin_theta_x, cos_theta_x), (sin_theta_b, cos_theta_b));
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.;
+        }
+        self.phi * cos_theta_p / d2
+    }
+}
+
+fn sin_from_cos(cos_theta: f64) -> f64 {
+    f64::sqrt(f64::max(0., 1. - cos_theta * cos_theta))
+}
+
+/// Sine and cosine of the angle `a - b`, or of zero when `b` is the larger angle.
+fn angle_difference(a: (f64, f64), b: (f64, f64)) -> (f64, f64) {
+    let ((sin_a, cos_a), (sin_b, cos_b)) = (a, b);
+    if cos_a > cos_b {
+        (0., 1.)
+    } else {
+        (sin_a * cos_b - cos_a * sin_b, cos_a * cos_b + sin_a * sin_b)
+    }
+}
+
+/// Smallest cone holding two cones of directions given by their unit axis and the
+/// cosine of their spread.
+fn cone_union(a: (Vec3, f64), b: (Vec3, f64)) -> (Vec3, f64) {
+    let theta_a = f64::acos(a.1.clamp(-1., 1.));
+    let theta_b = f64::acos(b.1.clamp(-1., 1.));
+    let theta_d = f64::acos(dot(&a.0, &b.0).clamp(-1., 1.));
+    if f64::min(theta_d + theta_b, PI) <= theta_a {
+        return a;
+    }
+    if f64::min(theta_d + theta_a, PI) <= theta_b {
+        return b;
+    }
+
+    let theta_o = (theta_a + theta_d + theta_b) / 2.;
+    let axis = cross(&a.0, &b.0);
+    if theta_o >= PI || axis.close_to_zero() {
+        return (a.0, -1.);
+    }
+    //Turn the axis of `a` towards `b` until the cone takes in both
+    let k = unit_vector(&axis);
+    let theta_r = theta_o - theta_a;
+    let w = f64::cos(theta_r) * a.0
+        + f64::sin(theta_r) * cross(&k, &a.0)
+        + (1. - f64::cos(theta_r)) * dot(&k, &a.0) * k;
+    (unit_vector(&w), f64::cos(theta_o))
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Content {
+    Light(usize),
+    /// Index of the second child, the first one follows the node.
+    Second(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    bounds: LightBounds,
+    content: Content,
+}
+
+#[derive(Default, Debug)]
+pub struct LightBvh {
+    nodes: Vec<Node>,
+    /// Way down from the root to each light, a bit per level set where it takes the
+    /// second child. `None` for lights left out of the tree.
+    trails: Vec<Option<u64>>,
+}
+
+impl LightBvh {
+    /// Builds the tree over the lights by their index, leaving out those without
+    /// bounds or power.
+    pub fn new(lights: &[Option<LightBounds>]) -> LightBvh {
+        let mut bvh = LightBvh {
+            nodes: Vec::new(),
+            trails: vec![None; lights.len()],
+        };
+        let mut lights: Vec<(usize, LightBounds)> = lights
+            .iter()
+            .enumerate()
+            .filter_map(|(index, bounds)| {
+                bounds.filter(|bounds| bounds.phi > 0.).map(|b| (index, b))
+            })
+            .collect();
+        if !lights.is_empty() {
+            bvh.build(&mut lights, 0, 0);
+        }
+        bvh
+    }
+
+    fn build(
+        &mut self,
+        lights: &mut [(usize, LightBounds)],
+        trail: u64,
+        depth: u32,
+    ) -> LightBounds {
+        let index = self.nodes.len();
+        if let [(light, bounds)] = *lights {
+            self.nodes.push(Node {
+                bounds,
+                content: Content::Light(light),
+            });
+            self.trails[light] = Some(trail);
+            return bounds;
+        }
+
+        //Halves along the axis the centers spread most on
+        let center = |bounds: &LightBounds| 0.5 * (bounds.bounds.min + bounds.bounds.max);
+        let (min, max) = lights.iter().fold(
+            (
+                Vec3(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+                -Vec3(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            ),
+            |(min, max), (_, bounds)| {
+                let c = center(bounds);
+                (
+                    Vec3(min.0.min(c.0), min.1.min(c.1), min.2.min(c.2)),
+                    Vec3(max.0.max(c.0), max.1.max(c.1), max.2.max(c.2)),
+                )
+            },
+        );
+        let extent = max - min;
+        let axis = if extent.0 >= extent.1 && extent.0 >= extent.2 {
+            0
+        } else if extent.1 >= extent.2 {
+            1
+        } else {
+            2
+        };
+        lights.sort_by(|a, b| center(&a.1)[axis].total_cmp(&center(&b.1)[axis]));
+
+        self.nodes.push(Node {
+            bounds: lights[0].1,
+            content: Content::Second(0),
+        });
+        let (first, second) = lights.split_at_mut(lights.len() / 2);
+        let first = self.build(first, trail, depth + 1);
+        let second_index = self.nodes.len();
+        let second = self.build(second, trail | 1 << depth, depth + 1);
+
+        let bounds = first.union(&second);
+        self.nodes[index] = Node {
+            bounds,
+            content: Content::Second(second_index),
+        };
+        bounds
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Picks a light by its importance at the point with the uniform random number
+    /// `u`, and returns it with the probability of picking it.
+    pub fn sample(&self, point: Vec3, mut u: f64) -> Option<(usize, f64)> {
+        let root = self.nodes.first()?;
+        let mut index = 0;
+        let mut pmf = 1.;
+        loop {
+            stats::record(|stats| stats.light_bvh_nodes_visited += 1);
+            match self.nodes[index].content {
+                Content::Light(light) => {
+                    return (index > 0 || root.bounds.importance(point) > 0.)
+                        .then_some((light, pmf));
+                }
+                Content::Second(second) => {
+                    let p = self.first_probability(index, second, point)?;
+                    //Stretch what is left of the random number back over [0, 1)
+                    if u < p {
+                        index += 1;
+                        u /= p;
+                        pmf *= p;
+                    } else {
+                        index = second;
+                        u = (u - p) / (1. - p);
+                        pmf *= 1. - p;
+                    }
+                    u = f64::min(u, 1. - f64::EPSILON);
+                }
+            }
+        }
+    }
+
+    /// Probability with which `sample` picks the light at the point.
+    pub fn pmf(&self, point: Vec3, light: usize) -> f64 {
+        let mut trail = match self.trails.get(light).copied().flatten() {
+            Some(trail) => trail,
+            None => return 0.,
+        };
+        let mut index = 0;
+        let mut pmf = 1.;
+        loop {
+            stats::record(|stats| stats.light_bvh_nodes_visited += 1);
+            match self.nodes[index].content {
+                Content::Light(_) => {
+                    return if index > 0 || self.nodes[0].bounds.importance(point) > 0. {
+                        pmf
+                    } else {
+                        0.
+                    };
+                }
+                Content::Second(second) => {
+                    let p = match self.first_probability(index, second, point) {
+                        Some(p) => p,
+                        None => return 0.,
+                    };
+                    if trail & 1 == 0 {
+                        index += 1;
+                        pmf *= p;
+                    } else {
+                        index = second;
+                        pmf *= 1. - p;
+                    }
+                    trail >>= 1;
+                }
+            }
+        }
+    }
+
+    /// Probability of going to the first child of the node, `None` when neither of
+    /// them matters at the point.
+    fn first_probability(&self, index: usize, second: usize, point: Vec3) -> Option<f64> {
+        let first = self.nodes[index + 1].bounds.importance(point);
+        let second = self.nodes[second].bounds.importance(point);
+        (first + second > 0.).then(|| first / (first + second))
+    }
+
+    /// Calls `f` with every light whose bounds hold the point.
+    pub fn containing(&self, point: Vec3, mut f: impl FnMut(usize)) {
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            stats::record(|stats| stats.light_bvh_nodes_visited += 1);
+            let node = &self.nodes[index];
+            if !holds(&node.bounds.bounds, point) {
+                continue;
+            }
+            match node.content {
+                Content::Light(light) => f(light),
+                Content::Second(second) => {
+                    stack.push(index + 1);
+                    stack.push(second);
+                }
+            }
+        }
+    }
+}
+
+/// Whether the point lies in the box, up to rounding.
+fn holds(bounds: &Aabb, point: Vec3) -> bool {
+    (0..3).all(|axis| {
+        let eps = 1e-6 * f64::max(1., bounds.max[axis] - bounds.min[axis]);
+        bounds.min[axis] - eps <= point[axis] && point[axis] <= bounds.max[axis] + eps
+    })
+}
+
+#[cfg(test)]
+mod light_bvh_tests {
+    use super::*;
+
+    //Lights of power one facing every way, along the x axis
+    fn row(count: usize) -> Vec<Option<LightBounds>> {
+        (0..count)
+            .map(|i| {
+                let p = Vec3(i as f64, 0., 0.);
+                Some(LightBounds {
+                    bounds: Aabb::new(p, p),
+                    phi: 1.,
+                    w: Vec3(0., 0., 1.),
+                    cos_theta_o: -1.,
+                    cos_theta_e: 0.,
+                    two_sided: false,
+                })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pmf_sums_to_one() {
+        let bvh = LightBvh::new(&row(37));
+        for point in [Vec3(0., 1., 0.), Vec3(18., -2., 3.), Vec3(100., 0., 0.)] {
+            let sum: f64 = (0..37).map(|light| bvh.pmf(point, light)).sum();
+            assert!(f64::abs(sum - 1.) < 1e-9, "{}", sum);
+        }
+    }
+
+    #[test]
+    fn test_sample_matches_pmf() {
+        let bvh = LightBvh::new(&row(10));
+        let point = Vec3(2.5, 1., 0.);
+        let n = 10_000;
+        let mut counts = [0; 10];
+        for i in 0..n {
+            let (light, pmf) = bvh.sample(point, (i as f64 + 0.5) / n as f64).unwrap();
+            assert!(f64::abs(pmf - bvh.pmf(point, light)) < 1e-12);
+            counts[light] += 1;
+        }
+        for (light, &count) in counts.iter().enumerate() {
+            let expected = bvh.pmf(point, light) * n as f64;
+            assert!(
+                f64::abs(count as f64 - expected) <= 1.,
+                "{} != {}",
+                count,
+                expected
+            );
+        }
+        //The lights next to the point are picked more than the far ones
+        assert!(counts[2] > 5 * counts[9]);
+    }
+
+    #[test]
+    fn test_one_sided_lights_are_skipped_behind() {
+        let mut lights = row(2);
+        for bounds in lights.iter_mut().flatten() {
+            bounds.cos_theta_o = 1.;
+        }
+        let bvh = LightBvh::new(&lights);
+        assert!(bvh.sample(Vec3(0.5, 0., 1.), 0.5).is_some());
+        assert!(bvh.sample(Vec3(0.5, 0., -1.), 0.5).is_none());
+        assert_eq!(bvh.pmf(Vec3(0.5, 0., -1.), 0), 0.);
+    }
+
+    #[test]
+    fn test_cone_union_holds_both() {
+        let a = (Vec3(0., 0., 1.), f64::cos(0.1));
+        let b = (Vec3(1., 0., 0.), f64::cos(0.2));
+        let (w, cos_theta) = cone_union(a, b);
+        for (axis, cos_spread) in [a, b] {
+            let angle = f64::acos(dot(&w, &axis)) + f64::acos(cos_spread);
+            assert!(angle <= f64::acos(cos_theta) + 1e-9);
+        }
+        assert!(f64::abs(f64::acos(cos_theta) - (PI / 2. + 0.3) / 2.) < 1e-9);
+        assert_eq!(cone_union(a, (Vec3(0., 0., -1.), 1.)).1, -1.);
+    }
+
+    #[test]
+    fn test_containing() {
+        let bvh = LightBvh::new(&row(20));
+        let mut found = Vec::new();
+        bvh.containing(Vec3(7., 0., 0.), |light| found.push(light));
+        assert_eq!(found, vec![7]);
+        found.clear();
+        bvh.containing(Vec3(7.5, 0., 0.), |light| found.push(light));
+        assert!(found.is_empty());
+    }
+
+    #[test]
+    fn test_counts_nodes_visited() {
+        let bvh = LightBvh::new(&row(16));
+        stats::take();
+        bvh.sample(Vec3(3., 1., 0.), 0.5).unwrap();
+        //Down the four levels of a balanced tree to a light
+        assert_eq!(stats::take().light_bvh_nodes_visited, 5);
+    }
+}
@@ -0,0 +1,1087 @@
+use crate::{
+    aabb::Aabb,
+    hittables::{bounding_box, sphere_roots, Hittables},
+    light_bvh::{LightBounds, LightBvh},
+    materials::{color_emitted, is_light, Material},
+    ray::Ray,
+    spectrum::emission,
+    utils::{degrees_to_radians, random_double, random_unit_vector, PI},
+    vec3::{cross, dot, orthonormal_basis, unit_vector, Vec3},
+};
+
+/// Emitters that integrators sample directly, so paths find the light instead of
+/// hitting it by chance. Lights are numbered in order, the punctual ones after the
+/// others.
+#[derive(Debug)]
+pub struct Lights {
+    lights: Vec<Hittables>,
+    punctual: Vec<Punctual>,
+    /// Lights with a position, to pick those that matter most at a point.
+    tree: LightBvh,
+    /// Far away lights, by index in `punctual`, picked uniformly as they matter the
+    /// same everywhere.
+    distant: Vec<usize>,
+    /// Running sums of the power of the lights, to pick the lights that start light
+    /// paths by their power.
+    power: Vec<f64>,
+}
+
+/// Lights without an area, which rays cannot hit: only the integrators sampling lights
+/// with shadow rays see them. The light material gives their color and strength.
+#[derive(Clone, Copy, Debug)]
+pub enum Punctual {
+    /// Point light at a position, its material giving the intensity. Light falls off
+    /// with the squared distance.
+    Point(Vec3, Material),
+    /// Point light shining along the direction, at full intensity within the inner
+    /// angle from it and fading out towards the outer angle, in degrees.
+    Spot(Vec3, Vec3, f64, f64, Material),
+    /// Sun or other far away light in the direction given, seen with the angular
+    /// diameter in degrees. The material gives the irradiance on surfaces facing it.
+    Directional(Vec3, f64, Material),
+}
+
+/// Direction from a point towards a sampled point on a light.
+#[derive(Clone, Copy, Debug)]
+pub struct LightSample {
+    /// Unit direction towards the light.
+    pub dir: Vec3,
+    /// Distance to the sampled point along `dir`.
+    pub distance: f64,
+    /// Radiance emitted towards the point.
+    pub radiance: Vec3,
+    /// Density of the direction per unit solid angle, light selection included. Only
+    /// the selection for point and spot lights, whose direction is certain.
+    pub pdf: f64,
+    /// Normal of the light at the sampled point, facing the point for punctual lights.
+    pub normal: Vec3,
+    /// Whether the light is punctual, so scattered rays never find it.
+    pub punctual: bool,
+}
+
+/// Point on a light and the direction of light leaving it, to trace light from the
+/// lights towards the camera.
+#[derive(Clone, Copy)]
+pub struct Emission {
+    pub point: Vec3,
+    /// Normal of the light surface, on the side the light leaves from. Punctual
+    /// lights take the direction itself.
+    pub normal: Vec3,
+    pub material: Material,
+    pub radiance: Vec3,
+    /// Density of the point per unit area, light selection included. Only the
+    /// selection for point and spot lights, whose position is certain.
+    pub pdf_position: f64,
+    /// Unit direction, cosine distributed around the normal of surfaces.
+    pub dir: Vec3,
+    /// Density of the direction per unit solid angle.
+    pub pdf_direction: f64,
+    /// Whether the light is punctual, so paths from the camera never find it.
+    pub punctual: bool,
+}
+
+impl Lights {
+    /// Collects the spheres, quads, disks, triangles and meshes with a light material
+    /// from the world. Meshes become a light per triangle.
+    pub fn new(world: &Hittables) -> Lights {
+        let mut lights = Vec::new();
+        collect(world, &mut lights);
+        let mut lights = Lights {
+            lights,
+            punctual: Vec::new(),
+            tree: LightBvh::default(),
+            distant: Vec::new(),
+            power: Vec::new(),
+        };
+        lights.build();
+        lights
+    }
+
+    pub fn add(&mut self, light: Punctual) {
+        self.punctual.push(light);
+        self.build();
+    }
+
+    fn build(&mut self) {
+        let bounds: Vec<Option<LightBounds>> = (self.lights.iter().map(light_bounds))
+            .chain(self.punctual.iter().map(punctual_bounds))
+            .collect();
+        self.tree = LightBvh::new(&bounds);
+        self.distant = (0..self.punctual.len())
+            .filter(|&i| matches!(self.punctual[i], Punctual::Directional(..)))
+            .collect();
+
+        let mut total = 0.;
+        self.power = (self.lights.iter().map(power))
+            .chain(self.punctual.iter().map(punctual_power))
+            .map(|power| {
+                total += power;
+                total
+            })
+            .collect();
+    }
+
+    /// Picks a light by how much it could contribute at the point, and returns its
+    /// number and the probability of picking it.
+    fn pick(&self, point: Vec3) -> Option<(usize, f64)> {
+        let distant = self.distant.len() as f64;
+        let p_distant = distant / (distant + if self.tree.is_empty() { 0. } else { 1. });
+        let u = random_double();
+        if u < p_distant {
+            let index = usize::min((u / p_distant * distant) as usize, self.distant.len() - 1);
+            return Some((self.lights.len() + self.distant[index], p_distant / distant));
+        }
+        let u = f64::min((u - p_distant) / (1. - p_distant), 1. - f64::EPSILON);
+        let (light, pmf) = self.tree.sample(point, u)?;
+        Some((light, pmf * (1. - p_distant)))
+    }
+
+    /// Probability with which `pick` picks the light at the point.
+    fn pmf(&self, point: Vec3, light: usize) -> f64 {
+        let distant = self.distant.len() as f64;
+        let p_distant = distant / (distant + if self.tree.is_empty() { 0. } else { 1. });
+        match light.checked_sub(self.lights.len()) {
+            Some(index) if self.distant.contains(&index) => p_distant / distant,
+            _ => self.tree.pmf(point, light) * (1. - p_distant),
+        }
+    }
+
+    /// Probability with which `sample_emission` picks the light.
+    fn power_pmf(&self, light: usize) -> f64 {
+        let before = if light == 0 {
+            0.
+        } else {
+            self.power[light - 1]
+        };
+        (self.power[light] - before) / self.power[self.power.len() - 1]
+    }
+
+    /// Picks a light, nearby and bright ones more often, and samples a direction
+    /// towards it from `point`.
+    pub fn sample(&self, point: Vec3) -> Option<LightSample> {
+        let (light, pmf) = self.pick(point)?;
+        let mut sample = match light.checked_sub(self.lights.len()) {
+            Some(index) => sample_punctual(&self.punctual[index], point)?,
+            None => sample_light(&self.lights[light], point)?,
+        };
+        sample.pdf *= pmf;
+        Some(sample)
+    }
+
+    /// Picks a light by its power, a point on it and a direction for light to leave
+    /// in. Two-sided lights pick a side at random too, as if they had twice the area.
+    /// Far away lights give no point to start from and are never picked.
+    pub fn sample_emission(&self) -> Option<Emission> {
+        let total = *self.power.last()?;
+        if total <= 0. {
+            return None;
+        }
+        let u = random_double() * total;
+        let light = usize::min(
+            self.power.partition_point(|&sum| sum <= u),
+            self.power.len() - 1,
+        );
+
+        let mut emission = match light.checked_sub(self.lights.len()) {
+            Some(index) => emit_punctual(&self.punctual[index])?,
+            None => emit(&self.lights[light])?,
+        };
+        emission.pdf_position *= self.power_pmf(light);
+        Some(emission)
+    }
+
+    /// Densities with which `sample_emission` picks the point, per unit area, and the
+    /// unit direction `dir` leaving it, per unit solid angle. Zero for points not on
+    /// any of the lights. Lights meeting at the point all count, the direction by
+    /// how likely each of them is to be the one picked.
+    pub fn pdf_emission(&self, point: Vec3, dir: Vec3) -> (f64, f64) {
+        let (mut pdf_position, mut pdf_joint) = (0., 0.);
+        self.tree.containing(point, |light| {
+            let found = match light.checked_sub(self.lights.len()) {
+                Some(index) => punctual_emission_pdf(&self.punctual[index], point, dir),
+                None => emission_pdf(&self.lights[light], point, dir),
+            };
+            if let Some((position, direction)) = found {
+                let position = position * self.power_pmf(light);
+                pdf_position += position;
+                pdf_joint += position * direction;
+            }
+        });
+        if pdf_position > 0. {
+            (pdf_position, pdf_joint / pdf_position)
+        } else {
+            (0., 0.)
+        }
+    }
+
+    /// Density with which `sample` picks the direction from `point` towards
+    /// `light_point`, a point found on one of the lights by other means.
+    pub fn pdf(&self, point: Vec3, light_point: Vec3) -> f64 {
+        let mut pdf = 0.;
+        self.tree.containing(light_point, |light| {
+            if let Some(shape) = self.lights.get(light) {
+                let density = light_pdf(shape, point, light_point);
+                if density > 0. {
+                    pdf += density * self.pmf(point, light);
+                }
+            }
+        });
+        pdf
+    }
+}
+
+/// The light with its material scaled to emit `power` in total, in the units of the
+/// scene, instead of giving its radiance. The color of the material only sets the
+/// tint then: the luminance of the emitted light adds up to the power.
+pub fn with_power(light: Hittables, power: f64) -> Hittables {
+    let scale = power / self::power(&light);
+    let material = match material(&light) {
+        Some(Material::Light(r, g, b)) => Material::Light(r * scale, g * scale, b * scale),
+        Some(Material::OneSidedLight(r, g, b)) => {
+            Material::OneSidedLight(r * scale, g * scale, b * scale)
+        }
+        Some(Material::SpectralLight(illuminant, value)) => {
+            Material::SpectralLight(illuminant, value * scale)
+        }
+        _ => return light,
+    };
+    match light {
+        Hittables::Sphere(center, radius, _) => Hittables::Sphere(center, radius, material),
+        Hittables::Quad(q, u, v, _) => Hittables::Quad(q, u, v, material),
+        Hittables::Disk(center, normal, radius, _) => {
+            Hittables::Disk(center, normal, radius, material)
+        }
+        Hittables::Triangle(a, b, c, _) => Hittables::Triangle(a, b, c, material),
+        Hittables::Mesh(mesh, _) => Hittables::Mesh(mesh, material),
+        light => light,
+    }
+}
+
+fn collect(hittable_object: &Hittables, lights: &mut Vec<Hittables>) {
+    match hittable_object {
+        Hittables::HittableObjects(list) => {
+            for item in list {
+                collect(item, lights);
+            }
+        }
+        Hittables::Mesh(mesh, material) if is_light(*material) => {
+            for i in 0..mesh.triangles.len() {
+                let (a, b, c) = mesh.triangle(i);
+                lights.push(Hittables::Triangle(a, b, c, *material));
+            }
+        }
+        Hittables::Sphere(_, _, material)
+        | Hittables::Quad(_, _, _, material)
+        | Hittables::Disk(_, _, _, material)
+        | Hittables::Triangle(_, _, _, material)
+            if is_light(*material) =>
+        {
+            lights.push(hittable_object.clone())
+        }
+        _ => {}
+    }
+}
+
+/// Point on the light and direction for light to leave in, before light selection.
+fn emit(light: &Hittables) -> Option<Emission> {
+    let material = material(light)?;
+    let (point, normal, front_face, pdf_position) = match light {
+        Hittables::Sphere(center, radius, _) => {
+            let outward = random_unit_vector();
+            //Negative radii turn the sphere inside out, light leaves on the inside
+            let normal = outward * radius.signum();
+            let point = *center + radius.abs() * outward;
+            (point, normal, true, 1. / (4. * PI * radius * radius))
+        }
+        _ => {
+            let (point, normal) = sample_surface(light);
+            let front_face = sides(material) == 1. || random_double() < 0.5;
+            let normal = if front_face { normal } else { -normal };
+            (
+                point,
+                normal,
+                front_face,
+                1. / (sides(material) * area(light)),
+            )
+        }
+    };
+
+    let mut dir = normal + random_unit_vector();
+    if dir.close_to_zero() {
+        dir = normal;
+    }
+    let dir = unit_vector(&dir);
+
+    Some(Emission {
+        point,
+        normal,
+        material,
+        radiance: color_emitted(material, front_face),
+        pdf_position,
+        dir,
+        pdf_direction: f64::max(dot(&normal, &dir), 0.) / PI,
+        punctual: false,
+    })
+}
+
+/// Densities of `emit` for the point and direction, if the point lies on the light.
+fn emission_pdf(light: &Hittables, point: Vec3, dir: Vec3) -> Option<(f64, f64)> {
+    let (pdf_position, normal) = match light {
+        Hittables::Sphere(center, radius, _) if on_sphere(*center, *radius, point) => {
+            let normal = (point - *center) / *radius;
+            (1. / (4. * PI * radius * radius), normal)
+        }
+        Hittables::Sphere(..) => return None,
+        _ if contains(light, point) => {
+            let material = material(light)?;
+            let normal = surface_normal(light);
+            let normal = if dot(&normal, &dir) < 0. && sides(material) == 2. {
+                -normal
+            } else {
+                normal
+            };
+            (1. / (sides(material) * area(light)), normal)
+        }
+        _ => return None,
+    };
+    Some((pdf_position, f64::max(dot(&normal, &dir), 0.) / PI))
+}
+
+fn material(light: &Hittables) -> Option<Material> {
+    match light {
+        Hittables::Sphere(_, _, material)
+        | Hittables::Quad(_, _, _, material)
+        | Hittables::Disk(_, _, _, material)
+        | Hittables::Triangle(_, _, _, material)
+        | Hittables::Mesh(_, material) => Some(*material),
+        _ => None,
+    }
+}
+
+/// Sides a flat light emits on.
+fn sides(material: Material) -> f64 {
+    match material {
+        Material::OneSidedLight(..) => 1.,
+        _ => 2.,
+    }
+}
+
+/// Luminance of the light a material emits.
+fn luminance(material: Material) -> f64 {
+    match material {
+        Material::Light(r, g, b) | Material::OneSidedLight(r, g, b) => Vec3(r, g, b).luminance(),
+        Material::SpectralLight(illuminant, scale) => emission(illuminant, scale).luminance(),
+        _ => 0.,
+    }
+}
+
+/// Power the light emits, by luminance. A Lambertian emitter sends π times its
+/// radiance through each unit of area.
+fn power(light: &Hittables) -> f64 {
+    let material = match material(light) {
+        Some(material) => material,
+        None => return 0.,
+    };
+    let sides = match light {
+        Hittables::Sphere(..) => 1.,
+        _ => sides(material),
+    };
+    luminance(material) * PI * area(light) * sides
+}
+
+/// Power of a point or spot light, none for far away lights, which start no light
+/// paths.
+fn punctual_power(light: &Punctual) -> f64 {
+    match *light {
+        Punctual::Point(_, material) => 4. * PI * luminance(material),
+        Punctual::Spot(_, _, inner, outer, material) => {
+            //As if the intensity fell off linearly in the cosine
+            let cos_inner = f64::cos(degrees_to_radians(inner));
+            let cos_outer = f64::cos(degrees_to_radians(outer));
+            2. * PI * luminance(material) * (1. - (cos_inner + cos_outer) / 2.)
+        }
+        Punctual::Directional(..) => 0.,
+    }
+}
+
+fn light_bounds(light: &Hittables) -> Option<LightBounds> {
+    let material = material(light)?;
+    let (w, cos_theta_o, two_sided) = match light {
+        Hittables::Sphere(..) => (Vec3(0., 0., 1.), -1., false),
+        _ => (surface_normal(light), 1., sides(material) == 2.),
+    };
+    Some(LightBounds {
+        bounds: bounding_box(light)?,
+        phi: power(light),
+        w,
+        cos_theta_o,
+        cos_theta_e: 0.,
+        two_sided,
+    })
+}
+
+/// Bounds of point and spot lights, far away lights have none.
+fn punctual_bounds(light: &Punctual) -> Option<LightBounds> {
+    let (position, w, cos_theta_o, cos_theta_e) = match *light {
+        Punctual::Point(position, _) => (position, Vec3(0., 0., 1.), -1., 0.),
+        Punctual::Spot(position, axis, inner, outer, _) => (
+            position,
+            unit_vector(&axis),
+            f64::cos(degrees_to_radians(inner)),
+            f64::cos(degrees_to_radians(outer - inner)),
+        ),
+        Punctual::Directional(..) => return None,
+    };
+    Some(LightBounds {
+        bounds: Aabb::new(position, position),
+        phi: punctual_power(light),
+        w,
+        cos_theta_o,
+        cos_theta_e,
+        two_sided: false,
+    })
+}
+
+fn area(light: &Hittables) -> f64 {
+    match light {
+        Hittables::Sphere(_, radius, _) => 4. * PI * radius * radius,
+        Hittables::Quad(_, u, v, _) => cross(u, v).length(),
+        Hittables::Disk(_, _, radius, _) => PI * radius * radius,
+        Hittables::Triangle(a, b, c, _) => 0.5 * cross(&(*b - *a), &(*c - *a)).length(),
+        Hittables::Mesh(mesh, _) => mesh.area(),
+        _ => 0.,
+    }
+}
+
+/// Unit normal on the front face of a flat light.
+fn surface_normal(light: &Hittables) -> Vec3 {
+    match light {
+        Hittables::Quad(_, u, v, _) => unit_vector(&cross(u, v)),
+        Hittables::Disk(_, normal, _, _) => unit_vector(normal),
+        Hittables::Triangle(a, b, c, _) => unit_vector(&cross(&(*b - *a), &(*c - *a))),
+        _ => Vec3(0., 0., 0.),
+    }
+}
+
+/// Point uniformly distributed over the area of a flat light, and its normal.
+fn sample_surface(light: &Hittables) -> (Vec3, Vec3) {
+    let point = match light {
+        Hittables::Quad(q, u, v, _) => *q + random_double() * *u + random_double() * *v,
+        Hittables::Disk(center, normal, radius, _) => {
+            let r = radius * f64::sqrt(random_double());
+            let phi = 2. * PI * random_double();
+            let (t, b) = orthonormal_basis(&unit_vector(normal));
+            *center + r * f64::cos(phi) * t + r * f64::sin(phi) * b
+        }
+        Hittables::Triangle(a, b, c, _) => {
+            let su = f64::sqrt(random_double());
+            let (u, v) = (1. - su, random_double() * su);
+            u * *a + v * *b + (1. - u - v) * *c
+        }
+        _ => Vec3(0., 0., 0.),
+    };
+    (point, surface_normal(light))
+}
+
+/// Whether the point lies on a flat light, up to rounding.
+fn contains(light: &Hittables, point: Vec3) -> bool {
+    let eps = 1e-6;
+    let in_plane = |origin: Vec3, normal: Vec3| {
+        f64::abs(dot(&(point - origin), &unit_vector(&normal)))
+            <= eps * f64::max(1., (point - origin).length())
+    };
+    //Coordinates along the edges, as in the intersection of the quad
+    let planar = |origin: Vec3, u: Vec3, v: Vec3| {
+        let n = cross(&u, &v);
+        let w = n / n.length_squared();
+        let p = point - origin;
+        (dot(&w, &cross(&p, &v)), dot(&w, &cross(&u, &p)))
+    };
+    match light {
+        Hittables::Quad(q, u, v, _) => {
+            let (alpha, beta) = planar(*q, *u, *v);
+            in_plane(*q, cross(u, v))
+                && (-eps..=1. + eps).contains(&alpha)
+                && (-eps..=1. + eps).contains(&beta)
+        }
+        Hittables::Disk(center, normal, radius, _) => {
+            in_plane(*center, *normal)
+                && (point - *center).length_squared() <= radius * radius * (1. + eps)
+        }
+        Hittables::Triangle(a, b, c, _) => {
+            let (alpha, beta) = planar(*a, *b - *a, *c - *a);
+            in_plane(*a, cross(&(*b - *a), &(*c - *a)))
+                && alpha >= -eps
+                && beta >= -eps
+                && alpha + beta <= 1. + eps
+        }
+        _ => false,
+    }
+}
+
+fn sample_light(light: &Hittables, point: Vec3) -> Option<LightSample> {
+    match light {
+        Hittables::Sphere(center, radius, material) => {
+            let radius = radius.abs();
+            let to_center = *center - point;
+            let distance_squared = to_center.length_squared();
+
+            let (dir, distance) = if distance_squared > radius * radius {
+                //Directions in the cone the sphere covers, uniformly
+                let cos_max = f64::sqrt(1. - radius * radius / distance_squared);
+                let dir = sample_cone(unit_vector(&to_center), cos_max);
+                let (near, _) = sphere_roots(*center, radius, &Ray { origin: point, dir })?;
+                (dir, near)
+            } else {
+                //Inside the sphere every point is visible, sample the surface uniformly
+                let offset = *center + radius * random_unit_vector() - point;
+                let distance = offset.length();
+                (offset / distance, distance)
+            };
+
+            let light_point = point + distance * dir;
+            let pdf = sphere_pdf(*center, radius, point, light_point);
+            (pdf > 0.).then_some(LightSample {
+                dir,
+                distance,
+                radiance: color_emitted(*material, true),
+                pdf,
+                normal: unit_vector(&(light_point - *center)),
+                punctual: false,
+            })
+        }
+        Hittables::Quad(..) | Hittables::Disk(..) | Hittables::Triangle(..) => {
+            let material = material(light)?;
+            let (light_point, normal) = sample_surface(light);
+            let offset = light_point - point;
+            let distance = offset.length();
+            let dir = offset / distance;
+
+            //Seen from the front when the direction towards the light opposes its normal
+            let cos_light = -dot(&normal, &dir);
+            let radiance = color_emitted(material, cos_light > 0.);
+            if f64::abs(cos_light) < 1e-9 || radiance.max_component() <= 0. {
+                return None;
+            }
+            Some(LightSample {
+                dir,
+                distance,
+                radiance,
+                pdf: distance * distance / (f64::abs(cos_light) * area(light)),
+                normal,
+                punctual: false,
+            })
+        }
+        _ => None,
+    }
+}
+
+fn sample_punctual(light: &Punctual, point: Vec3) -> Option<LightSample> {
+    let (dir, distance, radiance, pdf) = match *light {
+        Punctual::Point(position, material) | Punctual::Spot(position, _, _, _, material) => {
+            let offset = position - point;
+            let distance = offset.length();
+            let dir = offset / distance;
+            let intensity = color_emitted(material, true) * spot_falloff(light, -dir);
+            if distance == 0. || intensity.max_component() <= 0. {
+                return None;
+            }
+            (dir, distance, intensity / (distance * distance), 1.)
+        }
+        Punctual::Directional(towards, diameter, material) => {
+            let towards = unit_vector(&towards);
+            let irradiance = color_emitted(material, true);
+            let cos_max = f64::cos(degrees_to_radians(diameter / 2.));
+            if cos_max >= 1. {
+                (towards, f64::INFINITY, irradiance, 1.)
+            } else {
+                //A disk of even radiance, adding up to the irradiance
+                let pdf = 1. / (2. * PI * (1. - cos_max));
+                let dir = sample_cone(towards, cos_max);
+                (dir, f64::INFINITY, irradiance * pdf, pdf)
+            }
+        }
+    };
+    Some(LightSample {
+        dir,
+        distance,
+        radiance,
+        pdf,
+        normal: -dir,
+        punctual: true,
+    })
+}
+
+/// Light leaving a point or spot light in a random direction. Far away lights have
+/// no point to leave from.
+fn emit_punctual(light: &Punctual) -> Option<Emission> {
+    let (point, dir, pdf_direction, material) = match *light {
+        Punctual::Point(position, material) => {
+            (position, random_unit_vector(), 1. / (4. * PI), material)
+        }
+        Punctual::Spot(position, axis, _, outer, material) => {
+            let cos_max = f64::cos(degrees_to_radians(outer));
+            let dir = sample_cone(unit_vector(&axis), cos_max);
+            (position, dir, 1. / (2. * PI * (1. - cos_max)), material)
+        }
+        Punctual::Directional(..) => return None,
+    };
+    Some(Emission {
+        point,
+        normal: dir,
+        material,
+        radiance: color_emitted(material, true) * spot_falloff(light, dir),
+        pdf_position: 1.,
+        dir,
+        pdf_direction,
+        punctual: true,
+    })
+}
+
+/// Densities of `emit_punctual` for the direction, if the point is the light's.
+fn punctual_emission_pdf(light: &Punctual, point: Vec3, dir: Vec3) -> Option<(f64, f64)> {
+    let at = |position: Vec3| (point - position).length() <= 1e-6 * f64::max(1., position.length());
+    match *light {
+        Punctual::Point(position, _) if at(position) => Some((1., 1. / (4. * PI))),
+        Punctual::Spot(position, axis, _, outer, _) if at(position) => {
+            let cos_max = f64::cos(degrees_to_radians(outer));
+            let inside = dot(&unit_vector(&axis), &dir) >= cos_max;
+            Some((
+                1.,
+                if inside {
+                    1. / (2. * PI * (1. - cos_max))
+                } else {
+                    0.
+                },
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Fraction of the intensity of a spot light leaving in the unit direction `dir`,
+/// smoothly fading between its angles. One for other lights.
+fn spot_falloff(light: &Punctual, dir: Vec3) -> f64 {
+    let (axis, inner, outer) = match *light {
+        Punctual::Spot(_, axis, inner, outer, _) => (axis, inner, outer),
+        _ => return 1.,
+    };
+    let cos_theta = dot(&unit_vector(&axis), &dir);
+    let cos_inner = f64::cos(degrees_to_radians(inner));
+    let cos_outer = f64::cos(degrees_to_radians(outer));
+    if cos_theta >= cos_inner {
+        1.
+    } else if cos_theta <= cos_outer {
+        0.
+    } else {
+        let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+        t * t * (3. - 2. * t)
+    }
+}
+
+/// Unit direction uniformly distributed in the cone around the unit `axis` out to the
+/// angle with cosine `cos_max`.
+fn sample_cone(axis: Vec3, cos_max: f64) -> Vec3 {
+    let cos_theta = 1. - random_double() * (1. - cos_max);
+    let sin_theta = f64::sqrt(f64::max(0., 1. - cos_theta * cos_theta));
+    let phi = 2. * PI * random_double();
+    let (t, b) = orthonormal_basis(&axis);
+    sin_theta * f64::cos(phi) * t + sin_theta * f64::sin(phi) * b + cos_theta * axis
+}
+
+fn light_pdf(light: &Hittables, point: Vec3, light_point: Vec3) -> f64 {
+    match light {
+        Hittables::Sphere(center, radius, _) if on_sphere(*center, *radius, light_point) => {
+            sphere_pdf(*center, radius.abs(), point, light_point)
+        }
+        Hittables::Sphere(..) => 0.,
+        _ if contains(light, light_point) => {
+            let offset = light_point - point;
+            let cos_light = f64::abs(dot(&surface_normal(light), &unit_vector(&offset)));
+            if cos_light < 1e-9 {
+                return 0.;
+            }
+            offset.length_squared() / (cos_light * area(light))
+        }
+        _ => 0.,
+    }
+}
+
+fn on_sphere(center: Vec3, radius: f64, point: Vec3) -> bool {
+    let radius = radius.abs();
+    ((point - center).length() - radius).abs() <= 1e-6 * f64::max(radius, 1.)
+}
+
+/// Solid angle density of the sphere sampling in `sample_light`.
+fn sphere_pdf(center: Vec3, radius: f64, point: Vec3, light_point: Vec3) -> f64 {
+    let distance_squared = (center - point).length_squared();
+    if distance_squared > radius * radius {
+        let cos_max = f64::sqrt(1. - radius * radius / distance_squared);
+        1. / (2. * PI * (1. - cos_max))
+    } else {
+        //Area density converted to solid angle
+        let offset = light_point - point;
+        let cos_light = dot(&unit_vector(&offset), &unit_vector(&(light_point - center))).abs();
+        if cos_light < 1e-9 {
+            return 0.;
+        }
+        offset.length_squared() / (cos_light * 4. * PI * radius * radius)
+    }
+}
+
+#[cfg(test)]
+mod lights_tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use crate::{
+        hittables::xz_rect,
+        materials::Material::{Lambertian, Light, OneSidedLight},
+        mesh::Mesh,
+        rand,
+        scenes::SceneKind,
+    };
+
+    //Flat lights of every kind, facing down, one-sided and two-sided
+    fn area_lights() -> Vec<Hittables> {
+        vec![
+            xz_rect(-1., 1., -1., 1., 3., Light(2., 2., 2.)),
+            Hittables::Disk(
+                Vec3(2., 2., 0.),
+                Vec3(0., -1., 0.),
+                0.5,
+                OneSidedLight(1., 1., 1.),
+            ),
+            Hittables::Triangle(
+                Vec3(-2., 2., 0.),
+                Vec3(-3., 2., 1.),
+                Vec3(-3., 2., -1.),
+                OneSidedLight(1., 1., 1.),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_collects_light_spheres() {
+        let world = Hittables::HittableObjects(vec![
+            Hittables::Sphere(Vec3(0., 0., 0.), 1., Lambertian(0.5, 0.5, 0.5)),
+            Hittables::HittableObjects(vec![Hittables::Sphere(
+                Vec3(0., 3., 0.),
+                0.5,
+                Light(2., 2., 2.),
+            )]),
+        ]);
+        let lights = Lights::new(&world);
+        assert_eq!(lights.lights.len(), 1);
+
+        //Meshes become a light per triangle
+        let square = Mesh::new(
+            vec![
+                Vec3(0., 0., 0.),
+                Vec3(1., 0., 0.),
+                Vec3(1., 1., 0.),
+                Vec3(0., 1., 0.),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+        );
+        let mut world = area_lights();
+        world.push(Hittables::Mesh(Arc::new(square), Light(1., 1., 1.)));
+        let lights = Lights::new(&Hittables::HittableObjects(world));
+        assert_eq!(lights.lights.len(), 5);
+        assert!(Lights::new(&Hittables::HittableObjects(vec![]))
+            .lights
+            .is_empty());
+    }
+
+    #[test]
+    fn test_sample_pdf_matches_pdf() {
+        let mut world = area_lights();
+        world.push(Hittables::Sphere(Vec3(0., 3., 0.), 0.5, Light(2., 2., 2.)));
+        world.push(Hittables::Sphere(Vec3(0., 0., 0.), 10., Light(1., 1., 1.)));
+        let lights = Lights::new(&Hittables::HittableObjects(world));
+        rand::seed(3, &[]);
+        for point in [Vec3(0., 0., 0.), Vec3(1., 0.5, 2.), Vec3(0.5, 4., 0.)] {
+            for _ in 0..100 {
+                //One-sided lights seen from behind give no samples
+                let sample = match lights.sample(point) {
+                    Some(sample) => sample,
+                    None => continue,
+                };
+                let light_point = point + sample.distance * sample.dir;
+                let pdf = lights.pdf(point, light_point);
+                assert!(
+                    f64::abs(pdf - sample.pdf) < 1e-6 * pdf,
+                    "{} != {}",
+                    pdf,
+                    sample.pdf
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_emission_leaves_the_light() {
+        let mut world = area_lights();
+        world.push(Hittables::Sphere(Vec3(0., 3., 0.), 0.5, Light(2., 2., 2.)));
+        world.push(Hittables::Sphere(Vec3(0., 0., 0.), -10., Light(1., 1., 1.)));
+        let lights = Lights::new(&Hittables::HittableObjects(world));
+        rand::seed(4, &[]);
+        let mut upwards = 0;
+        for _ in 0..1000 {
+            let emission = lights.sample_emission().unwrap();
+            let (pdf_position, pdf_direction) = lights.pdf_emission(emission.point, emission.dir);
+            assert!(f64::abs(pdf_position - emission.pdf_position) < 1e-12);
+            assert!(f64::abs(pdf_direction - emission.pdf_direction) < 1e-12);
+            assert!(dot(&emission.dir, &emission.normal) >= 0.);
+            //Light from the inside out sphere goes towards its center
+            if emission.point.length() > 9. {
+                assert!(dot(&emission.normal, &emission.point) < 0.);
+            }
+            //One-sided lights only shine down, the two-sided one both ways
+            if f64::abs(emission.point.1 - 2.) < 1e-9 {
+                assert!(emission.normal.1 < 0.);
+            }
+            if f64::abs(emission.point.1 - 3.) < 1e-9 && emission.normal.1 > 0. {
+                upwards += 1;
+            }
+        }
+        assert!(upwards > 0);
+        assert_eq!(
+            lights.pdf_emission(Vec3(0., 1., 0.), Vec3(0., 1., 0.)),
+            (0., 0.)
+        );
+    }
+
+    #[test]
+    fn test_emission_pdf_counts_every_light_at_the_point() {
+        //Either sphere could have emitted from the point, together they always do
+        let lights = Lights::new(&Hittables::HittableObjects(vec![
+            Hittables::Sphere(Vec3(0., 0., 0.), 1., Light(1., 1., 1.)),
+            Hittables::Sphere(Vec3(0., 0., 0.), 1., Light(3., 3., 3.)),
+        ]));
+        let (pdf_position, pdf_direction) =
+            lights.pdf_emission(Vec3(0., 1., 0.), unit_vector(&Vec3(0., 1., 1.)));
+        assert!(f64::abs(pdf_position - 1. / (4. * PI)) < 1e-12);
+        assert!(f64::abs(pdf_direction - f64::sqrt(0.5) / PI) < 1e-12);
+    }
+
+    #[test]
+    fn test_cone_covers_solid_angle() {
+        //Far away, the cone density is one over the solid angle of the sphere
+        let lights = Lights::new(&Hittables::Sphere(
+            Vec3(0., 0., -100.),
+            1.,
+            Light(1., 1., 1.),
+        ));
+        let pdf = lights.pdf(Vec3(0., 0., 0.), Vec3(0., 0., -99.));
+        let solid_angle = PI * 1. / (100. * 100.);
+        assert!(f64::abs(pdf * solid_angle - 1.) < 1e-3);
+    }
+
+    #[test]
+    fn test_one_sided_lights_are_dark_behind() {
+        let lights = Lights::new(&area_lights()[1]);
+        rand::seed(5, &[]);
+        assert!(lights.sample(Vec3(2., 0., 0.)).is_some());
+        assert!(lights.sample(Vec3(2., 4., 0.)).is_none());
+        assert_eq!(
+            color_emitted(OneSidedLight(1., 1., 1.), false),
+            Vec3(0., 0., 0.)
+        );
+    }
+
+    #[test]
+    fn test_punctual_lights() {
+        let origin = Vec3(0., 0., 0.);
+        rand::seed(7, &[]);
+
+        //Intensity over the squared distance
+        let point = sample_punctual(
+            &Punctual::Point(Vec3(0., 2., 0.), Light(4., 4., 4.)),
+            origin,
+        );
+        let point = point.unwrap();
+        assert_eq!(point.dir, Vec3(0., 1., 0.));
+        assert_eq!(point.radiance, Vec3(1., 1., 1.));
+        assert!(point.punctual && point.distance == 2. && point.pdf == 1.);
+
+        //Full inside the inner angle, fading out to the outer one
+        let spot = Punctual::Spot(
+            Vec3(0., 2., 0.),
+            Vec3(0., -1., 0.),
+            30.,
+            60.,
+            Light(4., 4., 4.),
+        );
+        let radiance = |point: Vec3| sample_punctual(&spot, point).map(|sample| sample.radiance.0);
+        assert_eq!(radiance(origin), Some(1.));
+        let fading = radiance(Vec3(2., 0., 0.)).unwrap();
+        assert!(0. < fading && fading < 4. / 8.);
+        assert_eq!(radiance(Vec3(4., 0., 0.)), None);
+
+        //Far away, the irradiance spread over the disk of the sun
+        let sun = Punctual::Directional(Vec3(0., 1., 0.), 0.53, Light(2., 2., 2.));
+        let cos_max = f64::cos(degrees_to_radians(0.53 / 2.));
+        for _ in 0..100 {
+            let sample = sample_punctual(&sun, origin).unwrap();
+            assert!(sample.distance.is_infinite());
+            assert!(sample.dir.1 >= cos_max - 1e-12);
+            assert!(f64::abs(sample.radiance.0 / sample.pdf - 2.) < 1e-9);
+        }
+        let sun = Punctual::Directional(Vec3(0., 1., 0.), 0., Light(2., 2., 2.));
+        let sample = sample_punctual(&sun, origin).unwrap();
+        assert_eq!(sample.dir, Vec3(0., 1., 0.));
+        assert_eq!(sample.radiance, Vec3(2., 2., 2.));
+    }
+
+    #[test]
+    fn test_punctual_emission() {
+        let mut lights = Lights::new(&area_lights()[0]);
+        let point = Punctual::Point(Vec3(0., 1., 0.), Light(1., 1., 1.));
+        lights.add(point);
+        lights.add(Punctual::Spot(
+            Vec3(2., 1., 0.),
+            Vec3(1., 0., 0.),
+            10.,
+            20.,
+            Light(1., 1., 1.),
+        ));
+        lights.add(Punctual::Directional(
+            Vec3(0., 1., 0.),
+            0.,
+            Light(1., 1., 1.),
+        ));
+        //Lights are picked by their power, the sun never as it emits nothing
+        let share = punctual_power(&point) / lights.power.last().unwrap();
+        rand::seed(8, &[]);
+        let n = 2000;
+        let mut points = 0;
+        for _ in 0..n {
+            let emission = lights.sample_emission().unwrap();
+            let (pdf_position, pdf_direction) = lights.pdf_emission(emission.point, emission.dir);
+            assert!(f64::abs(pdf_position - emission.pdf_position) < 1e-12);
+            assert!(f64::abs(pdf_direction - emission.pdf_direction) < 1e-12);
+            if emission.punctual {
+                assert_eq!(emission.normal, emission.dir);
+                //The spot stays within its outer angle
+                if emission.point.0 == 2. {
+                    assert!(emission.dir.0 >= f64::cos(degrees_to_radians(20.)) - 1e-12);
+                } else {
+                    points += 1;
+                    assert!(f64::abs(emission.pdf_position - share) < 1e-12);
+                }
+            }
+        }
+        let expected = share * n as f64;
+        assert!(f64::abs(points as f64 - expected) < 4. * expected.sqrt());
+    }
+
+    #[test]
+    fn test_nearby_lights_are_favoured() {
+        //A row of small lights, each picked most often by the points beside it
+        let lights: Vec<Hittables> = (0..64)
+            .map(|i| {
+                let x = i as f64;
+                xz_rect(x, x + 0.5, 0., 0.5, 1., Light(1., 1., 1.))
+            })
+            .collect();
+        let lights = Lights::new(&Hittables::HittableObjects(lights));
+        for (i, x) in [(3, 3.25), (40, 40.25)] {
+            let point = Vec3(x, 0., 0.25);
+            let total: f64 = (0..64).map(|light| lights.pmf(point, light)).sum();
+            assert!(f64::abs(total - 1.) < 1e-9);
+            let near = lights.pmf(point, i);
+            assert!((0..64).all(|light| lights.pmf(point, light) <= near));
+            assert!(near > 10. / 64.);
+        }
+
+        //Through the shadow rays, the pdf of the picked light adds its pmf
+        rand::seed(3, &[]);
+        let point = Vec3(10.25, 0., 0.25);
+        for _ in 0..100 {
+            let sample = lights.sample(point).unwrap();
+            let light_point = point + sample.distance * sample.dir;
+            assert!(f64::abs(lights.pdf(point, light_point) - sample.pdf) < 1e-6 * sample.pdf);
+        }
+    }
+
+    #[test]
+    fn test_tree_beats_uniform_picking() {
+        //Light on the ground between the spheres of the lamps scene, shadows left out,
+        //estimated with one lamp picked through the tree or picked uniformly
+        let lights = SceneKind::Lamps.build(1.).unwrap().lights;
+        let count = lights.lights.len();
+        assert!(count > 30);
+        let estimate =
+            |sample: LightSample| sample.radiance.luminance() * sample.dir.1 / sample.pdf;
+        let statistics = |values: &[f64]| {
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            let variance =
+                values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+            (mean, variance)
+        };
+
+        rand::seed(9, &[]);
+        let n = 4000;
+        for point in [
+            Vec3(0.75, 0., 0.75),
+            Vec3(-5.25, 0., 3.75),
+            Vec3(6.75, 0., -2.25),
+        ] {
+            let (mut tree, mut uniform) = (Vec::new(), Vec::new());
+            for _ in 0..n {
+                tree.push(lights.sample(point).map_or(0., estimate));
+                let light = usize::min((random_double() * count as f64) as usize, count - 1);
+                uniform.push(
+                    sample_light(&lights.lights[light], point)
+                        .map_or(0., |sample| estimate(sample) * count as f64),
+                );
+            }
+            let (tree_mean, tree_variance) = statistics(&tree);
+            let (uniform_mean, uniform_variance) = statistics(&uniform);
+            eprintln!(
+                "{} {} {} {}",
+                tree_mean, uniform_mean, tree_variance, uniform_variance
+            );
+            //Both see the same light, the tree with far less noise
+            let error = 4. * f64::sqrt((tree_variance + uniform_variance) / n as f64);
+            assert!(f64::abs(tree_mean - uniform_mean) < error);
+            assert!(tree_variance < 0.25 * uniform_variance);
+        }
+    }
+
+    #[test]
+    fn test_power_units() {
+        //Radiance is power over π, the area and the sides emitting
+        let quad = with_power(xz_rect(0., 2., 0., 1., 3., Light(1., 0.5, 0.5)), 100.);
+        let color = color_emitted(material(&quad).unwrap(), true);
+        assert!(f64::abs(color.luminance() - 100. / (PI * 2. * 2.)) < 1e-9);
+        assert!(f64::abs(color.0 - 2. * color.1) < 1e-9);
+
+        let disk = with_power(
+            Hittables::Disk(
+                Vec3(0., 0., 0.),
+                Vec3(0., 1., 0.),
+                1.,
+                OneSidedLight(1., 1., 1.),
+            ),
+            10.,
+        );
+        let color = color_emitted(material(&disk).unwrap(), true);
+        assert!(f64::abs(color.0 - 10. / (PI * PI)) < 1e-9);
+
+        //The light reaching a big sphere around the light adds up to its power
+        let lights = Lights::new(&quad);
+        rand::seed(6, &[]);
+        let n = 20_000;
+        let mut power = 0.;
+        for _ in 0..n {
+            let emission = lights.sample_emission().unwrap();
+            let cosine = dot(&emission.normal, &emission.dir);
+            power += emission.radiance.luminance() * cosine
+                / (emission.pdf_position * emission.pdf_direction);
+        }
+        let power = power / n as f64;
+        assert!(f64::abs(power - 100.) < 2., "{}", power);
+    }
+}
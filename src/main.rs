@@ -1,233 +1,389 @@
 use std::{
-    f64::INFINITY,
+    env, fs,
     io::{stdout, Stdout, Write},
+    net::TcpListener,
+    process,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
 };
 
-use image::{Rgb, RgbImage};
-use rayon::prelude::*;
+use signal_hook::consts::SIGINT;
 
-use crate::hittables::Hittables::*;
-use crate::materials::Material::{Dielectric, Lambertian, Metal};
-use camera::Camera;
+use checkpoint::Checkpoint;
 use crossterm::style::Stylize;
-use hittable::Hit;
-use hittables::{hit, Hittables};
-use materials::{color_emitted, scatter};
-use ray::Ray;
-use utils::{clamp, random_double};
+use framebuffer::{color_rgb, save_image, Framebuffer};
+use options::{Options, USAGE};
+use preview::Preview;
+use progress::Progress;
+use render::{render_tile, Settings};
+use stats::Report;
+use tiles::{tiles, Tile};
 use vec3::Vec3;
 
+mod aabb;
+mod bidirectional;
 mod camera;
+mod checkpoint;
+mod csg;
+mod distributed;
 mod file;
+mod framebuffer;
 mod hittable;
 mod hittables;
+mod integrator;
+mod light_bvh;
+mod lights;
 mod materials;
+mod medium;
+mod mesh;
+mod metropolis;
+mod options;
+mod photons;
+mod polynomial;
+mod preview;
+mod progress;
 mod rand;
 mod ray;
+mod render;
+mod scenes;
+mod sdf;
+mod sky;
+mod spectrum;
+mod stats;
+mod tiles;
 mod utils;
 mod vec3;
+mod voxel;
 
-fn ray_color(ray: &Ray, world: &Hittables, depth: i32) -> Vec3 {
-    if depth <= 0 {
-        return Vec3(0., 0., 0.);
-    }
+const ASPECT_RATIO: f64 = 16. / 9.;
 
-    let mut rec = Hit {
-        point: Vec3(0., 0., 0.),
-        normal: Vec3(0., 0., 0.),
-        t: 0.,
-        front_face: true,
-        material: materials::Material::Init,
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(1);
+        }
     };
-
-    if !hit(world, &ray, 0.001, INFINITY, &mut rec) {
-        return Vec3(0.0, 0.0, 0.0);
+    if options.help {
+        println!("{}", USAGE);
+        return;
     }
 
-    let mut scattered = Ray {
-        origin: Vec3(0., 0., 0.),
-        dir: Vec3(0., 0., 0.),
-    };
-
-    let mut attenuation = Vec3(0., 0., 0.);
-    let emitted = color_emitted(rec.material);
-
-    if !scatter(rec.material, ray, &rec, &mut attenuation, &mut scattered) {
-        return emitted;
+    let phase_start = Instant::now();
+    let mut scene = options.scene.build(ASPECT_RATIO).unwrap_or_else(|e| {
+        eprintln!("Cannot build the scene: {}", e);
+        process::exit(1);
+    });
+    scene.fog = options.fog;
+    let scene_time = phase_start.elapsed();
+
+    //Workers get the settings from the coordinator
+    if let Some(address) = &options.worker {
+        println!("Rendering for {}.", address);
+        if let Err(e) = distributed::work(address, |_| scene) {
+            eprintln!("Worker stopped: {}", e);
+            process::exit(1);
+        }
+        return;
     }
 
-    emitted + attenuation * ray_color(&scattered, world, depth - 1)
-}
+    //Image
+    let image_width = options.width;
+    let image_height = (image_width as f64 / ASPECT_RATIO) as i32;
+    let samples_per_pixel = options.samples_per_pixel;
+    let max_depth = options.max_depth;
 
-fn color_rgb(vec: &Vec3, samples_per_pixel: i32) -> (f64, f64, f64) {
-    let mut r = vec.0;
-    let mut g = vec.1;
-    let mut b = vec.2;
+    let mut phases = vec![("scene", scene_time)];
 
-    let scale = 1.0 / samples_per_pixel as f64;
+    //Render
+    let mut stdout = stdout();
 
-    r = f64::sqrt(scale * r);
-    g = f64::sqrt(scale * g);
-    b = f64::sqrt(scale * b);
+    //Ctrl+C stops the render and saves what is done, a second one exits right away
+    let interrupted = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register_conditional_shutdown(SIGINT, 130, Arc::clone(&interrupted))
+        .unwrap();
+    signal_hook::flag::register(SIGINT, Arc::clone(&interrupted)).unwrap();
+
+    let start = Instant::now();
+    let stopped = || {
+        interrupted.load(Ordering::Relaxed)
+            || options
+                .time_budget
+                .is_some_and(|budget| start.elapsed() >= budget)
+    };
 
-    (
-        256. * clamp(r, 0., 0.999),
-        256. * clamp(g, 0., 0.999),
-        256. * clamp(b, 0., 0.999),
-    )
-}
+    //Resuming continues with the samples and the seed of the checkpoint
+    let (checkpoint, framebuffer) = match &options.resume {
+        Some(path) => {
+            let (checkpoint, framebuffer) =
+                Checkpoint::load(path, image_width as usize, image_height as usize).unwrap_or_else(
+                    |e| {
+                        eprintln!("Cannot resume from {}: {}", path, e);
+                        process::exit(1);
+                    },
+                );
+            if checkpoint.max_depth != max_depth
+                || checkpoint.russian_roulette_depth != options.russian_roulette_depth
+                || checkpoint.integrator != options.integrator
+                || checkpoint.spectral != options.spectral
+            {
+                eprintln!(
+                    "Cannot resume from {}: it was rendered with depth {}, \
+                     roulette depth {} and integrator {}{}.",
+                    path,
+                    checkpoint.max_depth,
+                    checkpoint.russian_roulette_depth,
+                    checkpoint.integrator,
+                    if checkpoint.spectral {
+                        ", spectral"
+                    } else {
+                        ""
+                    }
+                );
+                process::exit(1);
+            }
+            (checkpoint, framebuffer)
+        }
+        None => (
+            Checkpoint {
+                max_depth,
+                russian_roulette_depth: options.russian_roulette_depth,
+                seed: options.seed,
+                integrator: options.integrator,
+                spectral: options.spectral,
+            },
+            Framebuffer::new(image_width as usize, image_height as usize),
+        ),
+    };
+    let checkpoint_path = options.checkpoint.as_ref().or(options.resume.as_ref());
+
+    //Look-dev renders only a part of the frame
+    let frame = Tile::image(image_width as usize, image_height as usize);
+    let region = options.region.unwrap_or(frame);
+    if !frame.contains(&region) {
+        eprintln!(
+            "The region does not fit into the {}x{} image.",
+            frame.width, frame.height
+        );
+        process::exit(1);
+    }
+    let composite = options.composite.as_ref().map(|path| {
+        let image = image::open(path).map(|image| image.to_rgb8());
+        match image {
+            Ok(image) if image.dimensions() == (frame.width as u32, frame.height as u32) => image,
+            Ok(image) => {
+                eprintln!(
+                    "Cannot composite into {}: it is {}x{}, not {}x{}.",
+                    path,
+                    image.width(),
+                    image.height(),
+                    frame.width,
+                    frame.height
+                );
+                process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Cannot composite into {}: {}", path, e);
+                process::exit(1);
+            }
+        }
+    });
+    let output_image = |framebuffer: &Framebuffer| match (&options.region, &composite) {
+        (Some(region), Some(composite)) => {
+            let mut image = composite.clone();
+            framebuffer.composite(region, &mut image);
+            image
+        }
+        (Some(region), None) => framebuffer.region_image(region),
+        (None, _) => framebuffer.to_image(),
+    };
 
-fn main() {
-    //Image
-    let aspect_ratio = 16. / 9.;
-    let image_width = 1920;
-    let image_height = (image_width as f64 / aspect_ratio) as i32;
-    let samples_per_pixel = 16;
-    let max_depth = 8;
-
-    let mut hittables = Vec::new();
-
-    hittables.push(Sphere(
-        Vec3(0., -100.5, -1.),
-        100.,
-        Metal(0.8, 0.8, 0.8, 0.2),
-    ));
-    hittables.push(Sphere(Vec3(0.9, 0., -1.), 0.5, Metal(1., 1., 1., 0.0)));
-    hittables.push(Sphere(Vec3(-0.9, 0., -1.), -0.5, Dielectric(2.2)));
-    hittables.push(Sphere(
-        Vec3(0., -0.25, -0.25),
-        0.25,
-        Lambertian(0.94, 0.81, 0.66),
-    ));
-
-    hittables.push(Sphere(
-        Vec3(1.25, -0.25, -0.25),
-        0.25,
-        materials::Material::Lambertian(0.6, 0.76, 0.73),
-    ));
-    hittables.push(Sphere(
-        Vec3(-1.25, -0.25, -0.25),
-        0.25,
-        materials::Material::Lambertian(0.84, 0.55, 0.8),
-    ));
-    // hittables.push(Sphere(
-    //     Vec3(-0.25, -0.25, 0.5),
-    //     0.1,
-    //     materials::Material::Metal(1., 1., 1., 0.3),
-    // ));
-    // hittables.push(Sphere(
-    //     Vec3(0.25, -0.25, 0.5),
-    //     0.1,
-    //     materials::Material::Metal(0.71, 0.46, 0.16, 0.3),
-    // ));
-
-    // for a in -10..11 {
-    //     for b in -10..11 {
-    //         if a == -10 && b == -10 {
-    //             continue;
-    //         }
-    //         let choose_mat = random_double();
-    //         let center = Vec3(
-    //             a as f64 + random_double() * 0.5,
-    //             0.3 + random_double() * 1.5,
-    //             b as f64 + random_double() * 0.5,
-    //         );
-
-    //         if choose_mat < 0.7 {
-    //             hittables.push(Sphere(
-    //                 center,
-    //                 0.3 - random_double() * 0.1,
-    //                 Lambertian(random_double(), random_double(), random_double()),
-    //             ))
-    //         } else if choose_mat < 0.8 {
-    //             hittables.push(Sphere(
-    //                 center,
-    //                 0.3 - random_double() * 0.1,
-    //                 Metal(0.9, 0.9, 0.9, random_double() * 0.1),
-    //             ))
-    //         } else if choose_mat < 0.9 {
-    //             hittables.push(Sphere(center, 0.3, Dielectric(1.3)))
-    //         } else {
-    //             //hittables.push(Sphere(center + Vec3(0., 20., 0.), 6., Light(1., 1., 1.)));
-    //         }
-    //     }
-    // }
-
-    let world = HittableObjects(hittables);
-    //Camera
-
-    let camera = Camera::new(
-        Vec3(0., 0.2, 2.),
-        Vec3(0., 0., 0.),
-        Vec3(0., 1., 0.),
-        50.,
-        aspect_ratio,
-    );
+    //Progressive and checkpointed renders take one sample per pixel in each pass, so
+    //there is something to save in between
+    let pass_samples = if options.progressive || checkpoint_path.is_some() {
+        1
+    } else {
+        samples_per_pixel
+    };
+    let samples_done = framebuffer.tile(&region).1.into_iter().min().unwrap_or(0) as i32;
+    let samples_left = samples_per_pixel - samples_done;
+    let passes = (i32::max(samples_left, 0) + pass_samples - 1) / pass_samples;
+
+    let tiles = tiles(&region, options.tile_size as usize, options.tile_order);
+    let progress = match options.time_budget {
+        Some(budget) => Progress::timed(budget, "tiles"),
+        None => Progress::new(passes as usize * tiles.len(), "tiles"),
+    };
+    let preview = if options.preview {
+        let preview = Preview::new(image_width as usize, image_height as usize);
+        if preview.is_none() {
+            eprintln!("Preview disabled: stdout is not a terminal.");
+        }
+        preview
+    } else {
+        None
+    };
 
-    //Render
-    let mut stdout = stdout();
+    let settings = Settings {
+        width: framebuffer.width,
+        height: framebuffer.height,
+        samples_per_pixel: match options.time_budget {
+            Some(_) => u32::MAX,
+            None => samples_per_pixel as u32,
+        },
+        max_depth,
+        russian_roulette_depth: options.russian_roulette_depth,
+        seed: checkpoint.seed,
+        integrator: options.integrator,
+        spectral: options.spectral,
+    };
+    let framebuffer = Mutex::new(framebuffer);
 
-    let mut pixels: Vec<Vec<Vec3>> = vec![Vec::new(); image_height as usize];
+    //The coordinator leaves the rendering and what it takes to the workers
+    if options.coordinator.is_none() {
+        let phase_start = Instant::now();
+        if let Some(phase) = scene.prepare(&settings) {
+            phases.push((phase, phase_start.elapsed()));
+        }
+    }
 
-    pixels.par_iter_mut().enumerate().for_each(|x| {
-        for i in 0..image_width {
-            let mut pixel_color = Vec3(0., 0., 0.);
+    //Shows a rendered tile and counts it
+    let on_tile = |tile: &Tile, pixels: &[Vec3], samples: &[u32]| {
+        if let Some(preview) = &preview {
+            for row in 0..tile.height {
+                let start = row * tile.width;
+                let colors: Vec<(u8, u8, u8)> = pixels[start..start + tile.width]
+                    .iter()
+                    .zip(&samples[start..start + tile.width])
+                    .map(|(pixel, count)| color_rgb(pixel, *count))
+                    .collect();
+                preview.update(tile.x, tile.y + row, &colors);
+            }
+        }
+        progress.advance(1);
+        stats::flush();
+    };
 
-            for _ in 0..samples_per_pixel {
-                let u = (i as f64 + random_double()) / (image_width - 1) as f64;
-                let v = (x.0 as f64 + random_double()) / (image_height - 1) as f64;
+    let phase_start = Instant::now();
+
+    if let Some(address) = &options.coordinator {
+        let listener = TcpListener::bind(address).unwrap_or_else(|e| {
+            eprintln!("Cannot listen on {}: {}", address, e);
+            process::exit(1);
+        });
+        println!("Waiting for workers on {}.", listener.local_addr().unwrap());
+        if let Err(e) = distributed::coordinate(
+            &listener,
+            &scene,
+            &settings,
+            &tiles,
+            &framebuffer,
+            options.task_timeout,
+            &stopped,
+            &on_tile,
+        ) {
+            eprintln!("Cannot hand out the tiles: {}", e);
+            process::exit(1);
+        }
+    }
 
-                let r = camera.get_ray(u, v);
+    let mut last_save = Instant::now();
+    let mut pass = 0;
+
+    while options.coordinator.is_none()
+        && (options.time_budget.is_some() || pass < passes)
+        && !stopped()
+    {
+        //Threads take the next tile in order as they become free
+        let next_tile = AtomicUsize::new(0);
+        rayon::scope(|scope| {
+            for _ in 0..rayon::current_num_threads() {
+                scope.spawn(|_| {
+                    while !stopped() {
+                        let tile = match tiles.get(next_tile.fetch_add(1, Ordering::Relaxed)) {
+                            Some(tile) => tile,
+                            None => break,
+                        };
+                        let (mut pixels, mut samples) = framebuffer.lock().unwrap().tile(tile);
+                        let mut splats = Vec::new();
+                        render_tile(
+                            &scene,
+                            &settings,
+                            tile,
+                            pass_samples as u32,
+                            &mut pixels,
+                            &mut samples,
+                            &mut splats,
+                        );
+                        let mut framebuffer = framebuffer.lock().unwrap();
+                        framebuffer.set_tile(tile, &pixels, &samples);
+                        framebuffer.add_splats(&splats);
+                        drop(framebuffer);
+                        on_tile(tile, &pixels, &samples);
+                    }
+                });
+            }
+        });
+        pass += 1;
 
-                pixel_color += ray_color(&r, &world, max_depth);
+        if last_save.elapsed() >= options.save_interval {
+            let framebuffer = framebuffer.lock().unwrap();
+            if options.progressive {
+                save_image(&output_image(&framebuffer), &options.output).unwrap();
+            }
+            if let Some(path) = checkpoint_path {
+                checkpoint.save(&framebuffer, path).unwrap();
             }
-            x.1.push(pixel_color);
+            last_save = Instant::now();
         }
-    });
+    }
+    let framebuffer = framebuffer.into_inner().unwrap();
+    if let Some(preview) = &preview {
+        preview.finish();
+    }
+    progress.finish();
+    phases.push(("render", phase_start.elapsed()));
 
-    let mut img = RgbImage::new(image_width as u32, image_height as u32);
+    if interrupted.load(Ordering::Relaxed) {
+        println!("{}", "Interrupted, saving the image so far.".yellow());
+    }
+    let phase_start = Instant::now();
+    save_image(&output_image(&framebuffer), &options.output).unwrap();
+    phases.push(("output", phase_start.elapsed()));
+    finished(&mut stdout, &options.output);
+    if let Some(path) = checkpoint_path {
+        let phase_start = Instant::now();
+        checkpoint.save(&framebuffer, path).unwrap();
+        phases.push(("checkpoint", phase_start.elapsed()));
+        println!("{}", format!("Checkpoint saved to {}.", path).green());
+    }
 
-    for (y, x_vec) in pixels.iter().rev().enumerate() {
-        for (x, pixel) in x_vec.iter().enumerate() {
-            let c = color_rgb(pixel, samples_per_pixel);
-            img.put_pixel(x as u32, y as u32, Rgb([c.0 as u8, c.1 as u8, c.2 as u8]))
+    let report = Report {
+        stats: stats::total(),
+        phases,
+    };
+    if options.stats {
+        print!("{}", report.summary());
+    }
+    if let Some(path) = &options.stats_json {
+        if let Err(e) = fs::write(path, report.json()) {
+            eprintln!("Cannot write statistics to {}: {}", path, e);
         }
     }
-
-    img.save("out.png").unwrap();
-    finished(&mut stdout);
 }
 
-fn finished(stdout: &mut Stdout) {
+fn finished(stdout: &mut Stdout, output: &str) {
     stdout
-        .write_all(format!("{}", "Render saved to out.png.\n".bold().green()).as_bytes())
+        .write_all(
+            format!(
+                "{}",
+                format!("Render saved to {}.\n", output).bold().green()
+            )
+            .as_bytes(),
+        )
         .unwrap();
 }
-
-// fn progress(stdout: &mut Stdout, current: i32, image_height: i32) {
-//     let percentage = current as f64 / image_height as f64;
-//     let rgb_value = (percentage * 255.) as u8;
-
-//     let current_color = Color::Rgb {
-//         r: rgb_value,
-//         g: 255 - rgb_value,
-//         b: 0,
-//     };
-
-//     stdout.queue(cursor::SavePosition).unwrap();
-//     stdout
-//         .write_all(format!("{} ", "Rendering to out.ppm: ".bold().dark_magenta(),).as_bytes())
-//         .unwrap();
-//     execute!(
-//         stdout,
-//         SetForegroundColor(current_color),
-//         Print(format!("{:.2}", 100. - percentage * 100.))
-//     )
-//     .unwrap();
-
-//     stdout.flush().unwrap();
-//     stdout.queue(cursor::RestorePosition).unwrap();
-//     stdout
-//         .queue(terminal::Clear(terminal::ClearType::FromCursorDown))
-//         .unwrap();
-// }
@@ -1,16 +1,37 @@
 use crate::{
     hittable::Hit,
+    medium::{henyey_greenstein, sample_henyey_greenstein},
     ray::Ray,
-    utils::{random_double, random_in_unit_sphere, random_unit_vector},
+    spectrum::{disperse, emission, illuminant, reflectance, Illuminant},
+    utils::{random_double, random_in_unit_sphere, random_unit_vector, PI},
     vec3::{dot, reflect, unit_vector, Vec3},
 };
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub enum Material {
     Lambertian(f64, f64, f64),
     Metal(f64, f64, f64, f64),
     Dielectric(f64),
+    /// Glass whose index of refraction changes with the wavelength, splitting white
+    /// light into its colors.
+    Dispersive(Glass),
+    /// Colored glass or liquid with the index of refraction `ir`. Light inside dims by
+    /// the Beer-Lambert law to the color (r, g, b) over a unit of distance, see
+    /// `medium::Interior`.
+    AbsorbingDielectric(f64, f64, f64, f64),
     Light(f64, f64, f64),
+    /// Light emitting on the front face only, the side the normal of the shape points
+    /// to. `Light` emits on both sides of flat shapes.
+    OneSidedLight(f64, f64, f64),
+    /// Light with the spectrum of a standard light source, scaled to the given value at
+    /// 560 nm.
+    SpectralLight(Illuminant, f64),
+    /// Phase function of a participating medium scattering equally in all directions.
+    Isotropic(f64, f64, f64),
+    /// Henyey-Greenstein phase function with the asymmetry parameter `g` in `(-1, 1)`.
+    HenyeyGreenstein(f64, f64, f64, f64),
+    /// Collision in an emissive medium: isotropic scattering albedo and emitted radiance.
+    EmissiveMedium(f64, f64, f64, f64, f64, f64),
     Init,
 }
 
@@ -33,7 +54,7 @@ pub fn scatter(
                 origin: rec.point,
                 dir: scatter_direction,
             };
-            *attenuation = Vec3(r, g, b);
+            *attenuation = reflectance(Vec3(r, g, b));
             true
         }
         Material::Metal(r, g, b, fuzzy) => {
@@ -43,54 +64,265 @@ pub fn scatter(
                 dir: reflected + fuzzy * random_in_unit_sphere(),
             };
 
-            *attenuation = Vec3(r, g, b);
+            *attenuation = reflectance(Vec3(r, g, b));
             dot(&scattered.dir, &rec.normal) > 0.
         }
-        Material::Dielectric(ir) => {
+        Material::Dielectric(ir) | Material::AbsorbingDielectric(ir, _, _, _) => {
             *attenuation = Vec3(1., 1., 1.);
-            let refraction_ratio = if rec.front_face { 1. / ir } else { ir };
-
-            let unit_direction = unit_vector(&ray.dir);
-
-            let cos_theta = f64::min(dot(&-unit_direction, &rec.normal), 1.0);
-            let sin_theta = f64::sqrt(1. - cos_theta * cos_theta);
-
-            let cannot_refract = refraction_ratio * sin_theta > 1.0;
-            let direction;
-
-            if cannot_refract || refractance(cos_theta, refraction_ratio) > random_double() {
-                direction = reflect(&unit_direction, &rec.normal);
-            } else {
-                direction = refract(&unit_direction, &rec.normal, refraction_ratio);
-            }
-
             *scattered = Ray {
                 origin: rec.point,
-                dir: direction,
+                dir: dielectric(ray, rec, ir),
             };
             true
         }
-        Material::Light(_, _, _) => false,
+        Material::Dispersive(glass) => {
+            let (wavelength, weight) = disperse();
+            *attenuation = weight;
+            *scattered = Ray {
+                origin: rec.point,
+                dir: dielectric(ray, rec, glass.index(wavelength)),
+            };
+            true
+        }
+        Material::Isotropic(r, g, b) | Material::EmissiveMedium(r, g, b, _, _, _) => {
+            *scattered = Ray {
+                origin: rec.point,
+                dir: random_unit_vector(),
+            };
+            *attenuation = reflectance(Vec3(r, g, b));
+            true
+        }
+        Material::HenyeyGreenstein(r, g, b, asymmetry) => {
+            *scattered = Ray {
+                origin: rec.point,
+                dir: sample_henyey_greenstein(&unit_vector(&ray.dir), asymmetry),
+            };
+            *attenuation = reflectance(Vec3(r, g, b));
+            true
+        }
+        Material::Light(..) | Material::OneSidedLight(..) | Material::SpectralLight(..) => false,
         Material::Init => false,
     }
 }
 
-pub fn color_emitted(material: Material) -> Vec3 {
+/// Direction glass with the index of refraction `ir` sends `ray` in, reflected or
+/// refracted at random by the Fresnel reflectance.
+fn dielectric(ray: &Ray, rec: &Hit, ir: f64) -> Vec3 {
+    let refraction_ratio = if rec.front_face { 1. / ir } else { ir };
+
+    let unit_direction = unit_vector(&ray.dir);
+
+    let cos_theta = f64::min(dot(&-unit_direction, &rec.normal), 1.0);
+    let sin_theta = f64::sqrt(1. - cos_theta * cos_theta);
+
+    let cannot_refract = refraction_ratio * sin_theta > 1.0;
+    if cannot_refract || refractance(cos_theta, refraction_ratio) > random_double() {
+        reflect(&unit_direction, &rec.normal)
+    } else {
+        refract(&unit_direction, &rec.normal, refraction_ratio)
+    }
+}
+
+/// Radiance emitted by the material, on the front face of the surface hit or its back.
+pub fn color_emitted(material: Material, front_face: bool) -> Vec3 {
     match material {
-        Material::Light(r, g, b) => Vec3(r, g, b),
+        Material::Light(r, g, b) => illuminant(Vec3(r, g, b)),
+        Material::OneSidedLight(r, g, b) if front_face => illuminant(Vec3(r, g, b)),
+        Material::SpectralLight(light, scale) => emission(light, scale),
+        Material::EmissiveMedium(_, _, _, r, g, b) => illuminant(Vec3(r, g, b)),
         _ => Vec3(0., 0., 0.),
     }
 }
 
-fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: f64) -> Vec3 {
-    let cos_theta = f64::min(dot(&-*uv, &n), 1.);
+/// Whether the material is a light source, as opposed to a glowing medium.
+pub fn is_light(material: Material) -> bool {
+    matches!(
+        material,
+        Material::Light(..) | Material::OneSidedLight(..) | Material::SpectralLight(..)
+    )
+}
+
+/// Whether the material scatters into a few exact directions only: mirrors and glass.
+pub fn is_specular(material: Material) -> bool {
+    matches!(
+        material,
+        Material::Metal(..)
+            | Material::Dielectric(_)
+            | Material::Dispersive(_)
+            | Material::AbsorbingDielectric(..)
+    )
+}
+
+/// Absorption coefficients of the inside of glass at the wavelengths of the sample,
+/// zero for clear glass. `None` for materials light does not go through.
+pub fn absorption(material: Material) -> Option<Vec3> {
+    let coefficient = |transmittance: f64| -f64::ln(f64::max(transmittance, 1e-12));
+    match material {
+        Material::Dielectric(_) | Material::Dispersive(_) => Some(Vec3(0., 0., 0.)),
+        Material::AbsorbingDielectric(_, r, g, b) => {
+            let color = reflectance(Vec3(r, g, b));
+            Some(Vec3(
+                coefficient(color.0),
+                coefficient(color.1),
+                coefficient(color.2),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Light scattered into `ray` per unit of radiance arriving from the unit direction
+/// `dir`, cosine included, and the density with which `scatter` picks `dir`. `None`
+/// for materials scattering into a few exact directions, which no light sample can hit.
+pub fn scattering(material: Material, ray: &Ray, rec: &Hit, dir: &Vec3) -> Option<(Vec3, f64)> {
+    match material {
+        Material::Lambertian(r, g, b) => {
+            let cosine = f64::max(dot(&rec.normal, dir), 0.);
+            Some((reflectance(Vec3(r, g, b)) * cosine / PI, cosine / PI))
+        }
+        Material::Isotropic(r, g, b) | Material::EmissiveMedium(r, g, b, _, _, _) => {
+            Some((reflectance(Vec3(r, g, b)) / (4. * PI), 1. / (4. * PI)))
+        }
+        Material::HenyeyGreenstein(r, g, b, asymmetry) => {
+            let phase = henyey_greenstein(dot(&unit_vector(&ray.dir), dir), asymmetry);
+            Some((reflectance(Vec3(r, g, b)) * phase, phase))
+        }
+        _ => None,
+    }
+}
+
+/// Base color of the material, white for clear glass.
+pub fn albedo(material: Material) -> Vec3 {
+    match material {
+        Material::Lambertian(r, g, b)
+        | Material::Metal(r, g, b, _)
+        | Material::Light(r, g, b)
+        | Material::OneSidedLight(r, g, b)
+        | Material::Isotropic(r, g, b)
+        | Material::HenyeyGreenstein(r, g, b, _)
+        | Material::EmissiveMedium(r, g, b, _, _, _) => Vec3(r, g, b),
+        Material::SpectralLight(light, scale) => emission(light, scale),
+        Material::Dielectric(_) | Material::Dispersive(_) => Vec3(1., 1., 1.),
+        Material::AbsorbingDielectric(_, r, g, b) => Vec3(r, g, b),
+        Material::Init => Vec3(0., 0., 0.),
+    }
+}
+
+/// Index of refraction of a glass as a function of the wavelength.
+#[derive(Clone, Copy, Debug)]
+pub enum Glass {
+    /// Cauchy's equation `A + B / λ²`, λ in micrometers.
+    Cauchy(f64, f64),
+    /// Sellmeier equation `n² = 1 + Σ Bᵢ λ² / (λ² - Cᵢ)` with the coefficients `B` and
+    /// `C`, λ in micrometers and `C` in square micrometers.
+    Sellmeier([f64; 3], [f64; 3]),
+}
+
+impl Glass {
+    /// Schott N-BK7, common optical crown glass.
+    pub const BK7: Glass = Glass::Sellmeier(
+        [1.03961212, 0.231792344, 1.01046945],
+        [0.00600069867, 0.0200179144, 103.560653],
+    );
+    /// Schott SF11, dense flint glass with strong dispersion.
+    pub const FLINT: Glass = Glass::Sellmeier(
+        [1.73759695, 0.313747346, 1.89878101],
+        [0.013188707, 0.0623068142, 155.23629],
+    );
+    /// Diamond, after Peter.
+    pub const DIAMOND: Glass = Glass::Sellmeier([0.3306, 4.3356, 0.], [0.030625, 0.011236, 0.]);
+
+    /// Index of refraction at the wavelength in nanometers.
+    pub fn index(&self, lambda: f64) -> f64 {
+        let micrometers = lambda / 1000.;
+        let squared = micrometers * micrometers;
+        match *self {
+            Glass::Cauchy(a, b) => a + b / squared,
+            Glass::Sellmeier(b, c) => f64::sqrt(
+                1. + (0..3)
+                    .map(|i| b[i] * squared / (squared - c[i]))
+                    .sum::<f64>(),
+            ),
+        }
+    }
+}
+
+pub fn refract(uv: &Vec3, n: &Vec3, etai_over_etat: f64) -> Vec3 {
+    let cos_theta = f64::min(dot(&-*uv, n), 1.);
     let r_out_perp = etai_over_etat * (*uv + cos_theta * *n);
     let r_out_parallel = -f64::sqrt(f64::abs(1.0 - r_out_perp.length_squared())) * *n;
     r_out_perp + r_out_parallel
 }
 
-fn refractance(cosine: f64, ref_idx: f64) -> f64 {
+pub fn refractance(cosine: f64, ref_idx: f64) -> f64 {
     let mut r0 = (1. - ref_idx) / (1. + ref_idx);
     r0 *= r0;
     r0 + (1. - r0) * f64::powf(1. - cosine, 5.)
 }
+
+#[cfg(test)]
+mod materials_tests {
+    use super::*;
+    use crate::{rand, spectrum};
+
+    #[test]
+    fn test_glass_indices() {
+        //Catalogue values at the yellow sodium line
+        for (glass, index) in [
+            (Glass::BK7, 1.5168),
+            (Glass::FLINT, 1.7847),
+            (Glass::DIAMOND, 2.4175),
+        ] {
+            let n = glass.index(587.6);
+            assert!(f64::abs(n - index) < 2e-3, "{} != {}", n, index);
+            //Blue light bends more than red
+            assert!(glass.index(450.) > glass.index(650.));
+        }
+        assert_eq!(Glass::Cauchy(1.5, 0.01).index(500.), 1.54);
+    }
+
+    #[test]
+    fn test_dispersion_keeps_white_light_white() {
+        let ray = Ray {
+            origin: Vec3(0., 0., 1.),
+            dir: Vec3(0.3, 0., -1.),
+        };
+        let mut rec = Hit::empty();
+        rec.normal = Vec3(0., 0., 1.);
+        rec.material = Material::Dispersive(Glass::FLINT);
+
+        for spectral in [false, true] {
+            rand::seed(3, &[]);
+            let n = 100_000;
+            let mut sum = Vec3(0., 0., 0.);
+            for _ in 0..n {
+                spectrum::set_wavelengths(
+                    spectral.then(|| spectrum::sample_wavelengths(rand::next_f64())),
+                );
+                let mut attenuation = Vec3(1., 1., 1.);
+                let mut scattered = ray;
+                //Twice through the glass still keeps the same channel
+                for _ in 0..2 {
+                    let mut weight = Vec3(0., 0., 0.);
+                    assert!(scatter(
+                        rec.material,
+                        &ray,
+                        &rec,
+                        &mut weight,
+                        &mut scattered
+                    ));
+                    attenuation = attenuation * weight;
+                }
+                sum += spectrum::to_rgb(attenuation * illuminant(Vec3(1., 1., 1.)));
+            }
+            spectrum::set_wavelengths(None);
+            let mean = sum / n as f64;
+            assert!(
+                (mean - Vec3(1., 1., 1.)).length() < 0.04,
+                "{:?}, spectral {}",
+                mean,
+                spectral
+            );
+        }
+    }
+}
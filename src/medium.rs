@@ -0,0 +1,242 @@
+use crate::{
+    hittable::Hit,
+    materials::absorption,
+    ray::Ray,
+    utils::{random_double, PI},
+    vec3::{dot, orthonormal_basis, unit_vector, Vec3},
+};
+
+/// Henyey-Greenstein phase function value for the cosine between the propagation
+/// direction and the scattered direction. `g > 0` scatters forward, `g = 0` is isotropic.
+pub fn henyey_greenstein(cos_theta: f64, g: f64) -> f64 {
+    let denom = 1. + g * g - 2. * g * cos_theta;
+    (1. - g * g) / (4. * PI * denom * f64::sqrt(denom))
+}
+
+/// Samples a scattered direction around the propagation direction `dir` (unit length)
+/// proportionally to the Henyey-Greenstein phase function.
+pub fn sample_henyey_greenstein(dir: &Vec3, g: f64) -> Vec3 {
+    let xi = random_double();
+    let cos_theta = if f64::abs(g) < 1e-3 {
+        1. - 2. * xi
+    } else {
+        let s = (1. - g * g) / (1. - g + 2. * g * xi);
+        (1. + g * g - s * s) / (2. * g)
+    };
+    let sin_theta = f64::sqrt(f64::max(0., 1. - cos_theta * cos_theta));
+    let phi = 2. * PI * random_double();
+
+    let (t, b) = orthonormal_basis(dir);
+    sin_theta * f64::cos(phi) * t + sin_theta * f64::sin(phi) * b + cos_theta * *dir
+}
+
+/// Global atmospheric fog whose density falls off exponentially with height:
+/// `density * exp(-falloff * (y - height))`. A falloff of zero gives uniform fog.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Fog {
+    pub density: f64,
+    pub falloff: f64,
+    pub height: f64,
+    pub albedo: Vec3,
+    pub g: f64,
+}
+
+impl Fog {
+    /// Samples the free-flight distance along the ray to the next scattering event,
+    /// proportionally to the transmittance. `None` if the ray escapes the fog.
+    pub fn sample_distance(&self, ray: &Ray) -> Option<f64> {
+        let dir = unit_vector(&ray.dir);
+        let sigma = self.density * f64::exp(-self.falloff * (ray.origin.1 - self.height));
+        let tau = -f64::ln(1. - random_double());
+
+        if sigma <= 0. {
+            return None;
+        }
+
+        //Optical depth over distance s is sigma * (1 - exp(-k * s)) / k
+        let k = self.falloff * dir.1;
+        let distance = if f64::abs(k) < 1e-9 {
+            tau / sigma
+        } else {
+            let x = 1. - tau * k / sigma;
+            if x <= 0. {
+                return None;
+            }
+            -f64::ln(x) / k
+        };
+
+        Some(distance / ray.dir.length())
+    }
+
+    /// Fraction of light that passes unscattered along the ray between `t = 0` and `t`.
+    pub fn transmittance(&self, ray: &Ray, t: f64) -> f64 {
+        let dir = unit_vector(&ray.dir);
+        let distance = t * ray.dir.length();
+        let sigma = self.density * f64::exp(-self.falloff * (ray.origin.1 - self.height));
+        let k = self.falloff * dir.1;
+
+        let optical_depth = if f64::abs(k) < 1e-9 {
+            sigma * distance
+        } else {
+            sigma * (1. - f64::exp(-k * distance)) / k
+        };
+        f64::exp(-optical_depth)
+    }
+}
+
+/// Glass and liquids a path is inside of, innermost last, with their absorption
+/// coefficients. Light travelling through them dims by the Beer-Lambert law. Paths
+/// start outside of all glass and keep track of it as they go through its surfaces,
+/// so glass must be closed and may only nest, like ice in a drink.
+#[derive(Clone, Default)]
+pub struct Interior(Vec<Vec3>);
+
+impl Interior {
+    /// Fraction of the light per channel left after `distance` inside the innermost.
+    pub fn transmittance(&self, distance: f64) -> Vec3 {
+        match self.0.last() {
+            Some(sigma) => Vec3(
+                f64::exp(-sigma.0 * distance),
+                f64::exp(-sigma.1 * distance),
+                f64::exp(-sigma.2 * distance),
+            ),
+            None => Vec3(1., 1., 1.),
+        }
+    }
+
+    /// Follows the ray scattered at `rec` into or out of the glass hit, if it went
+    /// through the surface.
+    pub fn cross(&mut self, rec: &Hit, scattered: &Ray) {
+        let sigma = match absorption(rec.material) {
+            Some(sigma) if dot(&scattered.dir, &rec.normal) < 0. => sigma,
+            _ => return,
+        };
+        if rec.front_face {
+            self.0.push(sigma);
+        } else {
+            self.0.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod medium_tests {
+    use super::*;
+    use crate::materials::Material;
+
+    #[test]
+    fn test_interior_follows_nested_glass() {
+        let tinted = Material::AbsorbingDielectric(1.3, 0.5, 1., 1.);
+        let clear = Material::Dielectric(1.5);
+        let crossing = |material, front_face| {
+            let rec = Hit {
+                normal: Vec3(0., 0., 1.),
+                front_face,
+                material,
+                ..Hit::empty()
+            };
+            let through = Ray {
+                origin: Vec3(0., 0., 0.),
+                dir: Vec3(0., 0., -1.),
+            };
+            (rec, through)
+        };
+        let mut interior = Interior::default();
+        let dimmed = |interior: &Interior| interior.transmittance(2.).0;
+
+        //Into a drink, into an ice cube in it, back out of the ice
+        let (rec, ray) = crossing(tinted, true);
+        interior.cross(&rec, &ray);
+        assert!(f64::abs(dimmed(&interior) - 0.25) < 1e-12);
+        let (rec, ray) = crossing(clear, true);
+        interior.cross(&rec, &ray);
+        assert_eq!(dimmed(&interior), 1.);
+        let (rec, ray) = crossing(clear, false);
+        interior.cross(&rec, &ray);
+        assert!(f64::abs(dimmed(&interior) - 0.25) < 1e-12);
+
+        //Reflected rays stay inside
+        let (rec, _) = crossing(tinted, false);
+        let reflected = Ray {
+            origin: Vec3(0., 0., 0.),
+            dir: Vec3(0., 0., 1.),
+        };
+        interior.cross(&rec, &reflected);
+        assert!(f64::abs(dimmed(&interior) - 0.25) < 1e-12);
+        let (rec, ray) = crossing(tinted, false);
+        interior.cross(&rec, &ray);
+        assert_eq!(dimmed(&interior), 1.);
+    }
+
+    #[test]
+    fn test_henyey_greenstein_normalized() {
+        //Integrate over the sphere: 2π ∫ p(cosθ) dcosθ
+        let steps = 100_000;
+        for g in [-0.5, 0., 0.8] {
+            let integral: f64 = (0..steps)
+                .map(|i| {
+                    let cos_theta = -1. + 2. * (i as f64 + 0.5) / steps as f64;
+                    henyey_greenstein(cos_theta, g) * 2. / steps as f64
+                })
+                .sum::<f64>()
+                * 2.
+                * PI;
+            assert!(f64::abs(integral - 1.) < 1e-3, "{}", integral);
+        }
+    }
+
+    #[test]
+    fn test_sample_henyey_greenstein_mean_cosine() {
+        //The mean cosine of the Henyey-Greenstein distribution is g
+        let dir = unit_vector(&Vec3(1., 1., 0.));
+        let samples = 200_000;
+        let mean = (0..samples)
+            .map(|_| dot(&sample_henyey_greenstein(&dir, 0.6), &dir))
+            .sum::<f64>()
+            / samples as f64;
+        assert!(f64::abs(mean - 0.6) < 0.01, "{}", mean);
+    }
+
+    #[test]
+    fn test_uniform_fog_mean_free_path() {
+        let fog = Fog {
+            density: 2.,
+            falloff: 0.,
+            height: 0.,
+            albedo: Vec3(1., 1., 1.),
+            g: 0.,
+        };
+        let ray = Ray {
+            origin: Vec3(0., 0., 0.),
+            dir: Vec3(0., 0., -2.),
+        };
+        let samples = 200_000;
+        let mean = (0..samples)
+            .map(|_| fog.sample_distance(&ray).unwrap() * 2.)
+            .sum::<f64>()
+            / samples as f64;
+        assert!(f64::abs(mean - 0.5) < 0.01, "{}", mean);
+        assert!(f64::abs(fog.transmittance(&ray, 0.25) - f64::exp(-1.)) < 1e-12);
+    }
+
+    #[test]
+    fn test_height_fog_escapes_upwards() {
+        let fog = Fog {
+            density: 0.1,
+            falloff: 1.,
+            height: 0.,
+            albedo: Vec3(1., 1., 1.),
+            g: 0.,
+        };
+        let up = Ray {
+            origin: Vec3(0., 0., 0.),
+            dir: Vec3(0., 1., 0.),
+        };
+        //Total optical depth upwards is density / falloff
+        assert!(f64::abs(fog.transmittance(&up, 1e6) - f64::exp(-0.1)) < 1e-9);
+        let escaped = (0..10_000)
+            .filter(|_| fog.sample_distance(&up).is_none())
+            .count();
+        assert!(f64::abs(escaped as f64 / 10_000. - f64::exp(-0.1)) < 0.02);
+    }
+}
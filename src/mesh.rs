@@ -0,0 +1,86 @@
+//! Triangle meshes: shared vertices and triangles indexing into them, counter-clockwise
+//! seen from the side the normals point to.
+
+use crate::{
+    aabb::Aabb,
+    vec3::{cross, Vec3},
+};
+
+#[derive(Debug)]
+pub struct Mesh {
+    pub vertices: Vec<Vec3>,
+    pub triangles: Vec<[usize; 3]>,
+    pub bounds: Aabb,
+}
+
+impl Mesh {
+    pub fn new(vertices: Vec<Vec3>, triangles: Vec<[usize; 3]>) -> Mesh {
+        assert!(!triangles.is_empty(), "mesh without triangles");
+        for triangle in &triangles {
+            assert!(
+                triangle.iter().all(|&i| i < vertices.len()),
+                "triangle vertex out of range"
+            );
+        }
+
+        let bounds = triangles
+            .iter()
+            .flatten()
+            .map(|&i| Aabb::new(vertices[i], vertices[i]))
+            .reduce(|a, b| Aabb::surrounding(&a, &b))
+            .unwrap();
+        Mesh {
+            vertices,
+            triangles,
+            bounds,
+        }
+    }
+
+    /// Corners of the triangle at `index`.
+    pub fn triangle(&self, index: usize) -> (Vec3, Vec3, Vec3) {
+        let [a, b, c] = self.triangles[index];
+        (self.vertices[a], self.vertices[b], self.vertices[c])
+    }
+
+    pub fn area(&self) -> f64 {
+        (0..self.triangles.len())
+            .map(|i| {
+                let (a, b, c) = self.triangle(i);
+                0.5 * cross(&(b - a), &(c - a)).length()
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod mesh_tests {
+    use super::*;
+
+    //Unit square in the xy plane from two triangles
+    fn square() -> Mesh {
+        Mesh::new(
+            vec![
+                Vec3(0., 0., 0.),
+                Vec3(1., 0., 0.),
+                Vec3(1., 1., 0.),
+                Vec3(0., 1., 0.),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+        )
+    }
+
+    #[test]
+    fn test_bounds_and_area() {
+        let mesh = square();
+        assert!(f64::abs(mesh.bounds.min.0) < 1e-3);
+        assert!(f64::abs(mesh.bounds.max.1 - 1.) < 1e-3);
+        assert!(f64::abs(mesh.area() - 1.) < 1e-12);
+        assert_eq!(mesh.triangle(1).2, Vec3(0., 1., 0.));
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn test_rejects_bad_indices() {
+        Mesh::new(vec![Vec3(0., 0., 0.)], vec![[0, 1, 2]]);
+    }
+}
@@ -0,0 +1,427 @@
+//! Metropolis light transport in primary sample space (Kelemen et al.).
+//!
+//! A path is a function of the random numbers it is built from, the primary samples.
+//! Instead of drawing new random numbers for every path, a Markov chain mutates them:
+//! large steps draw all of them anew, small steps move each a little. A mutated path
+//! is accepted with the ratio of its brightness to that of the current one, so the
+//! chain lingers on bright paths and keeps finding variations of paths that are hard
+//! to find at random, like light through a keyhole or caustics seen through glass.
+//!
+//! The chain visits paths in proportion to their brightness rather than their
+//! radiance, so the image it splats is scaled by the average brightness of the image,
+//! estimated before rendering from independent paths in a bootstrap phase. Those
+//! paths also give the starting points of the chains, picked in proportion to their
+//! brightness so chains do not have to converge first.
+
+use rayon::prelude::*;
+
+use crate::{
+    integrator::{Integrator, LightSamplingPathTracer},
+    rand,
+    ray::Ray,
+    render::{Scene, Settings},
+    spectrum, stats,
+    utils::PI,
+    vec3::Vec3,
+};
+
+/// Paths traced to estimate the average brightness of the image.
+const BOOTSTRAP_SAMPLES: u64 = 100_000;
+/// Mutations of the chain every sample runs.
+pub const MUTATIONS_PER_CHAIN: u32 = 100;
+
+/// Random numbers a path is built from, mutated lazily: a sample is only brought up
+/// to date with the mutations it missed when the path asks for it.
+#[derive(Default)]
+pub struct PrimarySamples {
+    samples: Vec<PrimarySample>,
+    /// Next sample handed out.
+    index: usize,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    /// Standard deviation of a small step.
+    sigma: f64,
+}
+
+#[derive(Clone, Copy)]
+struct PrimarySample {
+    value: f64,
+    /// Iteration the value was last changed in.
+    modified: u64,
+    backup: f64,
+    backup_modified: u64,
+}
+
+impl PrimarySamples {
+    /// Samples that are all drawn anew the first time they are asked for.
+    pub fn new(sigma: f64) -> PrimarySamples {
+        PrimarySamples {
+            large_step: true,
+            sigma,
+            ..Default::default()
+        }
+    }
+
+    /// Starts a mutation, drawing all samples anew with `large_step` and moving them
+    /// a little otherwise.
+    pub fn start_iteration(&mut self, large_step: bool) {
+        self.iteration += 1;
+        self.large_step = large_step;
+    }
+
+    /// Keeps the mutation.
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    /// Returns to the samples before the mutation.
+    pub fn reject(&mut self) {
+        for sample in &mut self.samples {
+            if sample.modified == self.iteration {
+                sample.value = sample.backup;
+                sample.modified = sample.backup_modified;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    /// Restarts handing out the samples from the first, for the same path again.
+    pub fn rewind(&mut self) {
+        self.index = 0;
+    }
+
+    pub fn next(&mut self) -> f64 {
+        if self.index == self.samples.len() {
+            //Samples asked for the first time are uniform whatever the step, as if they
+            //had been drawn before the mutation
+            let value = rand::uniform();
+            self.samples.push(PrimarySample {
+                value,
+                modified: self.iteration,
+                backup: value,
+                backup_modified: self.iteration.saturating_sub(1),
+            });
+        }
+        let sample = &mut self.samples[self.index];
+        self.index += 1;
+
+        //Samples not asked for since the last accepted large step are drawn anew
+        if sample.modified < self.last_large_step {
+            sample.value = rand::uniform();
+            sample.modified = self.last_large_step;
+        }
+
+        if sample.modified < self.iteration {
+            sample.backup = sample.value;
+            sample.backup_modified = sample.modified;
+            if self.large_step {
+                sample.value = rand::uniform();
+            } else {
+                //All the small steps it missed at once, their deviations add up
+                let steps = (self.iteration - sample.modified) as f64;
+                sample.value += normal() * self.sigma * steps.sqrt();
+                sample.value -= sample.value.floor();
+            }
+            sample.modified = self.iteration;
+        }
+        sample.value
+    }
+}
+
+/// Standard normal from the random sequence, by the Box-Muller transform.
+fn normal() -> f64 {
+    let radius = f64::sqrt(-2. * f64::ln(1. - rand::uniform()));
+    radius * f64::cos(2. * PI * rand::uniform())
+}
+
+/// Average brightness of the image and the paths the chains start from.
+pub struct Bootstrap {
+    pub brightness: f64,
+    /// Running sums of the brightness of the bootstrap paths.
+    cdf: Vec<f64>,
+}
+
+impl Bootstrap {
+    /// Traces the bootstrap paths, each with its own random sequence so the chains
+    /// start the same in every process.
+    pub fn new(scene: &Scene, settings: &Settings) -> Bootstrap {
+        let brightness: Vec<f64> = (0..BOOTSTRAP_SAMPLES)
+            .into_par_iter()
+            .map(|i| {
+                let mut samples = start(settings, i, 0.);
+                path(scene, settings, &mut samples).1.luminance()
+            })
+            .collect();
+
+        let cdf: Vec<f64> = brightness
+            .iter()
+            .scan(0., |sum, brightness| {
+                *sum += brightness;
+                Some(*sum)
+            })
+            .collect();
+        Bootstrap {
+            brightness: cdf.last().unwrap() / BOOTSTRAP_SAMPLES as f64,
+            cdf,
+        }
+    }
+
+    /// Bootstrap path picked in proportion to its brightness with the uniform `u`.
+    fn pick(&self, u: f64) -> u64 {
+        let target = u * self.cdf.last().unwrap();
+        let index = self.cdf.partition_point(|&sum| sum <= target);
+        usize::min(index, self.cdf.len() - 1) as u64
+    }
+}
+
+/// Primary samples of the bootstrap path `i`, reseeding the random sequence so the
+/// path comes out the same whenever it is traced.
+fn start(settings: &Settings, i: u64, sigma: f64) -> PrimarySamples {
+    //Pixel indices never get this high, the photons of photon mapping use `u64::MAX`
+    rand::seed(settings.seed, &[u64::MAX - 1, i]);
+    PrimarySamples::new(sigma)
+}
+
+/// Traces the path of the primary samples: the first two pick the point on the film,
+/// the next the wavelengths in spectral mode, the rest go to the path tracer. Returns
+/// the pixel and the radiance in RGB.
+fn path(scene: &Scene, settings: &Settings, samples: &mut PrimarySamples) -> (usize, Vec3) {
+    samples.rewind();
+    rand::with_primary_samples(samples, || {
+        let x = rand::next_f64() * settings.width as f64;
+        let row = rand::next_f64() * settings.height as f64;
+        //Rows are stored from the top, the camera counts from the bottom
+        let pixel = (settings.height - 1 - row as usize) * settings.width + x as usize;
+
+        let ray = scene.camera.get_ray(
+            x / (settings.width - 1) as f64,
+            row / (settings.height - 1) as f64,
+        );
+        spectrum::set_wavelengths(
+            settings
+                .spectral
+                .then(|| spectrum::sample_wavelengths(rand::next_f64())),
+        );
+        stats::record(|stats| stats.primary_rays += 1);
+        let radiance = LightSamplingPathTracer.radiance(&ray, scene, settings, &mut Vec::new());
+        let radiance = spectrum::to_rgb(radiance);
+        spectrum::set_wavelengths(None);
+        (pixel, radiance)
+    })
+}
+
+/// Runs a Markov chain over the paths of the path tracer with light sampling for
+/// every sample, splatting what it finds to the film.
+pub struct MetropolisPathTracer {
+    /// Chance of a mutation drawing all samples anew.
+    pub large_step_probability: f64,
+    /// Standard deviation of the small steps, in primary sample space.
+    pub sigma: f64,
+}
+
+impl Integrator for MetropolisPathTracer {
+    fn radiance(
+        &self,
+        _ray: &Ray,
+        scene: &Scene,
+        settings: &Settings,
+        splats: &mut Vec<(usize, Vec3)>,
+    ) -> Vec3 {
+        let bootstrap = match &scene.bootstrap {
+            Some(bootstrap) if bootstrap.brightness > 0. => bootstrap,
+            _ => return Vec3(0., 0., 0.),
+        };
+
+        //The sample's own random sequence drives the chain, after the start is retraced
+        let chain_seed = rand::next_u64();
+        let mut samples = start(settings, bootstrap.pick(rand::uniform()), self.sigma);
+        let mut current = path(scene, settings, &mut samples);
+        rand::seed(chain_seed, &[]);
+
+        //Every mutation stands for one path, splatted as brightness over its density
+        let scale = bootstrap.brightness / MUTATIONS_PER_CHAIN as f64;
+        for _ in 0..MUTATIONS_PER_CHAIN {
+            samples.start_iteration(rand::uniform() < self.large_step_probability);
+            let proposed = path(scene, settings, &mut samples);
+
+            let current_brightness = current.1.luminance();
+            let proposed_brightness = proposed.1.luminance();
+            let acceptance = if current_brightness > 0. {
+                f64::min(1., proposed_brightness / current_brightness)
+            } else {
+                1.
+            };
+
+            //Both paths contribute by their chance of being where the chain goes next
+            if proposed_brightness > 0. {
+                splats.push((
+                    proposed.0,
+                    proposed.1 * (acceptance * scale / proposed_brightness),
+                ));
+            }
+            if current_brightness > 0. {
+                splats.push((
+                    current.0,
+                    current.1 * ((1. - acceptance) * scale / current_brightness),
+                ));
+            }
+
+            if rand::uniform() < acceptance {
+                current = proposed;
+                samples.accept();
+            } else {
+                samples.reject();
+            }
+        }
+
+        Vec3(0., 0., 0.)
+    }
+}
+
+#[cfg(test)]
+mod metropolis_tests {
+    use super::*;
+    use crate::{
+        camera::Camera,
+        hittables::Hittables::{HittableObjects, Sphere},
+        integrator::IntegratorKind,
+        materials::Material::{Lambertian, Light},
+        render::render_tile,
+        tiles::Tile,
+    };
+
+    #[test]
+    fn test_reject_restores_samples() {
+        rand::seed(2, &[]);
+        let mut samples = PrimarySamples::new(0.1);
+        let first: Vec<f64> = (0..4).map(|_| samples.next()).collect();
+
+        for large_step in [false, true] {
+            samples.start_iteration(large_step);
+            samples.rewind();
+            let mutated: Vec<f64> = (0..4).map(|_| samples.next()).collect();
+            assert_ne!(mutated, first);
+            assert!(mutated.iter().all(|x| (0. ..1.).contains(x)));
+            samples.reject();
+
+            samples.rewind();
+            let restored: Vec<f64> = (0..4).map(|_| samples.next()).collect();
+            assert_eq!(restored, first);
+        }
+    }
+
+    #[test]
+    fn test_primary_samples_replace_random_numbers() {
+        rand::seed(3, &[]);
+        let mut samples = PrimarySamples::new(0.1);
+        let first: Vec<f64> =
+            rand::with_primary_samples(&mut samples, || (0..3).map(|_| rand::next_f64()).collect());
+        samples.rewind();
+        let again: Vec<f64> =
+            rand::with_primary_samples(&mut samples, || (0..3).map(|_| rand::next_f64()).collect());
+        assert_eq!(first, again);
+        assert_ne!(rand::next_f64(), first[0]);
+    }
+
+    //Inside a diffuse box lit by a sphere
+    fn image(integrator: IntegratorKind, samples_per_pixel: u32) -> Vec<f64> {
+        let mut scene = Scene::new(
+            HittableObjects(vec![
+                Sphere(Vec3(0., 0., 0.), -10., Lambertian(0.7, 0.7, 0.7)),
+                Sphere(Vec3(0., 5., 0.), 1., Light(4., 4., 4.)),
+            ]),
+            None,
+            Camera::new(
+                Vec3(0., -5., 8.),
+                Vec3(0., 0., 0.),
+                Vec3(0., 1., 0.),
+                70.,
+                4. / 3.,
+            ),
+        );
+        let settings = Settings {
+            width: 8,
+            height: 6,
+            samples_per_pixel,
+            max_depth: 16,
+            russian_roulette_depth: 4,
+            seed: 5,
+            integrator,
+            spectral: false,
+        };
+        scene.prepare(&settings);
+
+        let tile = Tile::image(settings.width, settings.height);
+        let mut pixels = vec![Vec3(0., 0., 0.); settings.width * settings.height];
+        let mut samples = vec![0; pixels.len()];
+        let mut splats = Vec::new();
+        render_tile(
+            &scene,
+            &settings,
+            &tile,
+            samples_per_pixel,
+            &mut pixels,
+            &mut samples,
+            &mut splats,
+        );
+        //Every pixel has the same samples, the splats spread over them like in
+        //`Framebuffer::region_image`
+        for (i, splat) in splats {
+            pixels[i] += splat;
+        }
+        pixels
+            .iter()
+            .map(|pixel| pixel.luminance() / samples_per_pixel as f64)
+            .collect()
+    }
+
+    #[test]
+    fn test_counts_every_path_once() {
+        //Each sample retraces the start of its chain and runs the mutations, the ray
+        //from the camera it is given is never traced. The bootstrap paths are counted
+        //on the threads of the pool.
+        stats::take();
+        image(
+            IntegratorKind::Metropolis {
+                large_step_probability: 0.3,
+                sigma: 0.01,
+            },
+            2,
+        );
+        let paths = 8 * 6 * 2 * (MUTATIONS_PER_CHAIN as u64 + 1);
+        let stats = stats::take();
+        assert_eq!(stats.primary_rays, paths);
+        assert!(stats.rays >= stats.primary_rays);
+    }
+
+    #[test]
+    fn test_converges_to_path_tracing() {
+        let path = image(IntegratorKind::LightSampling, 1000);
+        let metropolis = image(
+            IntegratorKind::Metropolis {
+                large_step_probability: 0.3,
+                sigma: 0.01,
+            },
+            20,
+        );
+
+        let mean = |image: &[f64]| image.iter().sum::<f64>() / image.len() as f64;
+        assert!(
+            f64::abs(mean(&path) - mean(&metropolis)) < 0.05 * mean(&path),
+            "path tracing {} != metropolis {}",
+            mean(&path),
+            mean(&metropolis)
+        );
+        //The chains spread over the image like the light does
+        let error = path
+            .iter()
+            .zip(&metropolis)
+            .map(|(a, b)| f64::abs(a - b))
+            .sum::<f64>()
+            / path.iter().sum::<f64>();
+        assert!(error < 0.2, "relative error {}", error);
+    }
+}
@@ -0,0 +1,427 @@
+use std::time::Duration;
+
+use crate::{
+    integrator::IntegratorKind,
+    medium::Fog,
+    scenes::SceneKind,
+    tiles::{Tile, TileOrder},
+    vec3::Vec3,
+};
+
+pub const USAGE: &str = "Usage: rustracer [options]
+
+Options:
+    --scene <name>         Scene to render: spheres, shapes, cornell, lenses, fractal,
+                           daylight, lamps, or volume:<file> for a voxel grid
+                           (spheres)
+    --fog <density>[:<falloff>]
+                           Fill the scene with haze of this density per unit of
+                           length at height 0, thinning out upwards by the falloff
+                           per unit of height (0, even fog)
+    --width <pixels>       Image width, the height follows from the 16:9 aspect ratio (1920)
+    --spp <samples>        Samples per pixel (16)
+    --depth <bounces>      Maximum path depth (64)
+    --rr-depth <bounces>   Depth from which paths are ended at random by Russian
+                           roulette, the more likely the less light they carry (3)
+    --integrator <name>    Rendering algorithm: path, nee for path tracing with light
+                           sampling, bdpt for bidirectional path tracing, photons or
+                           photons:<count> for caustics from a photon map, mlt or
+                           mlt:<large step probability>[:<sigma>] for Metropolis
+                           light transport, whose samples each run 100 mutations,
+                           ao or ao:<distance> for ambient occlusion, whitted, or
+                           the debug views normals, depth, uv and albedo (path)
+    --spectral             Trace light at sampled wavelengths instead of red, green
+                           and blue, for spectral lights and dispersion
+    --output <file>        Output image (out.png)
+    --progressive          Render one sample per pixel at a time and save the image
+                           periodically, Ctrl+C saves the image so far and stops
+    --time <duration>      Render progressively until the time is up, ignoring --spp,
+                           e.g. 90s, 10m or 2h
+    --save-interval <duration>
+                           Time between intermediate saves of the image when
+                           rendering progressively and of the checkpoint (1m)
+    --seed <n>             Seed of the random numbers, the same seed gives the same
+                           image (0)
+    --checkpoint <file>    Save the render state next to the image, and every
+                           --save-interval while rendering, to resume it later
+    --resume <file>        Continue the render saved in a checkpoint, with the same
+                           --width, --depth, --rr-depth, --integrator and
+                           --spectral, and keep checkpointing to it
+    --tile-size <pixels>   Width and height of the tiles the image is split into (32)
+    --tile-order <order>   Order to render the tiles in: scanline, spiral or hilbert
+                           (spiral)
+    --coordinator <address>
+                           Hand out the tiles to workers connecting to this address,
+                           e.g. 0.0.0.0:7878, instead of rendering them here
+    --worker <address>     Render tiles for the coordinator at this address, which
+                           decides all other settings but the --scene and --fog
+    --task-timeout <duration>
+                           Time the coordinator waits for a tile before giving it to
+                           another worker (10m)
+    --region <x,y,w,h>     Render only this rectangle of pixels, from the top left,
+                           and save it as a cropped image
+    --composite <image>    Paste the rendered --region into this earlier render of
+                           the whole frame instead of cropping
+    --stats                Print ray and intersection counts and the time spent in
+                           each phase, counted on this machine only
+    --stats-json <file>    Write the statistics as JSON
+    --preview              Show the render in progress in the terminal
+    --help                 Print this message";
+
+/// Render settings from the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub scene: SceneKind,
+    pub fog: Option<Fog>,
+    pub width: i32,
+    pub samples_per_pixel: i32,
+    pub max_depth: i32,
+    pub russian_roulette_depth: i32,
+    pub integrator: IntegratorKind,
+    pub spectral: bool,
+    pub output: String,
+    pub progressive: bool,
+    pub time_budget: Option<Duration>,
+    pub save_interval: Duration,
+    pub seed: u64,
+    pub checkpoint: Option<String>,
+    pub resume: Option<String>,
+    pub tile_size: i32,
+    pub tile_order: TileOrder,
+    pub coordinator: Option<String>,
+    pub worker: Option<String>,
+    pub task_timeout: Duration,
+    pub region: Option<Tile>,
+    pub composite: Option<String>,
+    pub stats: bool,
+    pub stats_json: Option<String>,
+    pub preview: bool,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            scene: SceneKind::Spheres,
+            fog: None,
+            width: 1920,
+            samples_per_pixel: 16,
+            max_depth: 64,
+            russian_roulette_depth: 3,
+            integrator: IntegratorKind::Path,
+            spectral: false,
+            output: "out.png".to_string(),
+            progressive: false,
+            time_budget: None,
+            save_interval: Duration::from_secs(60),
+            seed: 0,
+            checkpoint: None,
+            resume: None,
+            tile_size: 32,
+            tile_order: TileOrder::Spiral,
+            coordinator: None,
+            worker: None,
+            task_timeout: Duration::from_secs(600),
+            region: None,
+            composite: None,
+            stats: false,
+            stats_json: None,
+            preview: false,
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    /// Parses the arguments following the program name.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--scene" => {
+                    let name = value(&arg, args.next())?;
+                    options.scene = SceneKind::parse(&name).ok_or_else(|| {
+                        format!(
+                            "invalid value '{}' for '{}', expected spheres, shapes, cornell, \
+                             lenses, fractal, daylight, lamps or volume:<file>",
+                            name, arg
+                        )
+                    })?;
+                }
+                "--fog" => options.fog = Some(fog(&arg, args.next())?),
+                "--width" => options.width = positive(&arg, args.next())?,
+                "--spp" => options.samples_per_pixel = positive(&arg, args.next())?,
+                "--depth" => options.max_depth = positive(&arg, args.next())?,
+                "--rr-depth" => options.russian_roulette_depth = positive(&arg, args.next())?,
+                "--integrator" => {
+                    let name = value(&arg, args.next())?;
+                    options.integrator = IntegratorKind::parse(&name).ok_or_else(|| {
+                        format!(
+                            "invalid value '{}' for '{}', expected path, nee, bdpt, photons, \
+                             photons:<count>, mlt, mlt:<large step probability>[:<sigma>], \
+                             ao, ao:<distance>, whitted, normals, depth, uv or albedo",
+                            name, arg
+                        )
+                    })?;
+                }
+                "--spectral" => options.spectral = true,
+                "--output" => options.output = value(&arg, args.next())?,
+                "--progressive" => options.progressive = true,
+                "--time" => {
+                    options.time_budget = Some(duration(&arg, args.next())?);
+                    options.progressive = true;
+                }
+                "--save-interval" => options.save_interval = duration(&arg, args.next())?,
+                "--seed" => {
+                    let seed = value(&arg, args.next())?;
+                    options.seed = seed.parse().map_err(|_| {
+                        format!(
+                            "invalid value '{}' for '{}', expected an unsigned integer",
+                            seed, arg
+                        )
+                    })?;
+                }
+                "--checkpoint" => options.checkpoint = Some(value(&arg, args.next())?),
+                "--resume" => options.resume = Some(value(&arg, args.next())?),
+                "--tile-size" => options.tile_size = positive(&arg, args.next())?,
+                "--tile-order" => {
+                    let order = value(&arg, args.next())?;
+                    options.tile_order = TileOrder::parse(&order).ok_or_else(|| {
+                        format!(
+                            "invalid value '{}' for '{}', expected scanline, spiral or hilbert",
+                            order, arg
+                        )
+                    })?;
+                }
+                "--coordinator" => options.coordinator = Some(value(&arg, args.next())?),
+                "--worker" => options.worker = Some(value(&arg, args.next())?),
+                "--task-timeout" => options.task_timeout = duration(&arg, args.next())?,
+                "--region" => options.region = Some(region(&arg, args.next())?),
+                "--composite" => options.composite = Some(value(&arg, args.next())?),
+                "--stats" => options.stats = true,
+                "--stats-json" => options.stats_json = Some(value(&arg, args.next())?),
+                "--preview" => options.preview = true,
+                "--help" | "-h" => options.help = true,
+                _ => return Err(format!("unknown option '{}'", arg)),
+            }
+        }
+
+        //Pixels are spread from edge to edge, which takes two of them on each side
+        if ((options.width as f64 / crate::ASPECT_RATIO) as i32) < 2 {
+            return Err(format!(
+                "invalid value '{}' for '--width', the image would be less than 2 pixels high",
+                options.width
+            ));
+        }
+
+        //Workers render whole tiles, there are no passes to save in between
+        if options.coordinator.is_some() && options.progressive {
+            return Err(
+                "--coordinator cannot be combined with --progressive or --time".to_string(),
+            );
+        }
+
+        //Normals, depths and occlusion have no spectrum
+        if options.spectral
+            && matches!(
+                options.integrator,
+                IntegratorKind::AmbientOcclusion(_) | IntegratorKind::Debug(_)
+            )
+        {
+            return Err(format!(
+                "--spectral cannot be combined with --integrator {}",
+                options.integrator
+            ));
+        }
+
+        if options.composite.is_some() && options.region.is_none() {
+            return Err("--composite needs a --region to render".to_string());
+        }
+
+        Ok(options)
+    }
+}
+
+fn value(flag: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("missing value for '{}'", flag))
+}
+
+fn positive(flag: &str, value_arg: Option<String>) -> Result<i32, String> {
+    let value_arg = value(flag, value_arg)?;
+    match value_arg.parse::<i32>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!(
+            "invalid value '{}' for '{}', expected a positive integer",
+            value_arg, flag
+        )),
+    }
+}
+
+/// Duration in seconds, minutes or hours like `90s`, `10m` or `1.5h`, seconds without
+/// a unit.
+fn duration(flag: &str, value_arg: Option<String>) -> Result<Duration, String> {
+    let value_arg = value(flag, value_arg)?;
+    let (number, scale) = match value_arg.char_indices().last() {
+        Some((i, 's')) => (&value_arg[..i], 1.),
+        Some((i, 'm')) => (&value_arg[..i], 60.),
+        Some((i, 'h')) => (&value_arg[..i], 3600.),
+        _ => (value_arg.as_str(), 1.),
+    };
+    match number.parse::<f64>() {
+        Ok(n) if n > 0. && n.is_finite() => Ok(Duration::from_secs_f64(n * scale)),
+        _ => Err(format!(
+            "invalid value '{}' for '{}', expected a duration like 90s, 10m or 2h",
+            value_arg, flag
+        )),
+    }
+}
+
+/// Light grey haze given as `density` or `density:falloff`.
+fn fog(flag: &str, value_arg: Option<String>) -> Result<Fog, String> {
+    let value_arg = value(flag, value_arg)?;
+    let (density, falloff) = value_arg.split_once(':').unwrap_or((&value_arg, "0"));
+    match (density.parse::<f64>(), falloff.parse::<f64>()) {
+        (Ok(density), Ok(falloff))
+            if density > 0. && density.is_finite() && falloff.is_finite() =>
+        {
+            Ok(Fog {
+                density,
+                falloff,
+                height: 0.,
+                albedo: Vec3(0.9, 0.9, 0.9),
+                g: 0.3,
+            })
+        }
+        _ => Err(format!(
+            "invalid value '{}' for '{}', expected <density>[:<falloff>] with a positive density",
+            value_arg, flag
+        )),
+    }
+}
+
+/// Rectangle given as `x,y,width,height`.
+fn region(flag: &str, value_arg: Option<String>) -> Result<Tile, String> {
+    let value_arg = value(flag, value_arg)?;
+    let numbers: Vec<usize> = value_arg
+        .split(',')
+        .map(|n| n.trim().parse::<usize>())
+        .collect::<Result<_, _>>()
+        .unwrap_or_default();
+    match numbers[..] {
+        [x, y, width, height] if width > 0 && height > 0 => Ok(Tile {
+            x,
+            y,
+            width,
+            height,
+        }),
+        _ => Err(format!(
+            "invalid value '{}' for '{}', expected x,y,width,height",
+            value_arg, flag
+        )),
+    }
+}
+
+#[cfg(test)]
+mod options_tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_defaults() {
+        assert_eq!(parse(&[]), Ok(Options::default()));
+    }
+
+    #[test]
+    fn test_flags() {
+        let options = parse(&[
+            "--width",
+            "640",
+            "--spp",
+            "4",
+            "--preview",
+            "--output",
+            "a.png",
+            "--integrator",
+            "ao:2",
+            "--scene",
+            "shapes",
+        ])
+        .unwrap();
+        assert_eq!(options.width, 640);
+        assert_eq!(options.samples_per_pixel, 4);
+        assert!(options.preview);
+        assert_eq!(options.output, "a.png");
+        assert_eq!(options.integrator, IntegratorKind::AmbientOcclusion(2.));
+        assert_eq!(options.scene, SceneKind::Shapes);
+        assert!(!options.spectral);
+        assert!(
+            parse(&["--spectral", "--integrator", "bdpt"])
+                .unwrap()
+                .spectral
+        );
+    }
+
+    #[test]
+    fn test_fog() {
+        let fog = parse(&["--fog", "0.05:1.5"]).unwrap().fog.unwrap();
+        assert_eq!((fog.density, fog.falloff, fog.height), (0.05, 1.5, 0.));
+        assert_eq!(parse(&["--fog", "0.2"]).unwrap().fog.unwrap().falloff, 0.);
+        assert!(parse(&["--fog", "0"]).is_err());
+        assert!(parse(&["--fog", "0.1:x"]).is_err());
+    }
+
+    #[test]
+    fn test_durations() {
+        let options = parse(&["--time", "10m", "--save-interval", "45"]).unwrap();
+        assert_eq!(options.time_budget, Some(Duration::from_secs(600)));
+        assert_eq!(options.save_interval, Duration::from_secs(45));
+        assert!(options.progressive);
+
+        let options = parse(&["--time", "1.5h"]).unwrap();
+        assert_eq!(options.time_budget, Some(Duration::from_secs(5400)));
+
+        assert!(parse(&["--time", "10x"]).is_err());
+        assert!(parse(&["--time", "0s"]).is_err());
+    }
+
+    #[test]
+    fn test_region() {
+        let options = parse(&["--region", "10,20,300,200", "--composite", "base.png"]).unwrap();
+        assert_eq!(
+            options.region,
+            Some(Tile {
+                x: 10,
+                y: 20,
+                width: 300,
+                height: 200
+            })
+        );
+
+        assert!(parse(&["--region", "10,20,300"]).is_err());
+        assert!(parse(&["--region", "10,20,0,5"]).is_err());
+        assert!(parse(&["--composite", "base.png"]).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse(&["--width"]).is_err());
+        assert!(parse(&["--width", "-3"]).is_err());
+        assert!(parse(&["--width", "1"]).is_err());
+        assert!(parse(&["--width", "3"]).is_err());
+        assert!(parse(&["--width", "4"]).is_ok());
+        assert!(parse(&["--spp", "many"]).is_err());
+        assert!(parse(&["--seed", "-1"]).is_err());
+        assert!(parse(&["--tile-order", "random"]).is_err());
+        assert!(parse(&["--integrator", "ao:0"]).is_err());
+        assert!(parse(&["--spectral", "--integrator", "normals"]).is_err());
+        assert!(parse(&["--coordinator", "0.0.0.0:7878", "--time", "1h"]).is_err());
+        assert!(parse(&["--scene"]).is_err());
+        assert!(parse(&["--scene", "teapot"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+    }
+}
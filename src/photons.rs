@@ -0,0 +1,470 @@
+//! Photon mapping for caustics.
+//!
+//! Light focused by mirrors and glass onto diffuse surfaces is found by path tracing
+//! only when a path happens to bounce off the surface, through the glass and into
+//! the light, which at the sample counts we render at leaves caustics as scattered
+//! fireflies. Instead, photons are traced from the lights before rendering and
+//! stored where they land on a diffuse surface after mirrors or glass. The path
+//! tracer estimates the caustic light at its diffuse bounces from the density of the
+//! photons around them, and leaves out the same paths when it finds them itself.
+
+use rayon::prelude::*;
+
+use crate::{
+    hittable::Hit,
+    integrator::{
+        direct_light, fog_collision, power_heuristic, survives_roulette, trace, Integrator,
+    },
+    materials::{color_emitted, is_light, is_specular, scatter, scattering, Material},
+    medium::Interior,
+    rand,
+    ray::Ray,
+    render::{Scene, Settings},
+    spectrum::{self, illuminant},
+    stats,
+    utils::{random_double, PI},
+    vec3::{dot, unit_vector, Vec3},
+};
+
+/// Photons in the density estimate of a point.
+const GATHERED_PHOTONS: usize = 64;
+
+/// Light arriving at a diffuse surface.
+#[derive(Clone, Copy, Debug)]
+pub struct Photon {
+    pub point: Vec3,
+    /// Unit direction the photon travelled in.
+    pub dir: Vec3,
+    /// Flux, the power of the light over the number of photons traced, in RGB.
+    pub power: Vec3,
+}
+
+/// Photons in a balanced kd-tree, for finding the ones closest to a point.
+pub struct PhotonMap {
+    /// The median photon of the range `lo..hi` is at `(lo + hi) / 2`, the photons
+    /// before it lie below it along its axis, those after it above.
+    photons: Vec<Photon>,
+    axes: Vec<u8>,
+    /// Photons further away are never gathered, so surfaces away from the caustics
+    /// stay black rather than averaging far away photons.
+    max_distance: f64,
+}
+
+impl PhotonMap {
+    pub fn new(mut photons: Vec<Photon>) -> PhotonMap {
+        let mut axes = vec![0; photons.len()];
+        build(&mut photons, &mut axes);
+
+        //A twentieth of the extent of the photons
+        let max_distance = bounds(&photons).map_or(0., |(min, max)| (max - min).length() / 20.);
+
+        PhotonMap {
+            photons,
+            axes,
+            max_distance,
+        }
+    }
+
+    /// Traces `count` photons from the lights of the scene and keeps those reaching a
+    /// diffuse surface through mirrors or glass. Every photon has its own random
+    /// sequence, so the map comes out the same in every process.
+    pub fn caustics(scene: &Scene, settings: &Settings, count: u32) -> PhotonMap {
+        let photons = (0..count as u64)
+            .into_par_iter()
+            .filter_map(|i| {
+                //Pixel indices never get this high
+                rand::seed(settings.seed, &[u64::MAX, i]);
+                spectrum::set_wavelengths(
+                    settings
+                        .spectral
+                        .then(|| spectrum::sample_wavelengths(random_double())),
+                );
+                let photon = caustic_photon(scene, settings, count);
+                spectrum::set_wavelengths(None);
+                photon
+            })
+            .collect();
+        PhotonMap::new(photons)
+    }
+
+    /// Up to `k` photons closest to `point` within the maximum distance, nearest
+    /// first, with their squared distances.
+    pub fn nearest(&self, point: Vec3, k: usize) -> Vec<(f64, &Photon)> {
+        let mut found = Vec::with_capacity(k + 1);
+        self.search(
+            0,
+            self.photons.len(),
+            point,
+            k,
+            self.max_distance * self.max_distance,
+            &mut found,
+        );
+        found
+            .into_iter()
+            .map(|(distance, i)| (distance, &self.photons[i]))
+            .collect()
+    }
+
+    fn search(
+        &self,
+        lo: usize,
+        hi: usize,
+        point: Vec3,
+        k: usize,
+        max_squared: f64,
+        found: &mut Vec<(f64, usize)>,
+    ) {
+        if lo >= hi {
+            return;
+        }
+        let mid = (lo + hi) / 2;
+        let photon = &self.photons[mid];
+        let axis = self.axes[mid] as usize;
+        let offset = point[axis] - photon.point[axis];
+
+        //The side of the point first, the other only if it can hold closer photons
+        let (near, far) = if offset < 0. {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+        self.search(near.0, near.1, point, k, max_squared, found);
+
+        let squared = (photon.point - point).length_squared();
+        if squared <= max_squared {
+            let position = found.partition_point(|&(distance, _)| distance <= squared);
+            if position < k {
+                found.insert(position, (squared, mid));
+                found.truncate(k);
+            }
+        }
+
+        let radius_squared = if found.len() == k {
+            found[k - 1].0
+        } else {
+            max_squared
+        };
+        if offset * offset <= radius_squared {
+            self.search(far.0, far.1, point, k, max_squared, found);
+        }
+    }
+
+    /// Caustic radiance leaving the diffuse surface of `rec` back along `ray`, from
+    /// the density of the photons around it.
+    pub fn radiance(&self, ray: &Ray, rec: &Hit) -> Vec3 {
+        let albedo = match rec.material {
+            Material::Lambertian(r, g, b) => Vec3(r, g, b),
+            _ => return Vec3(0., 0., 0.),
+        };
+        if self.photons.is_empty() {
+            return Vec3(0., 0., 0.);
+        }
+
+        let nearest = self.nearest(rec.point, GATHERED_PHOTONS);
+        //Too few photons around to tell how far they spread
+        let radius_squared = if nearest.len() == GATHERED_PHOTONS {
+            nearest[GATHERED_PHOTONS - 1].0
+        } else {
+            self.max_distance * self.max_distance
+        };
+        if radius_squared == 0. {
+            return Vec3(0., 0., 0.);
+        }
+
+        //Only photons arriving on the side the ray sees
+        let normal = if dot(&ray.dir, &rec.normal) < 0. {
+            rec.normal
+        } else {
+            -rec.normal
+        };
+        let flux = nearest
+            .iter()
+            .filter(|(_, photon)| dot(&photon.dir, &normal) < 0.)
+            .fold(Vec3(0., 0., 0.), |sum, (_, photon)| sum + photon.power);
+
+        albedo / PI * flux / (PI * radius_squared)
+    }
+}
+
+/// Sorts the photons into a balanced kd-tree, splitting each range at its median
+/// along the axis the range is widest in.
+fn build(photons: &mut [Photon], axes: &mut [u8]) {
+    if photons.len() <= 1 {
+        return;
+    }
+
+    let (min, max) = bounds(photons).unwrap();
+    let extent = max - min;
+    let axis = (0..3)
+        .max_by(|&a, &b| extent[a].total_cmp(&extent[b]))
+        .unwrap();
+
+    let mid = photons.len() / 2;
+    photons.select_nth_unstable_by(mid, |a, b| a.point[axis].total_cmp(&b.point[axis]));
+    axes[mid] = axis as u8;
+
+    let (below, rest) = photons.split_at_mut(mid);
+    let (axes_below, axes_rest) = axes.split_at_mut(mid);
+    build(below, axes_below);
+    build(&mut rest[1..], &mut axes_rest[1..]);
+}
+
+/// Smallest and largest coordinates of the photons, `None` without photons.
+fn bounds(photons: &[Photon]) -> Option<(Vec3, Vec3)> {
+    let first = photons.first()?.point;
+    Some(photons.iter().fold((first, first), |(min, max), photon| {
+        let p = photon.point;
+        (
+            Vec3(min.0.min(p.0), min.1.min(p.1), min.2.min(p.2)),
+            Vec3(max.0.max(p.0), max.1.max(p.1), max.2.max(p.2)),
+        )
+    }))
+}
+
+/// Follows a photon from a light through mirrors and glass, and returns it where it
+/// lands on a diffuse surface after at least one of them.
+fn caustic_photon(scene: &Scene, settings: &Settings, count: u32) -> Option<Photon> {
+    let emission = scene.lights.sample_emission()?;
+    if emission.pdf_direction == 0. {
+        return None;
+    }
+    let cosine = dot(&emission.normal, &emission.dir);
+    let power = emission.radiance
+        * (cosine / (emission.pdf_position * emission.pdf_direction * count as f64));
+
+    let mut ray = Ray {
+        origin: emission.point,
+        dir: emission.dir,
+    };
+    let mut beta = Vec3(1., 1., 1.);
+    let mut interior = Interior::default();
+
+    for depth in 0..settings.max_depth {
+        let mut rec = Hit::empty();
+        let hit_anything = trace(&scene.world, &ray, &mut rec);
+        //Light scattered by the fog makes no sharp caustics
+        if !hit_anything || fog_collision(scene, &ray, hit_anything, &rec).is_some() {
+            return None;
+        }
+        beta = beta * interior.transmittance(rec.t * ray.dir.length());
+
+        match rec.material {
+            _ if is_specular(rec.material) => {}
+            Material::Lambertian(..) if depth > 0 => {
+                return Some(Photon {
+                    point: rec.point,
+                    dir: unit_vector(&ray.dir),
+                    power: spectrum::to_rgb(power * beta),
+                })
+            }
+            _ => return None,
+        }
+
+        let mut scattered = Ray {
+            origin: Vec3(0., 0., 0.),
+            dir: Vec3(0., 0., 0.),
+        };
+        let mut attenuation = Vec3(0., 0., 0.);
+        if !scatter(rec.material, &ray, &rec, &mut attenuation, &mut scattered) {
+            return None;
+        }
+        interior.cross(&rec, &scattered);
+        beta = beta * attenuation;
+        ray = scattered;
+
+        if !survives_roulette(&mut beta, depth, settings) {
+            return None;
+        }
+    }
+    None
+}
+
+/// Path tracer with light sampling like `LightSamplingPathTracer`, taking the light
+/// that reaches its diffuse bounces through mirrors and glass from the caustic
+/// photon map of the scene instead.
+pub struct PhotonMappingPathTracer;
+
+impl Integrator for PhotonMappingPathTracer {
+    fn radiance(
+        &self,
+        ray: &Ray,
+        scene: &Scene,
+        settings: &Settings,
+        _splats: &mut Vec<(usize, Vec3)>,
+    ) -> Vec3 {
+        let mut radiance = Vec3(0., 0., 0.);
+        let mut throughput = Vec3(1., 1., 1.);
+        let mut ray = *ray;
+        let mut scattering_pdf: Option<f64> = None;
+        //Whether the path went from a diffuse surface through mirrors and glass only,
+        //the light it finds then is already in the photon map
+        let mut after_diffuse = false;
+        let mut specular = false;
+        let mut interior = Interior::default();
+
+        for depth in 0..settings.max_depth {
+            let mut rec = Hit::empty();
+
+            let hit_anything = trace(&scene.world, &ray, &mut rec);
+
+            if let Some(collision) = fog_collision(scene, &ray, hit_anything, &rec) {
+                rec = collision;
+                throughput = throughput * interior.transmittance(rec.t * ray.dir.length());
+            } else if !hit_anything {
+                return radiance + throughput * scene.background(ray.dir);
+            } else {
+                throughput = throughput * interior.transmittance(rec.t * ray.dir.length());
+                if !(after_diffuse && specular) {
+                    let weight = match scattering_pdf {
+                        Some(pdf) if is_light(rec.material) => {
+                            power_heuristic(pdf, scene.lights.pdf(ray.origin, rec.point))
+                        }
+                        _ => 1.,
+                    };
+                    radiance += throughput * color_emitted(rec.material, rec.front_face) * weight;
+                }
+            }
+
+            radiance += throughput * direct_light(scene, &ray, &rec, true);
+
+            match rec.material {
+                Material::Lambertian(..) => {
+                    if let Some(caustics) = &scene.caustics {
+                        //Photons are stored in RGB, whatever wavelengths they had
+                        radiance += throughput * illuminant(caustics.radiance(&ray, &rec));
+                    }
+                    after_diffuse = true;
+                    specular = false;
+                }
+                material if is_specular(material) => specular = true,
+                _ => after_diffuse = false,
+            }
+
+            let mut scattered = Ray {
+                origin: Vec3(0., 0., 0.),
+                dir: Vec3(0., 0., 0.),
+            };
+            let mut attenuation = Vec3(0., 0., 0.);
+            if !scatter(rec.material, &ray, &rec, &mut attenuation, &mut scattered) {
+                return radiance;
+            }
+
+            interior.cross(&rec, &scattered);
+            scattering_pdf = scattering(rec.material, &ray, &rec, &unit_vector(&scattered.dir))
+                .map(|(_, pdf)| pdf);
+            throughput = throughput * attenuation;
+            ray = scattered;
+
+            if !survives_roulette(&mut throughput, depth, settings) {
+                return radiance;
+            }
+        }
+
+        stats::record(|stats| stats.terminated_by_depth += 1);
+        radiance
+    }
+}
+
+#[cfg(test)]
+mod photons_tests {
+    use super::*;
+    use crate::{
+        camera::Camera,
+        hittables::Hittables::{HittableObjects, Sphere},
+        integrator::IntegratorKind,
+        materials::Material::{Dielectric, Lambertian, Light},
+        render::render_tile,
+        tiles::Tile,
+    };
+
+    #[test]
+    fn test_nearest_matches_brute_force() {
+        rand::seed(5, &[]);
+        let photons: Vec<Photon> = (0..500)
+            .map(|_| Photon {
+                point: Vec3(rand::next_f64(), rand::next_f64(), 0.2 * rand::next_f64()),
+                dir: Vec3(0., -1., 0.),
+                power: Vec3(1., 1., 1.),
+            })
+            .collect();
+        let map = PhotonMap::new(photons.clone());
+        assert_eq!(map.photons.len(), 500);
+
+        for _ in 0..20 {
+            let point = Vec3(rand::next_f64(), rand::next_f64(), 0.);
+            let mut expected: Vec<f64> = photons
+                .iter()
+                .map(|photon| (photon.point - point).length_squared())
+                .filter(|&squared| squared <= map.max_distance * map.max_distance)
+                .collect();
+            expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            expected.truncate(10);
+
+            let found: Vec<f64> = map
+                .nearest(point, 10)
+                .iter()
+                .map(|(squared, _)| *squared)
+                .collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    //A glass ball focusing a light onto the floor of a diffuse box
+    fn image_brightness(integrator: IntegratorKind, samples_per_pixel: u32) -> f64 {
+        let mut scene = Scene::new(
+            HittableObjects(vec![
+                Sphere(Vec3(0., 0., 0.), -10., Lambertian(0.7, 0.7, 0.7)),
+                Sphere(Vec3(0., 6., 0.), 1.5, Light(4., 4., 4.)),
+                Sphere(Vec3(0., 0., 0.), 2., Dielectric(1.5)),
+            ]),
+            None,
+            Camera::new(
+                Vec3(0., 2., 8.),
+                Vec3(0., -4., 0.),
+                Vec3(0., 1., 0.),
+                70.,
+                4. / 3.,
+            ),
+        );
+        let settings = Settings {
+            width: 8,
+            height: 6,
+            samples_per_pixel,
+            max_depth: 16,
+            russian_roulette_depth: 4,
+            seed: 11,
+            integrator,
+            spectral: false,
+        };
+        scene.prepare(&settings);
+
+        let tile = Tile::image(settings.width, settings.height);
+        let mut pixels = vec![Vec3(0., 0., 0.); settings.width * settings.height];
+        let mut samples = vec![0; pixels.len()];
+        render_tile(
+            &scene,
+            &settings,
+            &tile,
+            samples_per_pixel,
+            &mut pixels,
+            &mut samples,
+            &mut Vec::new(),
+        );
+        let sum = pixels
+            .iter()
+            .fold(Vec3(0., 0., 0.), |sum, pixel| sum + *pixel);
+        (sum.0 + sum.1 + sum.2) / (3. * samples_per_pixel as f64 * pixels.len() as f64)
+    }
+
+    #[test]
+    fn test_converges_to_path_tracing() {
+        let path = image_brightness(IntegratorKind::LightSampling, 1000);
+        let photons = image_brightness(IntegratorKind::PhotonMapping(200_000), 100);
+        assert!(
+            f64::abs(path - photons) < 0.02 * path,
+            "path tracing {} != photon mapping {}",
+            path,
+            photons
+        );
+    }
+}
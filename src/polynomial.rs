@@ -0,0 +1,184 @@
+use crate::utils::PI;
+
+const EPSILON: f64 = 1e-9;
+
+#[inline(always)]
+fn is_zero(x: f64) -> bool {
+    x > -EPSILON && x < EPSILON
+}
+
+/// Real roots of `c[2]x² + c[1]x + c[0]`.
+pub fn solve_quadratic(c: [f64; 3]) -> Vec<f64> {
+    let p = c[1] / (2. * c[2]);
+    let q = c[0] / c[2];
+    let d = p * p - q;
+
+    if is_zero(d) {
+        vec![-p]
+    } else if d < 0. {
+        Vec::new()
+    } else {
+        let sqrt_d = f64::sqrt(d);
+        vec![sqrt_d - p, -sqrt_d - p]
+    }
+}
+
+/// Real roots of `c[3]x³ + c[2]x² + c[1]x + c[0]`.
+pub fn solve_cubic(c: [f64; 4]) -> Vec<f64> {
+    let a = c[2] / c[3];
+    let b = c[1] / c[3];
+    let c = c[0] / c[3];
+
+    //Substitute x = y - a/3 to eliminate the quadric term: y³ + 3py + 2q = 0
+    let sq_a = a * a;
+    let p = 1. / 3. * (-1. / 3. * sq_a + b);
+    let q = 1. / 2. * (2. / 27. * a * sq_a - 1. / 3. * a * b + c);
+
+    let cb_p = p * p * p;
+    let d = q * q + cb_p;
+
+    let mut roots = if is_zero(d) {
+        if is_zero(q) {
+            vec![0.]
+        } else {
+            let u = f64::cbrt(-q);
+            vec![2. * u, -u]
+        }
+    } else if d < 0. {
+        let phi = 1. / 3. * f64::acos(-q / f64::sqrt(-cb_p));
+        let t = 2. * f64::sqrt(-p);
+        vec![
+            t * f64::cos(phi),
+            -t * f64::cos(phi + PI / 3.),
+            -t * f64::cos(phi - PI / 3.),
+        ]
+    } else {
+        let sqrt_d = f64::sqrt(d);
+        vec![f64::cbrt(sqrt_d - q) - f64::cbrt(sqrt_d + q)]
+    };
+
+    let sub = 1. / 3. * a;
+    roots.iter_mut().for_each(|root| *root -= sub);
+    roots
+}
+
+/// Real roots of `c[4]x⁴ + c[3]x³ + c[2]x² + c[1]x + c[0]` (Ferrari's method).
+pub fn solve_quartic(c: [f64; 5]) -> Vec<f64> {
+    let a = c[3] / c[4];
+    let b = c[2] / c[4];
+    let c_ = c[1] / c[4];
+    let d = c[0] / c[4];
+
+    //Substitute x = y - a/4 to eliminate the cubic term: y⁴ + py² + qy + r = 0
+    let sq_a = a * a;
+    let p = -3. / 8. * sq_a + b;
+    let q = 1. / 8. * sq_a * a - 1. / 2. * a * b + c_;
+    let r = -3. / 256. * sq_a * sq_a + 1. / 16. * sq_a * b - 1. / 4. * a * c_ + d;
+
+    let mut roots = if is_zero(r) {
+        let mut roots = solve_cubic([q, p, 0., 1.]);
+        roots.push(0.);
+        roots
+    } else {
+        let cubic = solve_cubic([1. / 2. * r * p - 1. / 8. * q * q, -r, -1. / 2. * p, 1.]);
+        let z = cubic[0];
+
+        let mut u = z * z - r;
+        let mut v = 2. * z - p;
+
+        if is_zero(u) {
+            u = 0.;
+        } else if u > 0. {
+            u = f64::sqrt(u);
+        } else {
+            return Vec::new();
+        }
+
+        if is_zero(v) {
+            v = 0.;
+        } else if v > 0. {
+            v = f64::sqrt(v);
+        } else {
+            return Vec::new();
+        }
+
+        let mut roots = solve_quadratic([z - u, if q < 0. { -v } else { v }, 1.]);
+        roots.extend(solve_quadratic([z + u, if q < 0. { v } else { -v }, 1.]));
+        roots
+    };
+
+    let sub = 1. / 4. * a;
+    roots.iter_mut().for_each(|root| *root -= sub);
+    roots
+}
+
+/// Evaluates the polynomial with coefficients in ascending order at `x`.
+pub fn evaluate(c: &[f64], x: f64) -> f64 {
+    c.iter()
+        .rev()
+        .fold(0., |acc, coefficient| acc * x + coefficient)
+}
+
+/// Polishes an approximate root with a few Newton-Raphson iterations.
+pub fn refine_root(c: &[f64], mut x: f64) -> f64 {
+    let derivative: Vec<f64> = c
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, coefficient)| i as f64 * coefficient)
+        .collect();
+
+    for _ in 0..4 {
+        let slope = evaluate(&derivative, x);
+        if is_zero(slope) {
+            break;
+        }
+        x -= evaluate(c, x) / slope;
+    }
+    x
+}
+
+#[cfg(test)]
+mod polynomial_tests {
+    use super::*;
+
+    fn sorted(mut roots: Vec<f64>) -> Vec<f64> {
+        roots.sort_by(f64::total_cmp);
+        roots
+    }
+
+    fn assert_roots(roots: Vec<f64>, expected: &[f64]) {
+        let roots = sorted(roots);
+        assert_eq!(roots.len(), expected.len(), "{:?}", roots);
+        for (root, expected) in roots.iter().zip(expected) {
+            assert!(f64::abs(root - expected) < 1e-6, "{:?}", roots);
+        }
+    }
+
+    #[test]
+    fn test_quadratic() {
+        //(x - 1)(x - 3)
+        assert_roots(solve_quadratic([3., -4., 1.]), &[1., 3.]);
+        assert_roots(solve_quadratic([1., 0., 1.]), &[]);
+    }
+
+    #[test]
+    fn test_cubic() {
+        //(x - 1)(x - 2)(x + 3)
+        assert_roots(solve_cubic([6., -7., 0., 1.]), &[-3., 1., 2.]);
+    }
+
+    #[test]
+    fn test_quartic() {
+        //(x - 1)(x - 2)(x - 3)(x - 4)
+        assert_roots(solve_quartic([24., -50., 35., -10., 1.]), &[1., 2., 3., 4.]);
+        //(x² + 1)(x² + 2)
+        assert_roots(solve_quartic([2., 0., 3., 0., 1.]), &[]);
+    }
+
+    #[test]
+    fn test_refine_root() {
+        let c = [-2., 0., 1.];
+        assert!(f64::abs(refine_root(&c, 1.4) - f64::sqrt(2.)) < 1e-12);
+    }
+}
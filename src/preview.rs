@@ -0,0 +1,181 @@
+use std::{
+    io::{stdout, IsTerminal, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    cursor,
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+    terminal, QueueableCommand,
+};
+
+const REFRESH_INTERVAL: Duration = Duration::from_millis(250);
+
+struct Pixels {
+    //Latest color of every image pixel, `None` until it is first rendered
+    colors: Vec<Option<(u8, u8, u8)>>,
+    last_draw: Option<Instant>,
+}
+
+/// Live preview of the render in the terminal. Each character cell shows two preview
+/// pixels with the upper half block `▀`, using 24-bit foreground and background colors.
+pub struct Preview {
+    image_width: usize,
+    image_height: usize,
+    width: usize,
+    height: usize,
+    pixels: Mutex<Pixels>,
+}
+
+impl Preview {
+    /// Fits the preview into the terminal and clears the screen for it, leaving the line
+    /// below for the progress report. `None` if stdout is not a terminal.
+    pub fn new(image_width: usize, image_height: usize) -> Option<Preview> {
+        if !stdout().is_terminal() {
+            return None;
+        }
+        let (columns, lines) = terminal::size().ok()?;
+        let (width, height) = fit(
+            image_width,
+            image_height,
+            columns as usize,
+            (lines as usize).saturating_sub(2),
+        );
+
+        let mut stdout = stdout().lock();
+        stdout
+            .queue(terminal::Clear(terminal::ClearType::All))
+            .ok()?;
+        stdout
+            .queue(cursor::MoveTo(0, height.div_ceil(2) as u16))
+            .ok()?;
+        stdout.flush().ok()?;
+
+        Some(Preview {
+            image_width,
+            image_height,
+            width,
+            height,
+            pixels: Mutex::new(Pixels {
+                colors: vec![None; image_width * image_height],
+                last_draw: None,
+            }),
+        })
+    }
+
+    /// Sets a horizontal run of pixels starting at `(x, y)`, with `y` counted from the
+    /// top of the image, replacing what earlier passes showed there. Redraws if the last
+    /// refresh is long enough ago.
+    pub fn update(&self, x: usize, y: usize, colors: &[(u8, u8, u8)]) {
+        let mut pixels = self.pixels.lock().unwrap();
+        let start = y * self.image_width + x;
+        for (pixel, color) in pixels.colors[start..start + colors.len()]
+            .iter_mut()
+            .zip(colors)
+        {
+            *pixel = Some(*color);
+        }
+
+        if pixels
+            .last_draw
+            .is_some_and(|last| last.elapsed() < REFRESH_INTERVAL)
+        {
+            return;
+        }
+        pixels.last_draw = Some(Instant::now());
+        self.draw(&pixels);
+    }
+
+    /// Draws the current state regardless of the refresh interval.
+    pub fn finish(&self) {
+        let pixels = self.pixels.lock().unwrap();
+        self.draw(&pixels);
+    }
+
+    /// Averages the rendered image pixels falling into each preview pixel.
+    fn cells(&self, pixels: &Pixels) -> Vec<Color> {
+        let mut sums = vec![(0, 0, 0, 0); self.width * self.height];
+        for (i, color) in pixels.colors.iter().enumerate() {
+            if let Some(color) = color {
+                let px = (i % self.image_width) * self.width / self.image_width;
+                let py = (i / self.image_width) * self.height / self.image_height;
+                let sum: &mut (u32, u32, u32, u32) = &mut sums[py * self.width + px];
+                sum.0 += color.0 as u32;
+                sum.1 += color.1 as u32;
+                sum.2 += color.2 as u32;
+                sum.3 += 1;
+            }
+        }
+
+        sums.iter()
+            .map(|&(r, g, b, count)| match count {
+                0 => Color::Rgb { r: 0, g: 0, b: 0 },
+                _ => Color::Rgb {
+                    r: (r / count) as u8,
+                    g: (g / count) as u8,
+                    b: (b / count) as u8,
+                },
+            })
+            .collect()
+    }
+
+    fn draw(&self, pixels: &Pixels) {
+        let cells = self.cells(pixels);
+        //Odd preview heights leave the bottom half of the last line black
+        let color = |x: usize, y: usize| {
+            cells
+                .get(y * self.width + x)
+                .copied()
+                .unwrap_or(Color::Rgb { r: 0, g: 0, b: 0 })
+        };
+
+        let mut stdout = stdout().lock();
+        stdout.queue(cursor::SavePosition).unwrap();
+        for line in 0..self.height.div_ceil(2) {
+            stdout.queue(cursor::MoveTo(0, line as u16)).unwrap();
+            for x in 0..self.width {
+                stdout
+                    .queue(SetForegroundColor(color(x, 2 * line)))
+                    .unwrap();
+                stdout
+                    .queue(SetBackgroundColor(color(x, 2 * line + 1)))
+                    .unwrap();
+                stdout.queue(Print('▀')).unwrap();
+            }
+            stdout.queue(ResetColor).unwrap();
+        }
+        stdout.queue(cursor::RestorePosition).unwrap();
+        stdout.flush().unwrap();
+    }
+}
+
+/// Preview size in pixels for the terminal, two pixels per character cell vertically,
+/// keeping the aspect ratio of the image.
+fn fit(image_width: usize, image_height: usize, columns: usize, lines: usize) -> (usize, usize) {
+    let aspect = image_width as f64 / image_height as f64;
+    let mut width = usize::min(columns, image_width);
+    let mut height = (width as f64 / aspect).round() as usize;
+
+    if height > 2 * lines {
+        height = 2 * lines;
+        width = (height as f64 * aspect).round() as usize;
+    }
+
+    (usize::max(width, 1), usize::max(height, 1))
+}
+
+#[cfg(test)]
+mod preview_tests {
+    use super::*;
+
+    #[test]
+    fn test_fit() {
+        //Limited by the terminal width
+        assert_eq!(fit(1920, 1080, 80, 100), (80, 45));
+        //Limited by the terminal height
+        assert_eq!(fit(1920, 1080, 200, 20), (71, 40));
+        //Never larger than the image
+        assert_eq!(fit(16, 9, 200, 100), (16, 9));
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    cell::Cell,
+    io::{stdout, IsTerminal, Write},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crossterm::{
+    cursor,
+    style::{Color, Stylize},
+    terminal, QueueableCommand,
+};
+
+//Redraw interval of the progress line on a terminal
+const TTY_INTERVAL: Duration = Duration::from_millis(100);
+//Interval between log lines when stdout is redirected
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+thread_local! {
+    static RAYS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Counts a traced ray on the current thread, collected by the next `Progress::advance`.
+#[inline(always)]
+pub fn count_ray() {
+    RAYS.with(|rays| rays.set(rays.get() + 1));
+}
+
+/// Takes the rays counted on the current thread since the last call.
+pub fn take_rays() -> u64 {
+    RAYS.with(|rays| rays.replace(0))
+}
+
+/// Counts rays traced elsewhere, e.g. by a remote worker, on the current thread.
+pub fn count_rays(n: u64) {
+    RAYS.with(|rays| rays.set(rays.get() + n));
+}
+
+/// Thread-safe render progress, updated from the worker threads as units of work
+/// (rows, tiles, passes) complete.
+pub struct Progress {
+    total: usize,
+    //Time to render for instead of a fixed amount of work
+    budget: Option<Duration>,
+    unit: &'static str,
+    done: AtomicUsize,
+    rays: AtomicU64,
+    start: Instant,
+    tty: bool,
+    last_report: Mutex<Option<Instant>>,
+}
+
+impl Progress {
+    pub fn new(total: usize, unit: &'static str) -> Progress {
+        Progress {
+            total,
+            budget: None,
+            unit,
+            done: AtomicUsize::new(0),
+            rays: AtomicU64::new(0),
+            start: Instant::now(),
+            tty: stdout().is_terminal(),
+            last_report: Mutex::new(None),
+        }
+    }
+
+    /// Progress of a render that runs for `budget` however much work gets done.
+    pub fn timed(budget: Duration, unit: &'static str) -> Progress {
+        Progress {
+            budget: Some(budget),
+            ..Progress::new(0, unit)
+        }
+    }
+
+    /// Marks `n` units as done and reports if enough time has passed since the last report.
+    pub fn advance(&self, n: usize) {
+        self.rays.fetch_add(take_rays(), Ordering::Relaxed);
+        self.done.fetch_add(n, Ordering::Relaxed);
+
+        //Skip the report rather than wait when another thread is printing
+        let mut last_report = match self.last_report.try_lock() {
+            Ok(last_report) => last_report,
+            Err(_) => return,
+        };
+        let interval = if self.tty { TTY_INTERVAL } else { LOG_INTERVAL };
+        if last_report.is_some_and(|last| last.elapsed() < interval) {
+            return;
+        }
+        *last_report = Some(Instant::now());
+        self.report(false);
+    }
+
+    pub fn done(&self) -> usize {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    pub fn rays(&self) -> u64 {
+        self.rays.load(Ordering::Relaxed)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Prints the final state and ends the progress line.
+    pub fn finish(&self) {
+        let _lock = self.last_report.lock().unwrap();
+        self.report(true);
+    }
+
+    fn report(&self, finished: bool) {
+        let elapsed = self.elapsed().as_secs_f64();
+        let (fraction, work) = match self.budget {
+            Some(budget) => (
+                f64::min(elapsed / budget.as_secs_f64(), 1.),
+                format!("{} {}", self.done(), self.unit),
+            ),
+            None => {
+                let done = usize::min(self.done(), self.total);
+                let fraction = if self.total == 0 {
+                    1.
+                } else {
+                    done as f64 / self.total as f64
+                };
+                (fraction, format!("{}/{} {}", done, self.total, self.unit))
+            }
+        };
+        let eta = if fraction > 0. {
+            elapsed / fraction - elapsed
+        } else {
+            f64::INFINITY
+        };
+
+        let line = format!(
+            "{:5.1}% ({}), elapsed {}, ETA {}, {} rays/s",
+            100. * fraction,
+            work,
+            format_duration(elapsed),
+            format_duration(eta),
+            format_rate(self.rays() as f64 / f64::max(elapsed, 1e-9)),
+        );
+
+        let mut stdout = stdout().lock();
+        if self.tty {
+            let value = (fraction * 255.) as u8;
+            let color = Color::Rgb {
+                r: 255 - value,
+                g: value,
+                b: 0,
+            };
+            stdout.queue(cursor::MoveToColumn(0)).unwrap();
+            stdout
+                .queue(terminal::Clear(terminal::ClearType::CurrentLine))
+                .unwrap();
+            write!(
+                stdout,
+                "{} {}",
+                "Rendering:".bold().dark_magenta(),
+                line.with(color)
+            )
+            .unwrap();
+            if finished {
+                writeln!(stdout).unwrap();
+            }
+        } else {
+            writeln!(stdout, "Rendering: {}", line).unwrap();
+        }
+        stdout.flush().unwrap();
+    }
+}
+
+/// Human readable duration, e.g. `42.0s`, `3m07s` or `1h02m03s`.
+pub fn format_duration(seconds: f64) -> String {
+    if !seconds.is_finite() {
+        return "--".to_string();
+    }
+    let whole = seconds.round() as u64;
+    if seconds < 60. {
+        format!("{:.1}s", seconds)
+    } else if whole < 3600 {
+        format!("{}m{:02}s", whole / 60, whole % 60)
+    } else {
+        format!("{}h{:02}m{:02}s", whole / 3600, whole / 60 % 60, whole % 60)
+    }
+}
+
+/// Rate with a metric prefix, e.g. `12.3M`.
+pub fn format_rate(rate: f64) -> String {
+    if rate >= 1e9 {
+        format!("{:.2}G", rate / 1e9)
+    } else if rate >= 1e6 {
+        format!("{:.2}M", rate / 1e6)
+    } else if rate >= 1e3 {
+        format!("{:.2}k", rate / 1e3)
+    } else {
+        format!("{:.0}", rate)
+    }
+}
+
+#[cfg(test)]
+mod progress_tests {
+    use super::*;
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(4.3), "4.3s");
+        assert_eq!(format_duration(187.), "3m07s");
+        assert_eq!(format_duration(3723.), "1h02m03s");
+        assert_eq!(format_duration(f64::INFINITY), "--");
+    }
+
+    #[test]
+    fn test_format_rate() {
+        assert_eq!(format_rate(512.), "512");
+        assert_eq!(format_rate(12_345_678.), "12.35M");
+        assert_eq!(format_rate(2.5e9), "2.50G");
+    }
+
+    #[test]
+    fn test_advance_collects_rays() {
+        let progress = Progress::new(4, "rows");
+        for _ in 0..10 {
+            count_ray();
+        }
+        progress.advance(1);
+        progress.advance(1);
+        assert_eq!(progress.done(), 2);
+        assert_eq!(progress.rays(), 10);
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    cell::{Cell, RefCell},
+    mem,
+};
+
+use crate::metropolis::PrimarySamples;
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(::rand::random());
+    /// Samples handed out instead of the random sequence, see `with_primary_samples`.
+    static PRIMARY: RefCell<Option<PrimarySamples>> = const { RefCell::new(None) };
+}
+
+/// SplitMix64 step, advances `state` and returns the next output.
+fn split_mix(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Restarts the random sequence of the current thread from a state derived from
+/// `seed` and the given indices, e.g. a pixel and the number of its sample. Renders
+/// seeded this way come out the same however the work is split and in whatever
+/// order it runs.
+pub fn seed(seed: u64, indices: &[u64]) {
+    let mut state = seed;
+    for index in indices {
+        state = split_mix(&mut state) ^ index;
+    }
+    STATE.with(|cell| cell.set(split_mix(&mut state)));
+}
+
+pub fn next_u64() -> u64 {
+    STATE.with(|cell| {
+        let mut state = cell.get();
+        let value = split_mix(&mut state);
+        cell.set(state);
+        value
+    })
+}
+
+/// Uniform in [0, 1) with the full 53 bits of precision, or the next primary sample
+/// inside `with_primary_samples`.
+pub fn next_f64() -> f64 {
+    PRIMARY.with(|primary| match primary.borrow_mut().as_mut() {
+        Some(samples) => samples.next(),
+        None => uniform(),
+    })
+}
+
+/// Uniform in [0, 1) from the random sequence, also inside `with_primary_samples`.
+pub fn uniform() -> f64 {
+    (next_u64() >> 11) as f64 * (1. / (1u64 << 53) as f64)
+}
+
+/// Runs `f` with `next_f64` handing out `samples`, so Metropolis sampling can
+/// mutate the random numbers a path is built from.
+pub fn with_primary_samples<R>(samples: &mut PrimarySamples, f: impl FnOnce() -> R) -> R {
+    PRIMARY.with(|primary| *primary.borrow_mut() = Some(mem::take(samples)));
+    let result = f();
+    *samples = PRIMARY.with(|primary| primary.borrow_mut().take()).unwrap();
+    result
+}
+
+#[cfg(test)]
+mod rand_tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_sequences_repeat() {
+        seed(7, &[12, 3]);
+        let a: Vec<f64> = (0..4).map(|_| next_f64()).collect();
+        seed(7, &[12, 3]);
+        let b: Vec<f64> = (0..4).map(|_| next_f64()).collect();
+        seed(7, &[12, 4]);
+        let c: Vec<f64> = (0..4).map(|_| next_f64()).collect();
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert!(a.iter().all(|x| (0. ..1.).contains(x)));
+    }
+
+    #[test]
+    fn test_uniform_mean() {
+        seed(1, &[]);
+        let n = 100_000;
+        let mean = (0..n).map(|_| next_f64()).sum::<f64>() / n as f64;
+        assert!(f64::abs(mean - 0.5) < 0.01);
+    }
+}
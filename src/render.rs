@@ -0,0 +1,171 @@
+use std::fmt::{self, Write};
+
+use crate::{
+    camera::Camera, hittables::Hittables, integrator::IntegratorKind, lights::Lights, medium::Fog,
+    metropolis::Bootstrap, photons::PhotonMap, rand, sky::Sky, spectrum, stats, tiles::Tile,
+    utils::random_double, vec3::Vec3,
+};
+
+/// Everything that is rendered.
+pub struct Scene {
+    pub world: Hittables,
+    pub fog: Option<Fog>,
+    pub camera: Camera,
+    /// The lights of the world, for integrators sampling them directly.
+    pub lights: Lights,
+    /// Seen where rays escape the world, black without.
+    pub sky: Option<Sky>,
+    /// Photons focused onto diffuse surfaces by mirrors and glass, for photon mapping.
+    pub caustics: Option<PhotonMap>,
+    /// Brightness of the image and starting paths, for Metropolis light transport.
+    pub bootstrap: Option<Bootstrap>,
+}
+
+impl Scene {
+    pub fn new(world: Hittables, fog: Option<Fog>, camera: Camera) -> Scene {
+        let lights = Lights::new(&world);
+        Scene {
+            world,
+            fog,
+            camera,
+            lights,
+            sky: None,
+            caustics: None,
+            bootstrap: None,
+        }
+    }
+
+    /// Puts the world under the sky, adding its sun to the lights.
+    pub fn set_sky(&mut self, sky: Sky) {
+        self.lights.add(sky.sun());
+        self.sky = Some(sky);
+    }
+
+    /// Radiance arriving along a ray in the direction `dir` that escaped the world.
+    pub fn background(&self, dir: Vec3) -> Vec3 {
+        match &self.sky {
+            Some(sky) => spectrum::illuminant(sky.radiance(dir)),
+            None => Vec3(0., 0., 0.),
+        }
+    }
+
+    /// Hash of everything the scene is built from, the same on every machine for the
+    /// same scene, to tell whether two processes render the same one. Leaves out what
+    /// `prepare` derives from it.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = Fnv(0xcbf2_9ce4_8422_2325);
+        write!(
+            hash,
+            "{:?} {:?} {:?} {:?} {:?}",
+            self.world, self.fog, self.camera, self.lights, self.sky
+        )
+        .unwrap();
+        hash.0
+    }
+
+    /// Does the work the settings call for before rendering, such as tracing the
+    /// photons of the photon map. Returns the name of the work done, if any.
+    pub fn prepare(&mut self, settings: &Settings) -> Option<&'static str> {
+        self.caustics = None;
+        self.bootstrap = None;
+        match settings.integrator {
+            IntegratorKind::PhotonMapping(count) => {
+                self.caustics = Some(PhotonMap::caustics(self, settings, count));
+                Some("photons")
+            }
+            IntegratorKind::Metropolis { .. } => {
+                self.bootstrap = Some(Bootstrap::new(self, settings));
+                Some("bootstrap")
+            }
+            _ => None,
+        }
+    }
+}
+
+/// FNV-1a over the text written to it, which unlike the hashers of the standard
+/// library is the same across builds.
+struct Fnv(u64);
+
+impl Write for Fnv {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        for byte in text.bytes() {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100_0000_01b3);
+        }
+        Ok(())
+    }
+}
+
+/// Settings every part of a render has to agree on, also when it is split across
+/// processes or resumed later.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Settings {
+    pub width: usize,
+    pub height: usize,
+    /// Samples each pixel ends up with, `u32::MAX` to render until stopped.
+    pub samples_per_pixel: u32,
+    /// Hard limit on the rays along a path.
+    pub max_depth: i32,
+    /// Depth from which paths are ended at random by Russian roulette.
+    pub russian_roulette_depth: i32,
+    pub seed: u64,
+    pub integrator: IntegratorKind,
+    /// Trace light at sampled wavelengths rather than in RGB, see `spectrum`.
+    pub spectral: bool,
+}
+
+/// Adds up to `pass_samples` samples to every pixel of the tile, stopping at the
+/// samples per pixel of the settings. `pixels` and `samples` hold the sums and sample
+/// counts of the tile so far, as returned by `Framebuffer::tile`, light landing in
+/// other pixels is added to `splats`.
+pub fn render_tile(
+    scene: &Scene,
+    settings: &Settings,
+    tile: &Tile,
+    pass_samples: u32,
+    pixels: &mut [Vec3],
+    samples: &mut [u32],
+    splats: &mut Vec<(usize, Vec3)>,
+) {
+    let integrator = settings.integrator.integrator();
+    for (i, (pixel, count)) in pixels.iter_mut().zip(samples.iter_mut()).enumerate() {
+        let x = tile.x + i % tile.width;
+        let y = tile.y + i / tile.width;
+        //Rows are stored from the top, the camera counts from the bottom
+        let row = settings.height - 1 - y;
+
+        //The last pass takes whatever is left of the samples
+        let target = count.saturating_add(pass_samples);
+        while *count < u32::min(target, settings.samples_per_pixel) {
+            //Every sample has its own random sequence, so resumed and distributed
+            //renders come out exactly like uninterrupted ones
+            rand::seed(
+                settings.seed,
+                &[(y * settings.width + x) as u64, *count as u64],
+            );
+
+            let u = (x as f64 + random_double()) / (settings.width - 1) as f64;
+            let v = (row as f64 + random_double()) / (settings.height - 1) as f64;
+
+            spectrum::set_wavelengths(
+                settings
+                    .spectral
+                    .then(|| spectrum::sample_wavelengths(random_double())),
+            );
+
+            let r = scene.camera.get_ray(u, v);
+            //Metropolis light transport leaves the ray aside and counts its own
+            if !matches!(settings.integrator, IntegratorKind::Metropolis { .. }) {
+                stats::record(|stats| stats.primary_rays += 1);
+            }
+
+            let first_splat = splats.len();
+            let radiance = spectrum::to_rgb(integrator.radiance(&r, scene, settings, splats));
+            *pixel += scene.camera.exposure * radiance;
+            for (_, splat) in &mut splats[first_splat..] {
+                *splat *= scene.camera.exposure;
+            }
+            *count += 1;
+        }
+    }
+    spectrum::set_wavelengths(None);
+}
@@ -0,0 +1,541 @@
+//! The scenes to render, chosen with `--scene`.
+
+use std::{io, sync::Arc};
+
+use crate::{
+    camera::Camera,
+    csg::CsgOperation,
+    hittables::{xy_rect, xz_rect, yz_rect, Hittables, Hittables::*},
+    lights::{with_power, Punctual},
+    materials::{Glass, Material::*},
+    mesh::Mesh,
+    rand,
+    render::Scene,
+    sdf::Sdf,
+    sky::{sun_direction, Sky},
+    spectrum::Illuminant,
+    utils::random_double,
+    vec3::Vec3,
+    voxel::VoxelGrid,
+};
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum SceneKind {
+    /// Metal, glass and diffuse spheres on a rough mirror.
+    Spheres,
+    /// One of each shape in the corner of a room, under a bright sphere and the sun.
+    Shapes,
+    /// A closed box lit through the ceiling, with smoke, a block of haze and green
+    /// glass.
+    Cornell,
+    /// Glass lenses and a capsule built with constructive solid geometry.
+    Lenses,
+    /// A Mandelbulb beside blended, twisted and repeated distance fields, outdoors.
+    Fractal,
+    /// A pyramid and other shapes on the ground under an afternoon sky.
+    Daylight,
+    /// Hundreds of small spheres on the ground at night, a tenth of them lamps.
+    Lamps,
+    /// The voxel grid in the file as smoke on the ground, outdoors.
+    Volume(String),
+}
+
+impl SceneKind {
+    /// The scene for the name given to `--scene`.
+    pub fn parse(name: &str) -> Option<SceneKind> {
+        match name {
+            "spheres" => Some(SceneKind::Spheres),
+            "shapes" => Some(SceneKind::Shapes),
+            "cornell" => Some(SceneKind::Cornell),
+            "lenses" => Some(SceneKind::Lenses),
+            "fractal" => Some(SceneKind::Fractal),
+            "daylight" => Some(SceneKind::Daylight),
+            "lamps" => Some(SceneKind::Lamps),
+            _ => {
+                let path = name.strip_prefix("volume:")?;
+                (!path.is_empty()).then(|| SceneKind::Volume(path.to_string()))
+            }
+        }
+    }
+
+    /// Builds the scene seen through a camera with the aspect ratio. Only volumes read
+    /// a file and can fail.
+    pub fn build(&self, aspect_ratio: f64) -> io::Result<Scene> {
+        Ok(match self {
+            SceneKind::Spheres => spheres(aspect_ratio),
+            SceneKind::Shapes => shapes(aspect_ratio),
+            SceneKind::Cornell => cornell(aspect_ratio),
+            SceneKind::Lenses => lenses(aspect_ratio),
+            SceneKind::Fractal => fractal(aspect_ratio),
+            SceneKind::Daylight => daylight(aspect_ratio),
+            SceneKind::Lamps => lamps(aspect_ratio),
+            SceneKind::Volume(path) => volume(VoxelGrid::load(path)?, aspect_ratio),
+        })
+    }
+}
+
+fn spheres(aspect_ratio: f64) -> Scene {
+    let hittables = vec![
+        Plane(
+            Vec3(0., -0.5, 0.),
+            Vec3(0., 1., 0.),
+            Metal(0.8, 0.8, 0.8, 0.2),
+        ),
+        Sphere(Vec3(0.9, 0., -1.), 0.5, Metal(1., 1., 1., 0.0)),
+        Sphere(Vec3(-0.9, 0., -1.), -0.5, Dielectric(2.2)),
+        Sphere(Vec3(0., -0.25, -0.25), 0.25, Lambertian(0.94, 0.81, 0.66)),
+        Sphere(Vec3(1.25, -0.25, -0.25), 0.25, Lambertian(0.6, 0.76, 0.73)),
+        Sphere(Vec3(-1.25, -0.25, -0.25), 0.25, Lambertian(0.84, 0.55, 0.8)),
+    ];
+
+    let camera = Camera::new(
+        Vec3(0., 0.2, 2.),
+        Vec3(0., 0., 0.),
+        Vec3(0., 1., 0.),
+        50.,
+        aspect_ratio,
+    );
+    Scene::new(HittableObjects(hittables), None, camera)
+}
+
+fn shapes(aspect_ratio: f64) -> Scene {
+    let white = Lambertian(0.73, 0.73, 0.73);
+    //Square pyramid standing on its base
+    let pyramid = Mesh::new(
+        vec![
+            Vec3(-2.2, 0., 0.),
+            Vec3(-1.6, 0., 0.),
+            Vec3(-1.6, 0., 0.6),
+            Vec3(-2.2, 0., 0.6),
+            Vec3(-1.9, 0.5, 0.3),
+        ],
+        vec![
+            [0, 2, 1],
+            [0, 3, 2],
+            [0, 1, 4],
+            [1, 2, 4],
+            [2, 3, 4],
+            [3, 0, 4],
+        ],
+    );
+    let hittables = vec![
+        xz_rect(-3., 3., -3., 2., 0., white),
+        xy_rect(-3., 3., 0., 3., -3., white),
+        yz_rect(0., 3., -3., 2., -3., Lambertian(0.65, 0.05, 0.05)),
+        Cuboid(Vec3(-2.2, 0., -2.2), Vec3(-1.4, 0.8, -1.4), white),
+        Quad(
+            Vec3(1.6, 0., -2.4),
+            Vec3(1., 0., 0.4),
+            Vec3(0., 1.4, 0.),
+            Metal(0.9, 0.9, 0.9, 0.05),
+        ),
+        Disk(
+            Vec3(0., 0.001, -0.5),
+            Vec3(0., 1., 0.),
+            0.6,
+            Lambertian(0.12, 0.45, 0.15),
+        ),
+        Cylinder(
+            Vec3(-0.8, 0., -1.),
+            Vec3(0., 0.9, 0.),
+            0.3,
+            Lambertian(0.2, 0.3, 0.6),
+        ),
+        Cone(
+            Vec3(0.8, 0., -1.),
+            Vec3(0., 0.9, 0.),
+            0.35,
+            Lambertian(0.8, 0.6, 0.2),
+        ),
+        Torus(
+            Vec3(0., 0.25, -0.5),
+            Vec3(0., 1., 0.2),
+            0.4,
+            0.12,
+            Metal(0.9, 0.6, 0.3, 0.2),
+        ),
+        Mesh(Arc::new(pyramid), Lambertian(0.85, 0.75, 0.55)),
+        Triangle(
+            Vec3(1.2, 0., 0.3),
+            Vec3(1.8, 0., 0.6),
+            Vec3(1.5, 0.5, 0.4),
+            Lambertian(0.7, 0.2, 0.2),
+        ),
+        Sphere(Vec3(0., 6., 1.), 2., Light(4., 4., 4.)),
+    ];
+
+    let camera = Camera::new(
+        Vec3(0., 1.5, 3.),
+        Vec3(0., 0.4, -1.),
+        Vec3(0., 1., 0.),
+        45.,
+        aspect_ratio,
+    );
+    //Sunlight coming in over the open side
+    let mut scene = Scene::new(HittableObjects(hittables), None, camera);
+    scene.lights.add(Punctual::Directional(
+        Vec3(1., 2., 1.),
+        0.53,
+        Light(1., 0.95, 0.9),
+    ));
+    scene
+}
+
+fn cornell(aspect_ratio: f64) -> Scene {
+    let white = Lambertian(0.73, 0.73, 0.73);
+    let hittables = vec![
+        yz_rect(0., 2., -1., 1., -1., Lambertian(0.65, 0.05, 0.05)),
+        yz_rect(0., 2., -1., 1., 1., Lambertian(0.12, 0.45, 0.15)),
+        xz_rect(-1., 1., -1., 1., 0., white),
+        xz_rect(-1., 1., -1., 1., 2., white),
+        xy_rect(-1., 1., 0., 2., -1., white),
+        //Shining down only, with the power of a 40 W bulb in units of the scene
+        with_power(
+            Disk(
+                Vec3(0., 1.999, 0.),
+                Vec3(0., -1., 0.),
+                0.3,
+                OneSidedLight(1., 0.85, 0.6),
+            ),
+            40.,
+        ),
+        Cuboid(Vec3(-0.7, 0., -0.6), Vec3(-0.1, 1.2, 0.), white),
+        //Green glass, losing most of its red and blue over the radius
+        Sphere(
+            Vec3(-0.55, 0.25, 0.5),
+            0.25,
+            AbsorbingDielectric(1.5, 0.2, 0.8, 0.3),
+        ),
+        //Forward scattering haze filling a block
+        ConstantMedium(
+            Box::new(Cuboid(Vec3(0.1, 0., -0.2), Vec3(0.8, 0.7, 0.5), white)),
+            2.,
+            HenyeyGreenstein(0.8, 0.8, 0.9, 0.6),
+        ),
+        ConstantMedium(
+            Box::new(Sphere(Vec3(0.4, 1.3, -0.4), 0.3, white)),
+            3.,
+            Isotropic(0.9, 0.9, 0.9),
+        ),
+    ];
+
+    let camera = Camera::new(
+        Vec3(0., 1., 4.5),
+        Vec3(0., 1., 0.),
+        Vec3(0., 1., 0.),
+        30.,
+        aspect_ratio,
+    );
+    Scene::new(HittableObjects(hittables), None, camera)
+}
+
+fn lenses(aspect_ratio: f64) -> Scene {
+    const WATER: Glass = Glass::Cauchy(1.3199, 0.00306);
+    let hittables = vec![
+        Plane(
+            Vec3(0., 0., 0.),
+            Vec3(0., 1., 0.),
+            Lambertian(0.8, 0.8, 0.8),
+        ),
+        //Convex lens where two spheres overlap
+        Csg(
+            CsgOperation::Intersection,
+            Box::new(Sphere(Vec3(-1., 0.5, -1.8), 1., Dispersive(Glass::BK7))),
+            Box::new(Sphere(Vec3(-1., 0.5, -0.2), 1., Dispersive(Glass::BK7))),
+        ),
+        //Concave lens carved out of a glass disk by two spheres
+        Csg(
+            CsgOperation::Difference,
+            Box::new(Cylinder(
+                Vec3(1., 0.5, -1.2),
+                Vec3(0., 0., 0.4),
+                0.45,
+                Dispersive(Glass::FLINT),
+            )),
+            Box::new(HittableObjects(vec![
+                Sphere(Vec3(1., 0.5, -2.17), 1., Dispersive(Glass::FLINT)),
+                Sphere(Vec3(1., 0.5, 0.17), 1., Dispersive(Glass::FLINT)),
+            ])),
+        ),
+        Cone(
+            Vec3(0., 0., -0.9),
+            Vec3(0., 0.5, 0.),
+            0.25,
+            Dispersive(Glass::DIAMOND),
+        ),
+        //Capsule of water lying on the ground, without inner surfaces where the parts
+        //meet, with Cauchy's approximation of the dispersion of water
+        Csg(
+            CsgOperation::Union,
+            Box::new(Cylinder(
+                Vec3(-0.3, 0.15, 0.6),
+                Vec3(0.6, 0., 0.),
+                0.15,
+                Dispersive(WATER),
+            )),
+            Box::new(HittableObjects(vec![
+                Sphere(Vec3(-0.3, 0.15, 0.6), 0.15, Dispersive(WATER)),
+                Sphere(Vec3(0.3, 0.15, 0.6), 0.15, Dispersive(WATER)),
+            ])),
+        ),
+        Sphere(Vec3(0., 3., -2.), 0.6, SpectralLight(Illuminant::E, 8.)),
+    ];
+
+    let camera = Camera::new(
+        Vec3(0., 1.5, 3.),
+        Vec3(0., 0.4, -0.5),
+        Vec3(0., 1., 0.),
+        45.,
+        aspect_ratio,
+    );
+    //Daylight from the front, warm incandescent bulbs on the sides
+    let mut scene = Scene::new(HittableObjects(hittables), None, camera);
+    scene.lights.add(Punctual::Spot(
+        Vec3(0., 3., 1.),
+        Vec3(0., -1., -0.5),
+        15.,
+        25.,
+        SpectralLight(Illuminant::D65, 20.),
+    ));
+    scene.lights.add(Punctual::Point(
+        Vec3(-2., 1.5, 1.),
+        SpectralLight(Illuminant::Blackbody(2700.), 3.),
+    ));
+    scene.lights.add(Punctual::Point(
+        Vec3(2., 1.5, 1.),
+        SpectralLight(Illuminant::A, 3.),
+    ));
+    scene
+}
+
+fn fractal(aspect_ratio: f64) -> Scene {
+    let translate =
+        |x: f64, z: f64, sdf: Sdf| Box::new(Sdf::Translate(Vec3(x, 0.3, z), Box::new(sdf)));
+    let hittables = vec![
+        Plane(
+            Vec3(0., -0.5, 0.),
+            Vec3(0., 1., 0.),
+            Lambertian(0.5, 0.5, 0.5),
+        ),
+        Sdf(
+            Box::new(Sdf::Translate(
+                Vec3(0., 0.6, -1.),
+                Box::new(Sdf::Mandelbulb(8., 12)),
+            )),
+            Lambertian(0.8, 0.6, 0.3),
+        ),
+        //A rounded box with a sphere carved out and a twisted torus next to it
+        Sdf(
+            Box::new(Sdf::Union(
+                translate(
+                    -1.6,
+                    -0.5,
+                    Sdf::SmoothSubtraction(
+                        0.05,
+                        Box::new(Sdf::SmoothUnion(
+                            0.1,
+                            Box::new(Sdf::Box(Vec3(0.3, 0.3, 0.3))),
+                            Box::new(Sdf::Sphere(0.05)),
+                        )),
+                        Box::new(Sdf::Sphere(0.38)),
+                    ),
+                ),
+                translate(1.6, -0.5, Sdf::Twist(2., Box::new(Sdf::Torus(0.3, 0.1)))),
+            )),
+            Metal(0.9, 0.9, 0.9, 0.1),
+        ),
+        //A row of spheres going into the distance
+        Sdf(
+            Box::new(Sdf::Translate(
+                Vec3(0., -0.35, -6.),
+                Box::new(Sdf::Repeat(Vec3(0., 0., 1.), Box::new(Sdf::Sphere(0.15)))),
+            )),
+            Lambertian(0.3, 0.5, 0.8),
+        ),
+    ];
+
+    let camera = Camera::new(
+        Vec3(0., 1., 3.),
+        Vec3(0., 0.3, -1.),
+        Vec3(0., 1., 0.),
+        45.,
+        aspect_ratio,
+    );
+    outdoors(hittables, camera)
+}
+
+fn daylight(aspect_ratio: f64) -> Scene {
+    //Square pyramid standing on its base
+    let pyramid = Mesh::new(
+        vec![
+            Vec3(-0.5, 0., -0.5),
+            Vec3(0.5, 0., -0.5),
+            Vec3(0.5, 0., 0.5),
+            Vec3(-0.5, 0., 0.5),
+            Vec3(0., 0.8, 0.),
+        ],
+        vec![
+            [0, 2, 1],
+            [0, 3, 2],
+            [0, 1, 4],
+            [1, 2, 4],
+            [2, 3, 4],
+            [3, 0, 4],
+        ],
+    );
+    let hittables = vec![
+        Disk(
+            Vec3(0., 0., 0.),
+            Vec3(0., 1., 0.),
+            20.,
+            Lambertian(0.4, 0.35, 0.3),
+        ),
+        Mesh(Arc::new(pyramid), Lambertian(0.85, 0.75, 0.55)),
+        Torus(
+            Vec3(1.5, 0.3, 0.5),
+            Vec3(1., 1., 0.),
+            0.3,
+            0.1,
+            Metal(0.9, 0.6, 0.3, 0.1),
+        ),
+        Quad(
+            Vec3(-2.5, 0., -1.),
+            Vec3(1., 0., 0.5),
+            Vec3(0., 1.5, 0.),
+            Lambertian(0.2, 0.3, 0.6),
+        ),
+        Triangle(
+            Vec3(-1., 0., 1.),
+            Vec3(-0.4, 0., 1.3),
+            Vec3(-0.7, 0.5, 1.1),
+            Lambertian(0.7, 0.2, 0.2),
+        ),
+    ];
+
+    let camera = Camera::new(
+        Vec3(0., 1.2, 4.),
+        Vec3(0., 0.4, 0.),
+        Vec3(0., 1., 0.),
+        40.,
+        aspect_ratio,
+    );
+    outdoors(hittables, camera)
+}
+
+fn lamps(aspect_ratio: f64) -> Scene {
+    //The same spheres on every machine, whatever random numbers were drawn before
+    rand::seed(0x1a3b5, &[]);
+    let mut hittables = vec![Plane(
+        Vec3(0., 0., 0.),
+        Vec3(0., 1., 0.),
+        Lambertian(0.5, 0.5, 0.5),
+    )];
+    for a in -10..11 {
+        for b in -10..11 {
+            let choose_mat = random_double();
+            let radius = 0.2 - random_double() * 0.1;
+            let center = Vec3(
+                a as f64 + random_double() * 0.5,
+                radius,
+                b as f64 + random_double() * 0.5,
+            );
+
+            hittables.push(if choose_mat < 0.7 {
+                Sphere(
+                    center,
+                    radius,
+                    Lambertian(random_double(), random_double(), random_double()),
+                )
+            } else if choose_mat < 0.8 {
+                Sphere(center, radius, Metal(0.9, 0.9, 0.9, random_double() * 0.1))
+            } else if choose_mat < 0.9 {
+                Sphere(center, radius, Dielectric(1.5))
+            } else {
+                //Warm lamps of all strengths, a few of them much brighter
+                let strength = 10. + 100. * random_double().powi(4);
+                Sphere(
+                    center,
+                    0.5 * radius,
+                    Light(strength, 0.8 * strength, 0.5 * strength),
+                )
+            });
+        }
+    }
+
+    let camera = Camera::new(
+        Vec3(0., 2.5, 9.),
+        Vec3(0., 0., 0.),
+        Vec3(0., 1., 0.),
+        45.,
+        aspect_ratio,
+    );
+    Scene::new(HittableObjects(hittables), None, camera)
+}
+
+fn volume(grid: VoxelGrid, aspect_ratio: f64) -> Scene {
+    //The camera looks at the grid from the front, from far enough to see all of it
+    let (min, max) = (grid.bounds.min, grid.bounds.max);
+    let center = 0.5 * (min + max);
+    let size = (max - min).length();
+    let hittables = vec![
+        Plane(min, Vec3(0., 1., 0.), Lambertian(0.4, 0.4, 0.4)),
+        VoxelMedium(
+            Arc::new(grid),
+            20.,
+            HenyeyGreenstein(0.9, 0.9, 0.9, 0.4),
+            Vec3(8., 3., 0.5),
+        ),
+    ];
+
+    let camera = Camera::new(
+        center + Vec3(0., 0.2 * size, 1.5 * size),
+        center,
+        Vec3(0., 1., 0.),
+        45.,
+        aspect_ratio,
+    );
+    outdoors(hittables, camera)
+}
+
+/// The scene under an afternoon sky with the sun 30° up, a turbidity of 3 and a grey
+/// ground, exposed for its brightness.
+fn outdoors(hittables: Vec<Hittables>, mut camera: Camera) -> Scene {
+    camera.exposure = 0.05;
+    let mut scene = Scene::new(HittableObjects(hittables), None, camera);
+    scene.set_sky(Sky::new(sun_direction(30., 45.), 3., Vec3(0.2, 0.2, 0.2)));
+    scene
+}
+
+#[cfg(test)]
+mod scenes_tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(SceneKind::parse("shapes"), Some(SceneKind::Shapes));
+        assert_eq!(
+            SceneKind::parse("volume:smoke.vxg"),
+            Some(SceneKind::Volume("smoke.vxg".to_string()))
+        );
+        assert_eq!(SceneKind::parse("volume:"), None);
+        assert_eq!(SceneKind::parse("teapot"), None);
+    }
+
+    #[test]
+    fn test_scenes_build() {
+        for name in [
+            "spheres", "shapes", "cornell", "lenses", "fractal", "daylight", "lamps",
+        ] {
+            assert!(SceneKind::parse(name).unwrap().build(16. / 9.).is_ok());
+        }
+        //Workers build the random scene on their own and must get the same one
+        rand::seed(1, &[]);
+        let first = lamps(1.).fingerprint();
+        rand::seed(2, &[]);
+        assert_eq!(lamps(1.).fingerprint(), first);
+        assert!(SceneKind::parse("volume:missing.vxg")
+            .unwrap()
+            .build(1.)
+            .is_err());
+    }
+}
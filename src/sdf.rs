@@ -0,0 +1,348 @@
+use crate::{
+    aabb::Aabb,
+    hittable::Hit,
+    hittables::sphere_uv,
+    materials::Material,
+    ray::Ray,
+    utils::clamp,
+    vec3::{unit_vector, Vec3},
+};
+
+const MAX_STEPS: usize = 512;
+//Marching distance for unbounded trees such as repetitions
+const MAX_DISTANCE: f64 = 1000.;
+const SURFACE_EPSILON: f64 = 1e-5;
+
+/// Signed distance field, composed from primitives centered at the origin, operations
+/// and domain transformations.
+#[derive(Clone, Debug)]
+pub enum Sdf {
+    Sphere(f64),
+    /// Box with the given half extents.
+    Box(Vec3),
+    /// Torus in the xz plane with major and minor radius.
+    Torus(f64, f64),
+    Translate(Vec3, Box<Sdf>),
+    Union(Box<Sdf>, Box<Sdf>),
+    /// Union blending the surfaces over the distance `k`.
+    SmoothUnion(f64, Box<Sdf>, Box<Sdf>),
+    /// The first field with the second one carved out, blended over the distance `k`.
+    SmoothSubtraction(f64, Box<Sdf>, Box<Sdf>),
+    /// Infinite repetition with the period per axis, zero disables an axis.
+    Repeat(Vec3, Box<Sdf>),
+    /// Twist around the y axis by the given angle in radians per unit of height.
+    Twist(f64, Box<Sdf>),
+    /// Mandelbulb fractal with power and iteration count, bounded by radius 1.2.
+    Mandelbulb(f64, usize),
+}
+
+pub fn distance(sdf: &Sdf, p: &Vec3) -> f64 {
+    match sdf {
+        Sdf::Sphere(radius) => p.length() - radius,
+        Sdf::Box(half) => {
+            let q = Vec3(p.0.abs() - half.0, p.1.abs() - half.1, p.2.abs() - half.2);
+            let outside = Vec3(q.0.max(0.), q.1.max(0.), q.2.max(0.)).length();
+            outside + f64::min(q.0.max(q.1.max(q.2)), 0.)
+        }
+        Sdf::Torus(major, minor) => {
+            let ring = Vec3(p.0, 0., p.2).length() - major;
+            f64::sqrt(ring * ring + p.1 * p.1) - minor
+        }
+        Sdf::Translate(offset, inner) => distance(inner, &(*p - *offset)),
+        Sdf::Union(a, b) => f64::min(distance(a, p), distance(b, p)),
+        Sdf::SmoothUnion(k, a, b) => smooth_min(distance(a, p), distance(b, p), *k),
+        Sdf::SmoothSubtraction(k, a, b) => -smooth_min(-distance(a, p), distance(b, p), *k),
+        Sdf::Repeat(period, inner) => {
+            let wrap = |x: f64, period: f64| {
+                if period > 0. {
+                    x - period * f64::round(x / period)
+                } else {
+                    x
+                }
+            };
+            let q = Vec3(
+                wrap(p.0, period.0),
+                wrap(p.1, period.1),
+                wrap(p.2, period.2),
+            );
+            distance(inner, &q)
+        }
+        Sdf::Twist(k, inner) => {
+            let (s, c) = f64::sin_cos(k * p.1);
+            let q = Vec3(c * p.0 - s * p.2, p.1, s * p.0 + c * p.2);
+            //The twist stretches distances by up to sqrt(1 + (k r)²) at radius r
+            let r = k * Vec3(p.0, 0., p.2).length();
+            distance(inner, &q) / f64::sqrt(1. + r * r)
+        }
+        Sdf::Mandelbulb(power, iterations) => mandelbulb(p, *power, *iterations),
+    }
+}
+
+/// Polynomial smooth minimum.
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0. {
+        return f64::min(a, b);
+    }
+    let h = clamp(0.5 + 0.5 * (b - a) / k, 0., 1.);
+    b + (a - b) * h - k * h * (1. - h)
+}
+
+fn mandelbulb(p: &Vec3, power: f64, iterations: usize) -> f64 {
+    let mut z = *p;
+    let mut dr = 1.;
+    let mut r = 0.;
+
+    for _ in 0..iterations {
+        r = z.length();
+        if r > 2. {
+            break;
+        }
+
+        let theta = f64::acos(clamp(z.2 / r, -1., 1.)) * power;
+        let phi = f64::atan2(z.1, z.0) * power;
+        dr = f64::powf(r, power - 1.) * power * dr + 1.;
+
+        let zr = f64::powf(r, power);
+        z =
+            zr * Vec3(
+                f64::sin(theta) * f64::cos(phi),
+                f64::sin(phi) * f64::sin(theta),
+                f64::cos(theta),
+            ) + *p;
+    }
+
+    if r == 0. {
+        return 0.;
+    }
+    0.5 * f64::ln(r) * r / dr
+}
+
+/// Surface normal from the gradient of the field (tetrahedron technique).
+pub fn normal(sdf: &Sdf, p: &Vec3) -> Vec3 {
+    let h = 1e-5;
+    let k = [
+        Vec3(1., -1., -1.),
+        Vec3(-1., -1., 1.),
+        Vec3(-1., 1., -1.),
+        Vec3(1., 1., 1.),
+    ];
+    let gradient = k.iter().fold(Vec3(0., 0., 0.), |acc, k| {
+        acc + distance(sdf, &(*p + h * *k)) * *k
+    });
+    unit_vector(&gradient)
+}
+
+/// Bounds of the zero set, `None` for unbounded fields.
+pub fn bounds(sdf: &Sdf) -> Option<Aabb> {
+    match sdf {
+        Sdf::Sphere(radius) => Some(Aabb::new(
+            Vec3(-radius, -radius, -radius),
+            Vec3(*radius, *radius, *radius),
+        )),
+        Sdf::Box(half) => Some(Aabb::new(-*half, *half)),
+        Sdf::Torus(major, minor) => {
+            let e = Vec3(major + minor, *minor, major + minor);
+            Some(Aabb::new(-e, e))
+        }
+        Sdf::Translate(offset, inner) => {
+            let b = bounds(inner)?;
+            Some(Aabb::new(b.min + *offset, b.max + *offset))
+        }
+        Sdf::Union(a, b) => Some(Aabb::surrounding(&bounds(a)?, &bounds(b)?)),
+        Sdf::SmoothUnion(k, a, b) => {
+            //Blending may bulge out of the operands by up to k / 4
+            let b = Aabb::surrounding(&bounds(a)?, &bounds(b)?);
+            let pad = Vec3(*k, *k, *k) / 4.;
+            Some(Aabb::new(b.min - pad, b.max + pad))
+        }
+        Sdf::SmoothSubtraction(_, a, _) => bounds(a),
+        Sdf::Repeat(_, _) => None,
+        Sdf::Twist(_, inner) => {
+            let b = bounds(inner)?;
+            let r = f64::sqrt(
+                f64::max(b.min.0.abs(), b.max.0.abs()).powi(2)
+                    + f64::max(b.min.2.abs(), b.max.2.abs()).powi(2),
+            );
+            Some(Aabb::new(Vec3(-r, b.min.1, -r), Vec3(r, b.max.1, r)))
+        }
+        Sdf::Mandelbulb(_, _) => Some(Aabb::new(Vec3(-1.2, -1.2, -1.2), Vec3(1.2, 1.2, 1.2))),
+    }
+}
+
+/// Sphere tracing: steps along the ray by the distance to the nearest surface until
+/// the surface is reached, starting on whichever side of it the ray begins.
+pub fn hit_sdf(
+    sdf: &Sdf,
+    material: Material,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    rec: &mut Hit,
+) -> bool {
+    let (mut t, t_end) = match bounds(sdf) {
+        Some(b) => match b.interval(ray, t_min, t_max) {
+            Some(interval) => interval,
+            None => return false,
+        },
+        None => (t_min, f64::min(t_max, MAX_DISTANCE)),
+    };
+
+    let ray_length = ray.dir.length();
+    let step_epsilon = SURFACE_EPSILON / ray_length;
+
+    //Rays entering the bounds start outside, others may start on or inside the surface
+    let mut side = 1.;
+    if t <= t_min {
+        //Leave the surface a secondary ray may start on
+        let mut d = distance(sdf, &ray.at(t));
+        for _ in 0..8 {
+            if f64::abs(d) >= SURFACE_EPSILON {
+                break;
+            }
+            t += 2. * step_epsilon;
+            d = distance(sdf, &ray.at(t));
+        }
+        if d < 0. {
+            side = -1.;
+        }
+    }
+
+    for _ in 0..MAX_STEPS {
+        if t > t_end {
+            return false;
+        }
+
+        let d = side * distance(sdf, &ray.at(t));
+        if d < SURFACE_EPSILON {
+            rec.t = t;
+            rec.point = ray.at(t);
+            let outward_normal = normal(sdf, &rec.point);
+            rec.set_face_normal(ray, &outward_normal);
+            (rec.u, rec.v) = sphere_uv(&outward_normal);
+            rec.material = material;
+            return true;
+        }
+
+        t += d / ray_length;
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod sdf_tests {
+    use super::*;
+    use crate::materials::Material::Lambertian;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!(f64::abs(a - b) < 1e-4, "{} != {}", a, b);
+    }
+
+    fn ray(origin: Vec3, dir: Vec3) -> Ray {
+        Ray { origin, dir }
+    }
+
+    #[test]
+    fn test_primitive_distances() {
+        assert_close(distance(&Sdf::Sphere(1.), &Vec3(0., 3., 0.)), 2.);
+        assert_close(distance(&Sdf::Sphere(1.), &Vec3(0., 0., 0.)), -1.);
+
+        let cube = Sdf::Box(Vec3(1., 1., 1.));
+        assert_close(distance(&cube, &Vec3(3., 0., 0.)), 2.);
+        assert_close(distance(&cube, &Vec3(2., 2., 1.)), f64::sqrt(2.));
+        assert_close(distance(&cube, &Vec3(0.5, 0., 0.)), -0.5);
+
+        let torus = Sdf::Torus(1., 0.25);
+        assert_close(distance(&torus, &Vec3(1., 0., 0.)), -0.25);
+        assert_close(distance(&torus, &Vec3(0., 0., 0.)), 0.75);
+    }
+
+    #[test]
+    fn test_operations() {
+        let a = Box::new(Sdf::Sphere(1.));
+        let b = Box::new(Sdf::Translate(Vec3(1.5, 0., 0.), Box::new(Sdf::Sphere(1.))));
+        let p = Vec3(0.75, 1., 0.);
+
+        let union = distance(&Sdf::Union(a.clone(), b.clone()), &p);
+        let smooth = distance(&Sdf::SmoothUnion(0.5, a.clone(), b.clone()), &p);
+        assert!(smooth < union);
+
+        //Carving the second sphere out moves the surface of the first inwards
+        let carved = Sdf::SmoothSubtraction(0.1, a, b);
+        assert!(distance(&carved, &Vec3(0.9, 0., 0.)) > 0.);
+        assert!(distance(&carved, &Vec3(-0.9, 0., 0.)) < 0.);
+
+        let repeated = Sdf::Repeat(Vec3(4., 0., 0.), Box::new(Sdf::Sphere(1.)));
+        assert_close(distance(&repeated, &Vec3(8., 2., 0.)), 1.);
+        assert_close(distance(&repeated, &Vec3(2., 0., 0.)), 1.);
+
+        //Twisting a torus around its axis leaves its surface in place
+        let twisted = Sdf::Twist(1., Box::new(Sdf::Torus(1., 0.25)));
+        assert_close(distance(&twisted, &Vec3(0., 0., 1.25)), 0.);
+        assert!(distance(&twisted, &Vec3(0., 0., 1.)) < 0.);
+    }
+
+    #[test]
+    fn test_hit_matches_sphere() {
+        let sphere = Sdf::Translate(Vec3(0., 0., -3.), Box::new(Sdf::Sphere(1.)));
+        let mut rec = Hit::empty();
+
+        assert!(hit_sdf(
+            &sphere,
+            Lambertian(0.5, 0.5, 0.5),
+            &ray(Vec3(0., 0., 0.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 2.);
+        assert!((rec.normal - Vec3(0., 0., 1.)).length() < 1e-3);
+        assert!(rec.front_face);
+
+        //Leaving the sphere from the inside
+        assert!(hit_sdf(
+            &sphere,
+            Lambertian(0.5, 0.5, 0.5),
+            &ray(rec.point, Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert_close(rec.t, 2.);
+        assert!(!rec.front_face);
+
+        assert!(!hit_sdf(
+            &sphere,
+            Lambertian(0.5, 0.5, 0.5),
+            &ray(Vec3(0., 2., 0.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+    }
+
+    #[test]
+    fn test_hit_mandelbulb() {
+        let bulb = Sdf::Mandelbulb(8., 12);
+        let mut rec = Hit::empty();
+
+        assert!(hit_sdf(
+            &bulb,
+            Lambertian(0.5, 0.5, 0.5),
+            &ray(Vec3(0., 0., 3.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+        assert!(rec.t > 1.5 && rec.t < 3.);
+        assert!(f64::abs(distance(&bulb, &rec.point)) < 1e-3);
+        assert!(!hit_sdf(
+            &bulb,
+            Lambertian(0.5, 0.5, 0.5),
+            &ray(Vec3(0., 2., 3.), Vec3(0., 0., -1.)),
+            0.001,
+            f64::INFINITY,
+            &mut rec
+        ));
+    }
+}
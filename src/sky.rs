@@ -0,0 +1,263 @@
+//! Daylight sky by the analytic model of Preetham, Shirley and Smits, lighting outdoor
+//! scenes from the directions rays escape in.
+//!
+//! The sky gives the luminance and chromaticity of every direction above the horizon
+//! from the height of the sun and the turbidity of the air, the haze: 2 for a clear
+//! sky, 3 for a usual one and up to 10 for a hazy one. Radiance is in thousands of
+//! candela per square meter, a few units for a blue sky. Below the horizon lies a
+//! diffuse ground of the given albedo, lit by the sky and the sun.
+//!
+//! The disk of the sun is left out of the sky and comes from the far away light of
+//! `sun` instead, dimmed and reddened by the air it goes through, so light sampling
+//! finds it and nothing counts it twice.
+
+use crate::{
+    lights::Punctual,
+    materials::Material,
+    spectrum::{xyz_to_rgb, RGB_WAVELENGTHS},
+    utils::{degrees_to_radians, PI},
+    vec3::{dot, unit_vector, Vec3},
+};
+
+/// Angle the disk of the sun takes up, in degrees.
+const SUN_ANGULAR_DIAMETER: f64 = 0.53;
+/// Illuminance of the sun outside the atmosphere, in lux over a thousand.
+const SUN_ILLUMINANCE: f64 = 128.;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Sky {
+    /// Unit direction towards the sun, the y axis being up.
+    sun: Vec3,
+    turbidity: f64,
+    /// Coefficients A to E of the Perez distribution of the luminance and of the x and
+    /// y chromaticities.
+    perez: [[f64; 5]; 3],
+    /// Luminance and chromaticities at the zenith, over the Perez distribution there.
+    zenith: [f64; 3],
+    /// Radiance of the ground, lit by the sky and the sun.
+    ground: Vec3,
+}
+
+impl Sky {
+    /// The sky with the sun in the direction `sun`, the y axis being up, which has to
+    /// be above the horizon.
+    pub fn new(sun: Vec3, turbidity: f64, ground_albedo: Vec3) -> Sky {
+        let sun = unit_vector(&sun);
+        assert!(sun.1 > 0., "sun below the horizon");
+        assert!(
+            (1.7..=10.).contains(&turbidity),
+            "turbidity out of range 1.7 to 10"
+        );
+        let t = turbidity;
+        let perez = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        let theta_s = f64::acos(sun.1);
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_s);
+        let luminance = (4.0453 * t - 4.9710) * f64::tan(chi) - 0.2155 * t + 2.4192;
+        let polynomial = |c: [[f64; 4]; 3]| {
+            let theta = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.];
+            let row = |c: [f64; 4]| (0..4).map(|i| c[i] * theta[i]).sum::<f64>();
+            t * t * row(c[0]) + t * row(c[1]) + row(c[2])
+        };
+        let x = polynomial([
+            [0.00166, -0.00375, 0.00209, 0.],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ]);
+        let y = polynomial([
+            [0.00275, -0.00610, 0.00317, 0.],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ]);
+        let mut zenith = [luminance, x, y];
+        for (value, coefficients) in zenith.iter_mut().zip(&perez) {
+            *value /= perez_distribution(coefficients, 1., sun.1);
+        }
+
+        let mut sky = Sky {
+            sun,
+            turbidity,
+            perez,
+            zenith,
+            ground: Vec3(0., 0., 0.),
+        };
+        sky.ground = ground_albedo * (sky.irradiance() / PI);
+        sky
+    }
+
+    /// Radiance of the sky seen in the direction `dir`, without the sun, in RGB.
+    pub fn radiance(&self, dir: Vec3) -> Vec3 {
+        let dir = unit_vector(&dir);
+        if dir.1 < 0. {
+            return self.ground;
+        }
+        //Straight at the horizon the distribution divides by zero
+        let cos_theta = f64::max(dir.1, 1e-3);
+        let cos_gamma = dot(&dir, &self.sun);
+        let [luminance, x, y] = [0, 1, 2]
+            .map(|i| self.zenith[i] * perez_distribution(&self.perez[i], cos_theta, cos_gamma));
+
+        let xyz = Vec3(x / y * luminance, luminance, (1. - x - y) / y * luminance);
+        let rgb = xyz_to_rgb(xyz);
+        Vec3(rgb.0.max(0.), rgb.1.max(0.), rgb.2.max(0.))
+    }
+
+    /// The far away light of the sun, its irradiance taken down by scattering off
+    /// the molecules of the air and the haze along the way, more so at red sunsets.
+    pub fn sun(&self) -> Punctual {
+        //Relative length of the way through the air, by Kasten's formula
+        let elevation = 90. - f64::acos(self.sun.1).to_degrees();
+        let mass = 1. / (self.sun.1 + 0.15 * f64::powf(elevation + 3.885, -1.253));
+        //Rayleigh scattering off molecules and Ångström's formula for the haze
+        let beta = 0.04608365822050 * self.turbidity - 0.04586025928522;
+        let transmittance = |lambda: f64| {
+            let micrometers = lambda / 1000.;
+            let rayleigh = 0.008735 * f64::powf(micrometers, -4.08);
+            let aerosol = beta * f64::powf(micrometers, -1.3);
+            SUN_ILLUMINANCE * f64::exp(-(rayleigh + aerosol) * mass)
+        };
+        let [r, g, b] = RGB_WAVELENGTHS.map(transmittance);
+        Punctual::Directional(self.sun, SUN_ANGULAR_DIAMETER, Material::Light(r, g, b))
+    }
+
+    /// Irradiance of the sky and the sun on the ground.
+    fn irradiance(&self) -> Vec3 {
+        //Midpoint rule over the hemisphere in elevation and azimuth
+        let (rows, columns) = (32, 64);
+        let d_theta = PI / 2. / rows as f64;
+        let d_phi = 2. * PI / columns as f64;
+        let mut irradiance = Vec3(0., 0., 0.);
+        for i in 0..rows {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..columns {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let dir = Vec3(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                let solid_angle = theta.sin() * d_theta * d_phi;
+                irradiance += self.radiance(dir) * (theta.cos() * solid_angle);
+            }
+        }
+
+        let sun = match self.sun() {
+            Punctual::Directional(_, _, Material::Light(r, g, b)) => Vec3(r, g, b),
+            _ => unreachable!(),
+        };
+        irradiance + sun * self.sun.1
+    }
+}
+
+/// Relative brightness of the sky by the Perez formula, from the cosines of the angle
+/// to the zenith and of the angle to the sun.
+fn perez_distribution(coefficients: &[f64; 5], cos_theta: f64, cos_gamma: f64) -> f64 {
+    let [a, b, c, d, e] = *coefficients;
+    let gamma = f64::acos(cos_gamma.clamp(-1., 1.));
+    (1. + a * f64::exp(b / cos_theta)) * (1. + c * f64::exp(d * gamma) + e * cos_gamma * cos_gamma)
+}
+
+/// Direction towards the sun at an elevation above the horizon and an azimuth from the
+/// x axis towards the z axis, both in degrees.
+pub fn sun_direction(elevation: f64, azimuth: f64) -> Vec3 {
+    let (elevation, azimuth) = (degrees_to_radians(elevation), degrees_to_radians(azimuth));
+    Vec3(
+        elevation.cos() * azimuth.cos(),
+        elevation.sin(),
+        elevation.cos() * azimuth.sin(),
+    )
+}
+
+#[cfg(test)]
+mod sky_tests {
+    use super::*;
+
+    fn sun_light(sky: &Sky) -> Vec3 {
+        match sky.sun() {
+            Punctual::Directional(_, _, Material::Light(r, g, b)) => Vec3(r, g, b),
+            _ => panic!("not a sun"),
+        }
+    }
+
+    #[test]
+    fn test_zenith_luminance() {
+        //Straight up the distribution cancels out, leaving the zenith luminance
+        let sky = Sky::new(sun_direction(45., 0.), 3., Vec3(0.2, 0.2, 0.2));
+        let chi = (4. / 9. - 3. / 120.) * (PI / 2.);
+        let expected = (4.0453 * 3. - 4.9710) * f64::tan(chi) - 0.2155 * 3. + 2.4192;
+        let luminance = sky.radiance(Vec3(0., 1., 0.)).luminance();
+        assert!(
+            f64::abs(luminance - expected) < 0.02 * expected,
+            "{} != {}",
+            luminance,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_clear_sky() {
+        let sky = Sky::new(sun_direction(30., 0.), 2.5, Vec3(0.2, 0.2, 0.2));
+        //Blue overhead
+        let zenith = sky.radiance(Vec3(0., 1., 0.));
+        assert!(zenith.2 > zenith.0);
+        //Brighter around the sun than away from it at the same height
+        let near = sky.radiance(sun_direction(35., 0.));
+        let away = sky.radiance(sun_direction(35., 180.));
+        assert!(near.luminance() > 2. * away.luminance());
+        //Finite down to the horizon
+        assert!(sky.radiance(Vec3(1., 0., 0.)).luminance().is_finite());
+    }
+
+    #[test]
+    fn test_sun_reddens_near_the_horizon() {
+        let noon = sun_light(&Sky::new(sun_direction(80., 0.), 3., Vec3(0., 0., 0.)));
+        let sunset = sun_light(&Sky::new(sun_direction(3., 0.), 3., Vec3(0., 0., 0.)));
+        assert!(noon.luminance() > 2. * sunset.luminance());
+        assert!(sunset.0 / sunset.2 > noon.0 / noon.2);
+        assert!(noon.luminance() < SUN_ILLUMINANCE);
+        //Haze dims it too
+        let hazy = sun_light(&Sky::new(sun_direction(80., 0.), 8., Vec3(0., 0., 0.)));
+        assert!(hazy.luminance() < noon.luminance());
+    }
+
+    #[test]
+    fn test_ground() {
+        let black = Sky::new(sun_direction(45., 0.), 3., Vec3(0., 0., 0.));
+        assert_eq!(black.radiance(Vec3(0., -1., 0.)), Vec3(0., 0., 0.));
+
+        //A white ground reflects all the light it gets, more than the sun alone
+        let white = Sky::new(sun_direction(45., 0.), 3., Vec3(1., 1., 1.));
+        let ground = white.radiance(Vec3(0.3, -1., 0.));
+        let sun = sun_light(&white) * f64::sin(degrees_to_radians(45.)) / PI;
+        assert!(ground.1 > sun.1);
+        assert!(ground.1 < 2. * sun.1);
+    }
+
+    #[test]
+    #[should_panic(expected = "below the horizon")]
+    fn test_rejects_night() {
+        Sky::new(Vec3(0., -1., 0.), 3., Vec3(0.2, 0.2, 0.2));
+    }
+}
@@ -0,0 +1,359 @@
+//! Spectral rendering with hero wavelength sampling.
+//!
+//! In spectral mode every sample picks three wavelengths, a random hero wavelength
+//! and two more spaced evenly across the visible range from it, and the components
+//! of the `Vec3`s along its path hold the light at those wavelengths instead of red,
+//! green and blue. The integrators work unchanged. Materials turn their RGB colors
+//! into spectra on the fly through `reflectance` and `illuminant`, and the film turns
+//! the radiance at the three wavelengths back into RGB through the CIE XYZ color
+//! matching functions with `to_rgb`.
+//!
+//! The wavelengths of the sample being rendered are kept per thread. Outside spectral
+//! mode there are none and all conversions leave colors as they are.
+//!
+//! Dispersive glass bends each wavelength its own way, so a path through it only
+//! carries light of the hero wavelength from there on. `disperse` makes the sample
+//! drop the other two, or in RGB all but one random color channel.
+
+use std::{cell::Cell, sync::OnceLock};
+
+use crate::{utils::random_double, vec3::Vec3};
+
+/// Visible range the wavelengths are sampled in, in nanometers.
+pub const LAMBDA_MIN: f64 = 380.;
+pub const LAMBDA_MAX: f64 = 780.;
+
+thread_local! {
+    static WAVELENGTHS: Cell<Option<Vec3>> = const { Cell::new(None) };
+    /// RGB of the last light source `emission` converted, to integrate its spectrum
+    /// only once.
+    static LAST_EMISSION: Cell<Option<(Illuminant, Vec3)>> = const { Cell::new(None) };
+    /// Channel the sample carries light in alone since it went through dispersive
+    /// glass.
+    static DISPERSED: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Wavelengths in nanometers the red, green and blue channels stand for when RGB
+/// light goes through dispersive glass, those the sRGB primaries look like.
+pub const RGB_WAVELENGTHS: [f64; 3] = [612., 549., 465.];
+
+/// Spectra of the standard light sources, for `Material::SpectralLight`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Illuminant {
+    /// Black body radiation at a temperature in kelvin.
+    Blackbody(f64),
+    /// CIE standard illuminant D65, average daylight and the white of sRGB.
+    D65,
+    /// CIE standard illuminant A, incandescent light, a black body at 2856 K.
+    A,
+    /// Equal energy at all wavelengths.
+    E,
+}
+
+/// Wavelengths for the uniform `u`: the hero wavelength and two more a third of the
+/// visible range apart, wrapping around at its end.
+pub fn sample_wavelengths(u: f64) -> Vec3 {
+    let range = LAMBDA_MAX - LAMBDA_MIN;
+    let wavelength = |i: f64| LAMBDA_MIN + (u + i / 3.).fract() * range;
+    Vec3(wavelength(0.), wavelength(1.), wavelength(2.))
+}
+
+/// Sets the wavelengths of the sample being rendered on this thread, `None` to go
+/// back to RGB. Starts a new sample, which has not gone through dispersive glass yet.
+pub fn set_wavelengths(wavelengths: Option<Vec3>) {
+    WAVELENGTHS.with(|cell| cell.set(wavelengths));
+    DISPERSED.with(|cell| cell.set(None));
+}
+
+pub fn wavelengths() -> Option<Vec3> {
+    WAVELENGTHS.with(|cell| cell.get())
+}
+
+/// Wavelength in nanometers dispersive glass refracts the sample at, and the
+/// attenuation that keeps light in the channel of that wavelength alone: the hero
+/// wavelength in spectral mode, in RGB a color channel picked at random the first time.
+/// `to_rgb` then weights the channel up for those dropped.
+pub fn disperse() -> (f64, Vec3) {
+    let wavelengths = wavelengths();
+    let channel = match (wavelengths, DISPERSED.with(|cell| cell.get())) {
+        (Some(_), _) => 0,
+        (None, Some(channel)) => channel,
+        (None, None) => usize::min((random_double() * 3.) as usize, 2),
+    };
+    DISPERSED.with(|cell| cell.set(Some(channel)));
+    let wavelength = match wavelengths {
+        Some(wavelengths) => wavelengths.0,
+        None => RGB_WAVELENGTHS[channel],
+    };
+    (wavelength, only(channel, 1.))
+}
+
+fn only(channel: usize, value: f64) -> Vec3 {
+    match channel {
+        0 => Vec3(value, 0., 0.),
+        1 => Vec3(0., value, 0.),
+        _ => Vec3(0., 0., value),
+    }
+}
+
+/// Reflectance spectrum of a surface color with components in `[0, 1]`, at the
+/// wavelengths of the sample. Stays within `[0, 1]`, so surfaces never reflect more
+/// light than they receive.
+pub fn reflectance(rgb: Vec3) -> Vec3 {
+    match wavelengths() {
+        Some(wavelengths) => map(wavelengths, |lambda| upsample(rgb, lambda)),
+        None => rgb,
+    }
+}
+
+/// Emission spectrum of a light color, at the wavelengths of the sample. A white
+/// light has the spectrum of D65 and comes out white on the film.
+pub fn illuminant(rgb: Vec3) -> Vec3 {
+    match wavelengths() {
+        Some(wavelengths) => map(wavelengths, |lambda| {
+            upsample(rgb, lambda) * d65(lambda) / d65_luminance()
+        }),
+        None => rgb,
+    }
+}
+
+/// Radiance of a standard light source scaled to `scale` at 560 nm, where the eye is
+/// most sensitive, at the wavelengths of the sample or in RGB outside spectral mode.
+pub fn emission(illuminant: Illuminant, scale: f64) -> Vec3 {
+    let spectrum = |lambda: f64| match illuminant {
+        Illuminant::Blackbody(temperature) => {
+            planck(lambda, temperature) / planck(560., temperature)
+        }
+        Illuminant::D65 => d65(lambda) / d65(560.),
+        Illuminant::A => planck(lambda, 2856.) / planck(560., 2856.),
+        Illuminant::E => 1.,
+    };
+    if let Some(wavelengths) = wavelengths() {
+        return map(wavelengths, spectrum) * scale;
+    }
+
+    let rgb = match LAST_EMISSION.with(|cell| cell.get()) {
+        Some((last, rgb)) if last == illuminant => rgb,
+        _ => {
+            //Integrated over the visible range at 5 nm steps
+            let steps = ((LAMBDA_MAX - LAMBDA_MIN) / 5.) as usize;
+            let xyz = (0..steps).fold(Vec3(0., 0., 0.), |sum, i| {
+                let lambda = LAMBDA_MIN + (i as f64 + 0.5) * 5.;
+                sum + spectrum(lambda) * 5. * color_matching(lambda)
+            });
+            let rgb = xyz_to_rgb(xyz / y_integral());
+            LAST_EMISSION.with(|cell| cell.set(Some((illuminant, rgb))));
+            rgb
+        }
+    };
+    rgb * scale
+}
+
+/// RGB of the radiance at the wavelengths of the sample. Averages the estimates of the
+/// three wavelengths, each the radiance weighted by the color matching functions over
+/// the density of the wavelength, or takes that of the hero wavelength alone once the
+/// sample went through dispersive glass. Outside spectral mode the radiance stays as it
+/// is, but for the channel picked by `disperse`.
+pub fn to_rgb(radiance: Vec3) -> Vec3 {
+    let dispersed = DISPERSED.with(|cell| cell.get());
+    let wavelengths = match (wavelengths(), dispersed) {
+        (Some(wavelengths), _) => wavelengths,
+        (None, Some(channel)) => return only(channel, 3. * radiance[channel]),
+        (None, None) => return radiance,
+    };
+    let range = LAMBDA_MAX - LAMBDA_MIN;
+    if dispersed.is_some() {
+        return xyz_to_rgb(color_matching(wavelengths.0) * radiance.0 * (range / y_integral()));
+    }
+    let xyz = color_matching(wavelengths.0) * radiance.0
+        + color_matching(wavelengths.1) * radiance.1
+        + color_matching(wavelengths.2) * radiance.2;
+    xyz_to_rgb(xyz * (range / (3. * y_integral())))
+}
+
+fn map(wavelengths: Vec3, f: impl Fn(f64) -> f64) -> Vec3 {
+    Vec3(f(wavelengths.0), f(wavelengths.1), f(wavelengths.2))
+}
+
+/// Linear sRGB of CIE XYZ, with the D65 white point.
+pub fn xyz_to_rgb(xyz: Vec3) -> Vec3 {
+    Vec3(
+        3.2406 * xyz.0 - 1.5372 * xyz.1 - 0.4986 * xyz.2,
+        -0.9689 * xyz.0 + 1.8758 * xyz.1 + 0.0415 * xyz.2,
+        0.0557 * xyz.0 - 0.2040 * xyz.1 + 1.0570 * xyz.2,
+    )
+}
+
+/// CIE 1931 2° color matching functions as X, Y and Z, by the multi-lobe Gaussian
+/// fit of Wyman, Sloan and Shirley.
+pub fn color_matching(lambda: f64) -> Vec3 {
+    let lobe = |mu: f64, below: f64, above: f64| {
+        let t = (lambda - mu) / if lambda < mu { below } else { above };
+        f64::exp(-0.5 * t * t)
+    };
+    Vec3(
+        1.056 * lobe(599.8, 37.9, 31.0) + 0.362 * lobe(442.0, 16.0, 26.7)
+            - 0.065 * lobe(501.1, 20.4, 26.2),
+        0.821 * lobe(568.8, 46.9, 40.5) + 0.286 * lobe(530.9, 16.3, 31.1),
+        1.217 * lobe(437.0, 11.8, 36.0) + 0.681 * lobe(459.0, 26.0, 13.8),
+    )
+}
+
+/// Integral of the Y color matching function over the visible range, so a spectrum
+/// of constant one has a luminance of one.
+fn y_integral() -> f64 {
+    static INTEGRAL: OnceLock<f64> = OnceLock::new();
+    *INTEGRAL.get_or_init(|| integrate(|lambda| color_matching(lambda).1))
+}
+
+/// Luminance of the D65 table, to scale white lights to a luminance of one.
+fn d65_luminance() -> f64 {
+    static LUMINANCE: OnceLock<f64> = OnceLock::new();
+    *LUMINANCE
+        .get_or_init(|| integrate(|lambda| d65(lambda) * color_matching(lambda).1) / y_integral())
+}
+
+/// Integral over the visible range at 1 nm steps.
+fn integrate(f: impl Fn(f64) -> f64) -> f64 {
+    let steps = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+    (0..steps).map(|i| f(LAMBDA_MIN + i as f64 + 0.5)).sum()
+}
+
+/// Spectral radiance of a black body by Planck's law, wavelength in nanometers.
+pub fn planck(lambda: f64, temperature: f64) -> f64 {
+    const H: f64 = 6.62607015e-34;
+    const C: f64 = 299792458.;
+    const K: f64 = 1.380649e-23;
+    let lambda = lambda * 1e-9;
+    2. * H * C * C / (lambda.powi(5) * (f64::exp(H * C / (lambda * K * temperature)) - 1.))
+}
+
+/// Relative spectral power of CIE illuminant D65 from 380 to 780 nm at 10 nm steps.
+const D65: [f64; 41] = [
+    49.98, 54.65, 82.75, 91.49, 93.43, 86.68, 104.86, 117.01, 117.81, 114.86, 115.92, 108.81,
+    109.35, 107.80, 104.79, 107.69, 104.41, 104.05, 100.00, 96.33, 95.79, 88.69, 90.01, 89.60,
+    87.70, 83.29, 83.70, 80.03, 80.21, 82.28, 78.28, 69.72, 71.61, 74.35, 61.60, 69.89, 75.09,
+    63.59, 46.42, 66.81, 63.38,
+];
+
+fn d65(lambda: f64) -> f64 {
+    let x = ((lambda - LAMBDA_MIN) / 10.).clamp(0., (D65.len() - 1) as f64);
+    let i = usize::min(x as usize, D65.len() - 2);
+    let t = x - i as f64;
+    D65[i] * (1. - t) + D65[i + 1] * t
+}
+
+/// Smooth spectrum of an RGB color, following Smits: the color is split into white,
+/// the secondary color of the two largest components and the primary color of the
+/// largest, each with a fixed spectrum. Those of the primaries are smooth steps at
+/// 490 and 590 nm, whose colors under D65 lie within a few percent of the sRGB
+/// primaries.
+fn upsample(rgb: Vec3, lambda: f64) -> f64 {
+    let Vec3(r, g, b) = Vec3(
+        f64::max(rgb.0, 0.),
+        f64::max(rgb.1, 0.),
+        f64::max(rgb.2, 0.),
+    );
+    let red = 1. / (1. + f64::exp((590. - lambda) / 10.));
+    let blue = 1. / (1. + f64::exp((lambda - 490.) / 10.));
+    let green = 1. - red - blue;
+    let (cyan, magenta, yellow) = (1. - red, 1. - green, 1. - blue);
+
+    if r <= g && r <= b {
+        r + if g <= b {
+            (g - r) * cyan + (b - g) * blue
+        } else {
+            (b - r) * cyan + (g - b) * green
+        }
+    } else if g <= r && g <= b {
+        g + if r <= b {
+            (r - g) * magenta + (b - r) * blue
+        } else {
+            (b - g) * magenta + (r - b) * red
+        }
+    } else {
+        b + if r <= g {
+            (r - b) * yellow + (g - r) * green
+        } else {
+            (g - b) * yellow + (r - g) * red
+        }
+    }
+}
+
+#[cfg(test)]
+mod spectrum_tests {
+    use super::*;
+    use crate::rand;
+
+    //RGB of a spectrum, averaged over many sampled wavelengths
+    fn film(spectrum: impl Fn() -> Vec3) -> Vec3 {
+        rand::seed(1, &[]);
+        let n = 100_000;
+        let mut sum = Vec3(0., 0., 0.);
+        for _ in 0..n {
+            set_wavelengths(Some(sample_wavelengths(rand::next_f64())));
+            sum += to_rgb(spectrum());
+        }
+        set_wavelengths(None);
+        sum / n as f64
+    }
+
+    fn assert_close(a: Vec3, b: Vec3, tolerance: f64) {
+        assert!((a - b).length() < tolerance, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_wavelengths_cover_visible_range() {
+        let wavelengths = sample_wavelengths(0.9);
+        assert!(f64::abs(wavelengths.0 - 740.) < 1e-9);
+        assert!(f64::abs(wavelengths.1 - (LAMBDA_MIN + (0.9 + 1. / 3. - 1.) * 400.)) < 1e-9);
+        for lambda in [wavelengths.0, wavelengths.1, wavelengths.2] {
+            assert!((LAMBDA_MIN..LAMBDA_MAX).contains(&lambda));
+        }
+    }
+
+    #[test]
+    fn test_white_light_comes_out_white() {
+        assert_close(
+            film(|| illuminant(Vec3(1., 1., 1.))),
+            Vec3(1., 1., 1.),
+            0.03,
+        );
+        //Lit by white light, a surface shows its color
+        for color in [
+            Vec3(0.8, 0.2, 0.1),
+            Vec3(0.1, 0.5, 0.3),
+            Vec3(0.5, 0.5, 0.9),
+        ] {
+            assert_close(
+                film(|| reflectance(color) * illuminant(Vec3(1., 1., 1.))),
+                color,
+                0.05,
+            );
+        }
+    }
+
+    #[test]
+    fn test_reflectance_conserves_energy() {
+        for color in [Vec3(1., 0., 0.), Vec3(1., 1., 0.), Vec3(0.3, 1., 0.7)] {
+            for i in 0..=40 {
+                let value = upsample(color, LAMBDA_MIN + 10. * i as f64);
+                assert!((0. ..=1.).contains(&value), "{}", value);
+            }
+        }
+    }
+
+    #[test]
+    fn test_emission_matches_in_rgb() {
+        //In RGB, emission integrates the spectrum that spectral mode samples
+        for light in [Illuminant::Blackbody(3000.), Illuminant::D65, Illuminant::E] {
+            let rgb = emission(light, 2.);
+            assert_close(film(|| emission(light, 2.)), rgb, 0.05 * rgb.length());
+        }
+        //D65 is the white of sRGB, lower temperatures are redder
+        let d65 = emission(Illuminant::D65, 1.);
+        assert!(f64::abs(d65.0 - d65.2) < 0.01 * d65.0);
+        let warm = emission(Illuminant::A, 1.);
+        assert!(warm.0 > warm.1 && warm.1 > warm.2);
+    }
+}
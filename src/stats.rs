@@ -0,0 +1,263 @@
+use std::{cell::RefCell, fmt::Write, sync::Mutex, time::Duration};
+
+/// Names of the primitive types in `Stats::intersection_tests`, in the order of the
+/// `Hittables` variants.
+pub const PRIMITIVES: [&str; 14] = [
+    "sphere",
+    "plane",
+    "quad",
+    "cuboid",
+    "disk",
+    "cylinder",
+    "cone",
+    "torus",
+    "constant_medium",
+    "voxel_medium",
+    "csg",
+    "sdf",
+    "triangle",
+    "mesh",
+];
+
+/// Counters collected while rendering.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Stats {
+    /// Camera rays, one per sample.
+    pub primary_rays: u64,
+    /// All rays traced, camera rays included.
+    pub rays: u64,
+    /// Visibility rays towards lights.
+    pub shadow_rays: u64,
+    pub intersection_tests: [u64; PRIMITIVES.len()],
+    /// Nodes of the light BVH looked at while picking lights.
+    pub light_bvh_nodes_visited: u64,
+    /// Paths cut off by the maximum depth rather than absorbed or escaped.
+    pub terminated_by_depth: u64,
+    pub terminated_by_roulette: u64,
+}
+
+impl Stats {
+    pub const fn new() -> Stats {
+        Stats {
+            primary_rays: 0,
+            rays: 0,
+            shadow_rays: 0,
+            intersection_tests: [0; PRIMITIVES.len()],
+            light_bvh_nodes_visited: 0,
+            terminated_by_depth: 0,
+            terminated_by_roulette: 0,
+        }
+    }
+
+    pub fn add(&mut self, other: &Stats) {
+        self.primary_rays += other.primary_rays;
+        self.rays += other.rays;
+        self.shadow_rays += other.shadow_rays;
+        for (total, tests) in self
+            .intersection_tests
+            .iter_mut()
+            .zip(other.intersection_tests)
+        {
+            *total += tests;
+        }
+        self.light_bvh_nodes_visited += other.light_bvh_nodes_visited;
+        self.terminated_by_depth += other.terminated_by_depth;
+        self.terminated_by_roulette += other.terminated_by_roulette;
+    }
+
+    pub fn secondary_rays(&self) -> u64 {
+        //Counts merged from several threads and machines need not add up
+        self.rays.saturating_sub(self.primary_rays)
+    }
+
+    /// Average number of rays traced along a path from the camera.
+    pub fn average_path_length(&self) -> f64 {
+        if self.primary_rays == 0 {
+            0.
+        } else {
+            self.rays as f64 / self.primary_rays as f64
+        }
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Stats {
+        Stats::new()
+    }
+}
+
+thread_local! {
+    static LOCAL: RefCell<Stats> = const { RefCell::new(Stats::new()) };
+}
+
+static TOTAL: Mutex<Stats> = Mutex::new(Stats::new());
+
+/// Updates the counters of the current thread, collected by the next `flush`.
+#[inline(always)]
+pub fn record(update: impl FnOnce(&mut Stats)) {
+    LOCAL.with_borrow_mut(update);
+}
+
+/// Takes the counters of the current thread, leaving them at zero.
+pub fn take() -> Stats {
+    LOCAL.with(|stats| stats.replace(Stats::new()))
+}
+
+/// Adds the counters of the current thread to the totals.
+pub fn flush() {
+    merge(&take());
+}
+
+/// Adds counters collected elsewhere, such as by a worker on another machine, to the
+/// totals.
+pub fn merge(stats: &Stats) {
+    TOTAL.lock().unwrap().add(stats);
+}
+
+pub fn total() -> Stats {
+    TOTAL.lock().unwrap().clone()
+}
+
+/// Summary of the counters and the time spent in each phase of the render.
+pub struct Report {
+    pub stats: Stats,
+    pub phases: Vec<(&'static str, Duration)>,
+}
+
+impl Report {
+    pub fn summary(&self) -> String {
+        let stats = &self.stats;
+        let mut summary = String::new();
+        writeln!(summary, "Rays").unwrap();
+        writeln!(summary, "  primary             {:>14}", stats.primary_rays).unwrap();
+        writeln!(
+            summary,
+            "  secondary           {:>14}",
+            stats.secondary_rays()
+        )
+        .unwrap();
+        writeln!(summary, "  shadow              {:>14}", stats.shadow_rays).unwrap();
+        writeln!(
+            summary,
+            "  average path length {:>14.2}",
+            stats.average_path_length()
+        )
+        .unwrap();
+        writeln!(
+            summary,
+            "  terminated by depth {:>14}",
+            stats.terminated_by_depth
+        )
+        .unwrap();
+        writeln!(
+            summary,
+            "  ended by roulette   {:>14}",
+            stats.terminated_by_roulette
+        )
+        .unwrap();
+        writeln!(summary, "Intersection tests").unwrap();
+        for (name, tests) in PRIMITIVES.iter().zip(stats.intersection_tests) {
+            if tests > 0 {
+                writeln!(summary, "  {:<19} {:>14}", name, tests).unwrap();
+            }
+        }
+        writeln!(
+            summary,
+            "  light bvh nodes     {:>14}",
+            stats.light_bvh_nodes_visited
+        )
+        .unwrap();
+        writeln!(summary, "Time").unwrap();
+        for (phase, duration) in &self.phases {
+            writeln!(summary, "  {:<19} {:>13.3}s", phase, duration.as_secs_f64()).unwrap();
+        }
+        summary
+    }
+
+    pub fn json(&self) -> String {
+        let stats = &self.stats;
+        let tests: Vec<String> = PRIMITIVES
+            .iter()
+            .zip(stats.intersection_tests)
+            .map(|(name, tests)| format!("\"{}\": {}", name, tests))
+            .collect();
+        let phases: Vec<String> = self
+            .phases
+            .iter()
+            .map(|(phase, duration)| format!("\"{}\": {:.6}", phase, duration.as_secs_f64()))
+            .collect();
+
+        format!(
+            "{{\n  \"rays\": {{\"primary\": {}, \"secondary\": {}, \"shadow\": {}}},\n  \
+             \"average_path_length\": {:.6},\n  \"terminated_by_depth\": {},\n  \
+             \"terminated_by_roulette\": {},\n  \
+             \"intersection_tests\": {{{}}},\n  \"light_bvh_nodes_visited\": {},\n  \
+             \"seconds\": {{{}}}\n}}\n",
+            stats.primary_rays,
+            stats.secondary_rays(),
+            stats.shadow_rays,
+            stats.average_path_length(),
+            stats.terminated_by_depth,
+            stats.terminated_by_roulette,
+            tests.join(", "),
+            stats.light_bvh_nodes_visited,
+            phases.join(", "),
+        )
+    }
+}
+
+#[cfg(test)]
+mod stats_tests {
+    use super::*;
+
+    #[test]
+    fn test_flush_adds_up_threads() {
+        let before = total();
+        std::thread::scope(|scope| {
+            for _ in 0..3 {
+                scope.spawn(|| {
+                    record(|stats| {
+                        stats.primary_rays += 1;
+                        stats.rays += 4;
+                        stats.intersection_tests[0] += 10;
+                    });
+                    flush();
+                });
+            }
+        });
+        let after = total();
+
+        //Other tests may render at the same time, so only check the lower bound
+        assert!(after.primary_rays - before.primary_rays >= 3);
+        assert!(after.rays - before.rays >= 12);
+        assert!(after.intersection_tests[0] - before.intersection_tests[0] >= 30);
+    }
+
+    #[test]
+    fn test_report() {
+        let mut stats = Stats::new();
+        stats.primary_rays = 10;
+        stats.rays = 25;
+        stats.intersection_tests[1] = 7;
+        let report = Report {
+            stats,
+            phases: vec![("render", Duration::from_millis(1500))],
+        };
+
+        assert_eq!(report.stats.secondary_rays(), 15);
+        assert_eq!(report.stats.average_path_length(), 2.5);
+        assert!(report.summary().contains("plane"));
+        assert!(!report.summary().contains("torus"));
+
+        let json = report.json();
+        assert!(json.contains("\"primary\": 10, \"secondary\": 15"));
+        assert!(json.contains("\"plane\": 7"));
+        assert!(json.contains("\"render\": 1.500000"));
+
+        //Never below zero, whatever the counts
+        let mut stats = Stats::new();
+        stats.primary_rays = 3;
+        stats.rays = 2;
+        assert_eq!(stats.secondary_rays(), 0);
+    }
+}
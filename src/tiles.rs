@@ -0,0 +1,199 @@
+/// Order in which the tiles of the image are handed out to the render threads.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TileOrder {
+    /// Row by row from the top left.
+    Scanline,
+    /// Rings around the center of the image, where the subject usually is.
+    Spiral,
+    /// Along a Hilbert curve, so tiles rendered close in time are close on screen.
+    Hilbert,
+}
+
+impl TileOrder {
+    pub fn parse(name: &str) -> Option<TileOrder> {
+        match name {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            "hilbert" => Some(TileOrder::Hilbert),
+            _ => None,
+        }
+    }
+}
+
+/// Rectangle of pixels rendered as one unit of work, with `y` counted from the top.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    /// The whole image as one rectangle.
+    pub fn image(width: usize, height: usize) -> Tile {
+        Tile {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        }
+    }
+
+    pub fn contains(&self, other: &Tile) -> bool {
+        //A rectangle reaching past the largest number fits nowhere
+        let fits = |start: usize, length: usize, end: usize| {
+            start
+                .checked_add(length)
+                .is_some_and(|other_end| other_end <= end)
+        };
+        self.x <= other.x
+            && self.y <= other.y
+            && fits(other.x, other.width, self.x + self.width)
+            && fits(other.y, other.height, self.y + self.height)
+    }
+}
+
+/// Splits a rectangle of the image, usually all of it, into tiles of at most `size` by
+/// `size` pixels in the given order.
+pub fn tiles(area: &Tile, size: usize, order: TileOrder) -> Vec<Tile> {
+    let columns = area.width.div_ceil(size);
+    let rows = area.height.div_ceil(size);
+
+    let mut cells: Vec<(usize, usize)> = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .collect();
+
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            let center = ((columns - 1) as f64 / 2., (rows - 1) as f64 / 2.);
+            let key = |&(column, row): &(usize, usize)| {
+                let dx = column as f64 - center.0;
+                let dy = row as f64 - center.1;
+                (f64::max(dx.abs(), dy.abs()), f64::atan2(dy, dx))
+            };
+            cells.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+        }
+        TileOrder::Hilbert => {
+            let side = usize::max(columns, rows).next_power_of_two();
+            cells.sort_by_key(|&(column, row)| hilbert_index(side, column, row));
+        }
+    }
+
+    cells
+        .into_iter()
+        .map(|(column, row)| {
+            let x = column * size;
+            let y = row * size;
+            Tile {
+                x: area.x + x,
+                y: area.y + y,
+                width: usize::min(size, area.width - x),
+                height: usize::min(size, area.height - y),
+            }
+        })
+        .collect()
+}
+
+/// Distance along the Hilbert curve filling a `side` by `side` grid, `side` being a
+/// power of two.
+fn hilbert_index(side: usize, mut x: usize, mut y: usize) -> usize {
+    let mut index = 0;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as usize;
+        let ry = (y & s > 0) as usize;
+        index += s * s * ((3 * rx) ^ ry);
+
+        //Rotate the quadrant so the curve inside it starts where the last one ended
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tiles_tests {
+    use super::*;
+
+    fn assert_covers(tiles: &[Tile], width: usize, height: usize) {
+        let mut covered = vec![0; width * height];
+        for tile in tiles {
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    covered[y * width + x] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&count| count == 1));
+    }
+
+    #[test]
+    fn test_tiles_cover_image_once() {
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            assert_covers(&tiles(&Tile::image(100, 57), 16, order), 100, 57);
+        }
+    }
+
+    #[test]
+    fn test_tiles_of_region() {
+        let region = Tile {
+            x: 10,
+            y: 5,
+            width: 20,
+            height: 7,
+        };
+        let tiles = tiles(&region, 8, TileOrder::Scanline);
+
+        assert_eq!(tiles.len(), 3);
+        assert!(tiles.iter().all(|tile| region.contains(tile)));
+        assert_eq!(
+            tiles
+                .iter()
+                .map(|tile| tile.width * tile.height)
+                .sum::<usize>(),
+            140
+        );
+        assert_eq!((tiles[2].x, tiles[2].width), (26, 4));
+    }
+
+    #[test]
+    fn test_contains() {
+        let image = Tile::image(100, 50);
+        let region = |x, y, width, height| Tile {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert!(image.contains(&image));
+        assert!(image.contains(&region(10, 20, 90, 30)));
+        assert!(!image.contains(&region(10, 20, 91, 30)));
+        assert!(!image.contains(&region(10, 20, 90, 31)));
+        assert!(!image.contains(&region(usize::MAX, 0, 1, 1)));
+        assert!(!image.contains(&region(0, 1, 1, usize::MAX)));
+    }
+
+    #[test]
+    fn test_spiral_starts_at_center() {
+        let tiles = tiles(&Tile::image(90, 90), 30, TileOrder::Spiral);
+        assert_eq!((tiles[0].x, tiles[0].y), (30, 30));
+    }
+
+    #[test]
+    fn test_hilbert_steps_to_neighbours() {
+        let tiles = tiles(&Tile::image(64, 64), 8, TileOrder::Hilbert);
+        for pair in tiles.windows(2) {
+            let dx = pair[0].x.abs_diff(pair[1].x);
+            let dy = pair[0].y.abs_diff(pair[1].y);
+            assert_eq!(dx + dy, 8);
+        }
+    }
+}
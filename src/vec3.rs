@@ -22,11 +22,20 @@ impl Vec3 {
         let s = 1e-8;
         f64::abs(self.0) < s && f64::abs(self.1) < s && f64::abs(self.2) < s
     }
+    #[inline(always)]
+    pub fn max_component(self) -> f64 {
+        f64::max(self.0, f64::max(self.1, self.2))
+    }
+    /// Brightness of a linear RGB color, with the Rec. 709 weights.
+    #[inline(always)]
+    pub fn luminance(self) -> f64 {
+        0.2126 * self.0 + 0.7152 * self.1 + 0.0722 * self.2
+    }
 }
 
 #[inline(always)]
 pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
-    *v - 2. * dot(&v, &n) * n.clone()
+    *v - 2. * dot(v, n) * *n
 }
 
 #[inline(always)]
@@ -48,12 +57,37 @@ pub fn unit_vector(vec: &Vec3) -> Vec3 {
     *vec / vec.length()
 }
 
+/// Two unit vectors that together with the unit vector `n` form an orthonormal basis.
+pub fn orthonormal_basis(n: &Vec3) -> (Vec3, Vec3) {
+    let helper = if f64::abs(n.0) > 0.9 {
+        Vec3(0., 1., 0.)
+    } else {
+        Vec3(1., 0., 0.)
+    };
+    let t = unit_vector(&cross(n, &helper));
+    (t, cross(n, &t))
+}
+
 impl fmt::Display for Vec3 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         writeln!(f, "Vector:\n 0:{} 1:{} 2:{}", self.0, self.1, self.2)
     }
 }
 
+impl ops::Index<usize> for Vec3 {
+    type Output = f64;
+
+    #[inline(always)]
+    fn index(&self, axis: usize) -> &Self::Output {
+        match axis {
+            0 => &self.0,
+            1 => &self.1,
+            2 => &self.2,
+            _ => panic!("Vec3 index out of range: {}", axis),
+        }
+    }
+}
+
 impl ops::Add<Vec3> for Vec3 {
     type Output = Vec3;
 
@@ -86,7 +120,7 @@ impl ops::Neg for Vec3 {
 
     #[inline(always)]
     fn neg(self) -> Self::Output {
-        Vec3(self.0 * -1., self.1 * -1., self.2 * -1.)
+        Vec3(-self.0, -self.1, -self.2)
     }
 }
 
@@ -179,6 +213,11 @@ mod vec3_tests {
         assert_eq!(a.length(), 3.4641016151377544)
     }
 
+    #[test]
+    fn test_max_component() {
+        assert_eq!(Vec3(0.2, 0.7, -1.).max_component(), 0.7);
+    }
+
     #[test]
     fn test_length_squared() {
         let a = basic_vec();
@@ -232,6 +271,22 @@ mod vec3_tests {
         assert_eq!(a / 2., basic_result_div());
     }
 
+    #[test]
+    fn test_index() {
+        let a = Vec3(1., 2., 3.);
+        assert_eq!((a[0], a[1], a[2]), (1., 2., 3.));
+    }
+
+    #[test]
+    fn test_orthonormal_basis() {
+        let n = unit_vector(&Vec3(1., 2., 3.));
+        let (t, b) = orthonormal_basis(&n);
+        assert!(f64::abs(dot(&n, &t)) < 1e-12);
+        assert!(f64::abs(dot(&n, &b)) < 1e-12);
+        assert!(f64::abs(dot(&t, &b)) < 1e-12);
+        assert!(f64::abs(b.length() - 1.) < 1e-12);
+    }
+
     #[test]
     fn test_div_assign() {
         let mut a = basic_vec();
@@ -0,0 +1,395 @@
+//! Dense voxel grids for heterogeneous participating media (clouds, smoke, fire).
+//!
+//! Grid files use a small little-endian binary layout:
+//!
+//! ```text
+//! magic      4 bytes  "VXG1"
+//! nx ny nz   3 x u32  resolution
+//! channels   u32      1 = density, 2 = density + emission
+//! min max    6 x f32  world space bounds
+//! data       nx * ny * nz * channels x f32
+//! ```
+//!
+//! Each channel is stored as a whole block, with x varying fastest, then y, then z.
+//! Values are sampled at cell centers and interpolated trilinearly.
+
+use std::{
+    cmp::Ordering,
+    fs::File,
+    io::{self, BufReader, Read},
+};
+
+use crate::{aabb::Aabb, ray::Ray, utils::random_double, vec3::Vec3};
+
+const MAGIC: &[u8; 4] = b"VXG1";
+
+#[derive(Debug)]
+pub struct VoxelGrid {
+    pub resolution: (usize, usize, usize),
+    pub bounds: Aabb,
+    density: Vec<f32>,
+    emission: Option<Vec<f32>>,
+    max_density: f64,
+}
+
+impl VoxelGrid {
+    pub fn new(
+        resolution: (usize, usize, usize),
+        bounds: Aabb,
+        density: Vec<f32>,
+        emission: Option<Vec<f32>>,
+    ) -> VoxelGrid {
+        let count = resolution.0 * resolution.1 * resolution.2;
+        assert_eq!(density.len(), count, "density channel has the wrong size");
+        if let Some(emission) = &emission {
+            assert_eq!(emission.len(), count, "emission channel has the wrong size");
+        }
+
+        let max_density = density.iter().fold(0f32, |acc, d| acc.max(*d)) as f64;
+        VoxelGrid {
+            resolution,
+            bounds,
+            density,
+            emission,
+            max_density,
+        }
+    }
+
+    pub fn load(path: &str) -> io::Result<VoxelGrid> {
+        VoxelGrid::read(&mut BufReader::new(File::open(path)?))
+    }
+
+    pub fn read(reader: &mut impl Read) -> io::Result<VoxelGrid> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a voxel grid file",
+            ));
+        }
+
+        let nx = read_u32(reader)? as usize;
+        let ny = read_u32(reader)? as usize;
+        let nz = read_u32(reader)? as usize;
+        let channels = read_u32(reader)?;
+        if !(1..=2).contains(&channels) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported channel count {}", channels),
+            ));
+        }
+
+        let min = Vec3(
+            read_f32(reader)? as f64,
+            read_f32(reader)? as f64,
+            read_f32(reader)? as f64,
+        );
+        let max = Vec3(
+            read_f32(reader)? as f64,
+            read_f32(reader)? as f64,
+            read_f32(reader)? as f64,
+        );
+
+        if nx == 0 || ny == 0 || nz == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "voxel grid without voxels",
+            ));
+        }
+        if (0..3).any(|axis| !min[axis].is_finite() || !max[axis].is_finite()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "voxel grid with infinite bounds",
+            ));
+        }
+        if (0..3).any(|axis| min[axis].partial_cmp(&max[axis]) != Some(Ordering::Less)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "voxel grid with empty bounds",
+            ));
+        }
+
+        let count = nx
+            .checked_mul(ny)
+            .and_then(|count| count.checked_mul(nz))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "voxel grid too large"))?;
+        let density = read_channel(reader, count)?;
+        let emission = if channels == 2 {
+            Some(read_channel(reader, count)?)
+        } else {
+            None
+        };
+
+        Ok(VoxelGrid::new(
+            (nx, ny, nz),
+            Aabb { min, max },
+            density,
+            emission,
+        ))
+    }
+
+    #[cfg(test)]
+    pub fn write(&self, writer: &mut impl io::Write) -> io::Result<()> {
+        let channels: u32 = if self.emission.is_some() { 2 } else { 1 };
+
+        writer.write_all(MAGIC)?;
+        for n in [self.resolution.0, self.resolution.1, self.resolution.2] {
+            writer.write_all(&(n as u32).to_le_bytes())?;
+        }
+        writer.write_all(&channels.to_le_bytes())?;
+        for axis in 0..3 {
+            writer.write_all(&(self.bounds.min[axis] as f32).to_le_bytes())?;
+        }
+        for axis in 0..3 {
+            writer.write_all(&(self.bounds.max[axis] as f32).to_le_bytes())?;
+        }
+        for value in self.density.iter().chain(self.emission.iter().flatten()) {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        writer.flush()
+    }
+
+    #[cfg(test)]
+    pub fn max_density(&self) -> f64 {
+        self.max_density
+    }
+
+    pub fn density(&self, p: &Vec3) -> f64 {
+        self.interpolate(&self.density, p)
+    }
+
+    pub fn emission(&self, p: &Vec3) -> f64 {
+        match &self.emission {
+            Some(emission) => self.interpolate(emission, p),
+            None => 0.,
+        }
+    }
+
+    /// Delta tracking: samples the distance to a real collision in the medium with
+    /// the density scaled by `scale`, between `t_min` and `t_max`.
+    pub fn sample_collision(&self, scale: f64, ray: &Ray, t_min: f64, t_max: f64) -> Option<f64> {
+        let majorant = self.max_density * scale;
+        if majorant <= 0. {
+            return None;
+        }
+
+        let ray_length = ray.dir.length();
+        let mut t = t_min;
+        loop {
+            t -= f64::ln(1. - random_double()) / (majorant * ray_length);
+            if t >= t_max {
+                return None;
+            }
+            if random_double() * majorant < self.density(&ray.at(t)) * scale {
+                return Some(t);
+            }
+        }
+    }
+
+    /// Ratio tracking: unbiased estimate of the transmittance between `t_min` and `t_max`.
+    pub fn transmittance(&self, scale: f64, ray: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let majorant = self.max_density * scale;
+        if majorant <= 0. {
+            return 1.;
+        }
+
+        let ray_length = ray.dir.length();
+        let mut transmittance = 1.;
+        let mut t = t_min;
+        loop {
+            t -= f64::ln(1. - random_double()) / (majorant * ray_length);
+            if t >= t_max {
+                return transmittance;
+            }
+            transmittance *= 1. - self.density(&ray.at(t)) * scale / majorant;
+        }
+    }
+
+    fn interpolate(&self, values: &[f32], p: &Vec3) -> f64 {
+        let (nx, ny, nz) = self.resolution;
+        let size = self.bounds.max - self.bounds.min;
+        let local = *p - self.bounds.min;
+        if (0..3).any(|axis| local[axis] < 0. || local[axis] > size[axis]) {
+            return 0.;
+        }
+
+        //Continuous voxel coordinates with samples at cell centers
+        let coordinate = |axis: usize, n: usize| {
+            let x = f64::max(local[axis] / size[axis] * n as f64 - 0.5, 0.);
+            let i = usize::min(x as usize, n - 1);
+            (i, usize::min(i + 1, n - 1), f64::min(x - i as f64, 1.))
+        };
+        let (x0, x1, fx) = coordinate(0, nx);
+        let (y0, y1, fy) = coordinate(1, ny);
+        let (z0, z1, fz) = coordinate(2, nz);
+        let at = |x: usize, y: usize, z: usize| values[x + nx * (y + ny * z)] as f64;
+
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let c00 = lerp(at(x0, y0, z0), at(x1, y0, z0), fx);
+        let c10 = lerp(at(x0, y1, z0), at(x1, y1, z0), fx);
+        let c01 = lerp(at(x0, y0, z1), at(x1, y0, z1), fx);
+        let c11 = lerp(at(x0, y1, z1), at(x1, y1, z1), fx);
+        lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz)
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+fn read_channel(reader: &mut impl Read, count: usize) -> io::Result<Vec<f32>> {
+    let length = count
+        .checked_mul(4)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "voxel grid too large"))?;
+    //Grows with the data actually there rather than the size the header claims
+    let mut bytes = Vec::new();
+    reader.take(length as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "voxel grid data cut short",
+        ));
+    }
+    let values: Vec<f32> = bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect();
+    //Densities and emission are amounts, a NaN or a negative one breaks the tracking
+    if let Some(value) = values.iter().find(|v| !v.is_finite() || **v < 0.) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("voxel grid with invalid value {}", value),
+        ));
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod voxel_tests {
+    use super::*;
+
+    fn unit_bounds() -> Aabb {
+        Aabb {
+            min: Vec3(0., 0., 0.),
+            max: Vec3(1., 1., 1.),
+        }
+    }
+
+    fn gradient_grid() -> VoxelGrid {
+        //Density increases along x: 0, 1
+        VoxelGrid::new((2, 1, 1), unit_bounds(), vec![0., 1.], Some(vec![2., 4.]))
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let grid = gradient_grid();
+        assert_eq!(grid.density(&Vec3(0.25, 0.5, 0.5)), 0.);
+        assert_eq!(grid.density(&Vec3(0.5, 0.5, 0.5)), 0.5);
+        assert_eq!(grid.density(&Vec3(0.9, 0.5, 0.5)), 1.);
+        assert_eq!(grid.emission(&Vec3(0.5, 0.5, 0.5)), 3.);
+        assert_eq!(grid.density(&Vec3(1.5, 0.5, 0.5)), 0.);
+        assert_eq!(grid.max_density(), 1.);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let grid = gradient_grid();
+        let mut bytes = Vec::new();
+        grid.write(&mut bytes).unwrap();
+
+        let read = VoxelGrid::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(read.resolution, (2, 1, 1));
+        assert_eq!(read.bounds, unit_bounds());
+        assert_eq!(read.density, grid.density);
+        assert_eq!(read.emission, grid.emission);
+
+        assert!(VoxelGrid::read(&mut &b"VXG0"[..]).is_err());
+    }
+
+    #[test]
+    fn test_rejects_bad_values() {
+        for value in [-0.5, f32::NAN, f32::INFINITY] {
+            for emission in [false, true] {
+                let mut grid = gradient_grid();
+                match &mut grid.emission {
+                    Some(channel) if emission => channel[1] = value,
+                    _ => grid.density[1] = value,
+                }
+                let mut bytes = Vec::new();
+                grid.write(&mut bytes).unwrap();
+                assert_eq!(
+                    VoxelGrid::read(&mut bytes.as_slice()).unwrap_err().kind(),
+                    io::ErrorKind::InvalidData
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_headers() {
+        //Header with a resolution, one channel and bounds, without data
+        let header = |n: [u32; 3], max: f32| {
+            let mut bytes = MAGIC.to_vec();
+            for value in n.into_iter().chain([1]) {
+                bytes.extend(value.to_le_bytes());
+            }
+            for value in [0f32, 0., 0., max, max, max] {
+                bytes.extend(value.to_le_bytes());
+            }
+            bytes
+        };
+        let kind = |bytes: Vec<u8>| VoxelGrid::read(&mut bytes.as_slice()).unwrap_err().kind();
+
+        assert_eq!(kind(header([0, 4, 4], 1.)), io::ErrorKind::InvalidData);
+        assert_eq!(kind(header([4, 4, 4], 0.)), io::ErrorKind::InvalidData);
+        assert_eq!(
+            kind(header([4, 4, 4], f32::NAN)),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            kind(header([4, 4, 4], f32::INFINITY)),
+            io::ErrorKind::InvalidData
+        );
+        //Claims far more data than it has, without allocating for it
+        assert_eq!(
+            kind(header([u32::MAX, u32::MAX, 2], 1.)),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(
+            kind(header([1 << 20, 1 << 20, 1 << 10], 1.)),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn test_tracking_matches_beer_lambert() {
+        //Constant density 0.5 over a unit distance: T = exp(-0.5 * scale)
+        let grid = VoxelGrid::new((2, 2, 2), unit_bounds(), vec![0.5; 8], None);
+        let ray = Ray {
+            origin: Vec3(0., 0.5, 0.5),
+            dir: Vec3(2., 0., 0.),
+        };
+        let expected = f64::exp(-1.);
+        let samples = 100_000;
+
+        let ratio = (0..samples)
+            .map(|_| grid.transmittance(2., &ray, 0., 0.5))
+            .sum::<f64>()
+            / samples as f64;
+        assert!(f64::abs(ratio - expected) < 0.01, "{}", ratio);
+
+        let escaped = (0..samples)
+            .filter(|_| grid.sample_collision(2., &ray, 0., 0.5).is_none())
+            .count();
+        assert!(f64::abs(escaped as f64 / samples as f64 - expected) < 0.01);
+    }
+}